@@ -1,3 +1,1786 @@
 //! module providing API abstractions for working with specific hardware keyboards.
+//!
+//! Each supported model has its own module, while the [`Keyboard`] trait and
+//! the [`AnyKeyboard`] type allow working with whichever model is connected.
 
+pub mod backup;
+pub mod bazecor;
 pub mod defy;
+pub mod keymap;
+pub mod layers;
+pub mod leds;
+pub mod lighting;
+pub mod macros;
+pub mod mouse;
+pub mod qukeys;
+pub mod raise;
+pub mod raise2;
+pub mod settings;
+pub mod superkeys;
+pub mod wireless;
+
+use crate::focus_api::{
+    Capabilities, ConnectionEvent, DeviceSelector, DiscoverDevicesError, FocusApiConnection,
+    GetCapabilitiesError, HidFocusApi, MockFocusApi, RequireCommandError, RunCommandError,
+    SerialPortFocusApi, discover_devices, parsing,
+};
+use backup::{
+    BACKUP_FORMAT_VERSION, BackupDevice, BackupSettings, DeviceBackup, GetBackupError,
+    RestoreBackupError, if_part_supported,
+};
+use defy::{
+    ApplyMacrosError, ApplySuperkeyError, ClearLayerError, CreateDefyKeyboardError, DefyKeyboard,
+    DefyKeymap, DefyLayout, DefyMacroMap, GetMacroMapError, GetSuperkeyMapError,
+    KeymapDoesNotHave10LayersError, ParseKeymapError, SuperkeyMap,
+};
+pub use error::*;
+use itertools::Itertools;
+use layers::{
+    ChangeLayerError, GetLayerStateError, LayerState, run_layer_command, with_virtual_layers,
+};
+use leds::{
+    GetLedError, SetLedError, check_led, colors_to_command_data, parse_colors, with_virtual_leds,
+};
+use lighting::{
+    AnyColormap, ApplyColormapError, ApplyPaletteError, Color, Colormap, GetColormapError,
+    GetPaletteError, Palette,
+};
+use mouse::{MouseKeySettings, with_virtual_mouse_keys};
+use qukeys::{DualFunctionSettings, with_virtual_qukeys};
+use raise::{CreateRaiseKeyboardError, RaiseKeyboard, RaiseKeymap, RaiseLayout};
+use raise2::{CreateRaise2KeyboardError, Raise2Keyboard};
+use settings::{
+    FirmwareVersion, GetSettingError, SetSettingError, Settings, SettingsCrc, check_setting_range,
+    get_setting, if_readable, parse_bool_setting, read_setting, set_setting,
+};
+use std::{
+    ops::{Deref, DerefMut},
+    str::FromStr,
+    time::Duration,
+};
+use superkeys::{SuperkeyConfig, SuperkeySettings, with_virtual_superkey_settings};
+use tokio::sync::mpsc;
+use wireless::{Battery, RfPower, Side, WirelessStatus, with_virtual_wireless};
+
+mod error {
+    use super::*;
+
+    /// Error when parsing a [`Model`] from a string slice.
+    #[derive(Clone, Debug, Display, From, Error)]
+    #[display("unknown keyboard model `{_0}`, expected `defy`, `raise` or `raise2`")]
+    pub struct ParseModelError(#[error(not(source))] String);
+
+    /// Error returned when connecting to a keyboard of a known model.
+    #[derive(Debug, Display, From, Error)]
+    pub enum ConnectKeyboardError {
+        /// Failed to connect to the Defy.
+        #[display("{_0}")]
+        Defy(CreateDefyKeyboardError),
+        /// Failed to connect to the Raise.
+        #[display("{_0}")]
+        Raise(CreateRaiseKeyboardError),
+        /// Failed to connect to the Raise 2.
+        #[display("{_0}")]
+        Raise2(CreateRaise2KeyboardError),
+    }
+
+    /// Error returned from [`AnyKeyboard::detect`].
+    #[derive(Debug, Display, From, Error)]
+    pub enum DetectKeyboardError {
+        /// Connected devices could not be listed.
+        #[display("{_0}")]
+        Discovering(DiscoverDevicesError),
+        /// No connected device matched the selector.
+        #[display("no connected Dygma keyboard matches the `{_0}` selector")]
+        NoDeviceFound(#[error(not(source))] DeviceSelector),
+        /// The matching device is not a model known to the SDK.
+        #[display("the connected device has an unknown product id {_0:#06x}")]
+        UnknownModel(#[error(not(source))] u16),
+        /// Failed to connect to the detected keyboard.
+        #[display("{_0}")]
+        Connecting(ConnectKeyboardError),
+    }
+
+    /// Error returned from [`Keyboard::get_keymap`].
+    #[derive(Debug, Display, From, Error)]
+    pub enum GetKeymapError {
+        /// The firmware does not support reading the keymap.
+        #[display("{_0}")]
+        Unsupported(RequireCommandError),
+        /// Failed to run command.
+        #[display("{_0}")]
+        CommandFailed(RunCommandError),
+        /// The layout variant, needed to interpret the keymap, could not
+        /// be determined.
+        #[display("{_0}")]
+        LayoutVariant(raise::GetLayoutVariantError),
+        /// Keymap returned by the keyboard failed to parse.
+        KeymapParsingFailure(ParseKeymapError),
+    }
+
+    impl From<defy::GetCustomKeymapError> for GetKeymapError {
+        fn from(err: defy::GetCustomKeymapError) -> Self {
+            match err {
+                defy::GetCustomKeymapError::CommandFailed(err) => Self::CommandFailed(err),
+                defy::GetCustomKeymapError::KeymapParsingFailure(err) => {
+                    Self::KeymapParsingFailure(err)
+                }
+            }
+        }
+    }
+
+    impl From<raise::GetCustomKeymapError> for GetKeymapError {
+        fn from(err: raise::GetCustomKeymapError) -> Self {
+            match err {
+                raise::GetCustomKeymapError::CommandFailed(err) => Self::CommandFailed(err),
+                raise::GetCustomKeymapError::LayoutVariant(err) => Self::LayoutVariant(err),
+                raise::GetCustomKeymapError::KeymapParsingFailure(err) => {
+                    Self::KeymapParsingFailure(err)
+                }
+            }
+        }
+    }
+
+    /// Error returned from [`Keyboard::apply_keymap`].
+    #[derive(Debug, Display, From, Error)]
+    pub enum ApplyKeymapError {
+        /// The keymap was made for a different model.
+        #[display("a {keymap} keymap cannot be applied to the {keyboard}")]
+        WrongModel {
+            /// The model family the keymap was made for.
+            keymap: &'static str,
+            /// The model of the keyboard.
+            keyboard: &'static str,
+        },
+        /// 10 layers are required, but this keymap has a different number of them.
+        #[display("{_0}")]
+        IncorrectNumberOfLayers(KeymapDoesNotHave10LayersError),
+        /// Command failed to run.
+        #[display("{_0}")]
+        CommandFailed(RunCommandError),
+    }
+
+    impl From<defy::ApplyCustomKeymapError> for ApplyKeymapError {
+        fn from(err: defy::ApplyCustomKeymapError) -> Self {
+            match err {
+                defy::ApplyCustomKeymapError::IncorrectNumberOfLayers(err) => {
+                    Self::IncorrectNumberOfLayers(err)
+                }
+                defy::ApplyCustomKeymapError::CommandFailed(err) => Self::CommandFailed(err),
+            }
+        }
+    }
+
+    /// Error returned from [`Keyboard::reset_keymap_to_default`].
+    #[derive(Debug, Display, From, Error)]
+    pub enum ResetKeymapError {
+        /// The default keymap could not be read.
+        #[display("failed to get the default keymap: {_0}")]
+        GettingDefault(GetKeymapError),
+        /// The default keymap could not be applied as the custom one.
+        #[display("failed to apply the default keymap: {_0}")]
+        Applying(ApplyKeymapError),
+    }
+}
+
+/// The Dygma keyboard models supported by the SDK.
+#[derive(Clone, Copy, Debug, Display, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Model {
+    /// The Dygma Defy.
+    #[display("defy")]
+    Defy,
+    /// The original Dygma Raise.
+    #[display("raise")]
+    Raise,
+    /// The Dygma Raise 2.
+    #[display("raise2")]
+    Raise2,
+}
+
+impl FromStr for Model {
+    type Err = ParseModelError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "defy" => Ok(Self::Defy),
+            "raise" => Ok(Self::Raise),
+            "raise2" => Ok(Self::Raise2),
+            s => Err(ParseModelError::from(s.to_string())),
+        }
+    }
+}
+
+impl Model {
+    /// Every supported model.
+    pub const ALL: [Self; 3] = [Self::Defy, Self::Raise, Self::Raise2];
+
+    /// Gets the static description of this model.
+    pub fn descriptor(self) -> &'static ModelDescriptor {
+        match self {
+            Self::Defy => &DefyKeyboard::DESCRIPTOR,
+            Self::Raise => &RaiseKeyboard::DESCRIPTOR,
+            Self::Raise2 => &Raise2Keyboard::DESCRIPTOR,
+        }
+    }
+
+    /// Finds the model with the given USB product id.
+    pub fn from_product_id(product_id: u16) -> Option<Self> {
+        Self::ALL
+            .into_iter()
+            .find(|model| model.descriptor().product_ids.contains(&product_id))
+    }
+}
+
+/// Static description of a keyboard model, with its layout and limits.
+#[derive(Clone, Copy, Debug)]
+pub struct ModelDescriptor {
+    /// The model being described.
+    pub model: Model,
+    /// Human-readable name of the model.
+    pub name: &'static str,
+    /// The USB product ids the model can show up with.
+    pub product_ids: &'static [u16],
+    /// Whether the model can connect wirelessly, over RF or Bluetooth.
+    pub wireless: bool,
+    /// The physical layout of the model.
+    pub layout: ModelLayout,
+    /// Number of keys per layer, including keys not physically present.
+    pub keys_per_layer: usize,
+    /// Number of layers in a `keymap.custom` command.
+    pub layers: usize,
+    /// Number of LEDs per layer in a `colormap.map` command.
+    pub leds_per_layer: usize,
+    /// Whether the LEDs have a white channel, making palette colours RGBW
+    /// rather than RGB.
+    pub rgbw: bool,
+    /// The memory size of the superkey map.
+    pub superkey_memory_size: usize,
+    /// The memory size of the macro map, in bytes.
+    pub macro_memory_size: usize,
+}
+
+/// The physical layout of a keyboard model.
+#[derive(Clone, Copy, Debug)]
+pub enum ModelLayout {
+    /// The Defy layout.
+    Defy(&'static DefyLayout),
+    /// The Raise family layout, which is sold in ANSI and ISO variants.
+    Raise {
+        /// The ANSI variant.
+        ansi: &'static RaiseLayout,
+        /// The ISO variant.
+        iso: &'static RaiseLayout,
+    },
+}
+
+/// A keymap for any supported model.
+///
+/// When deserializing, the model family is told apart by the shape of the
+/// layers.
+#[derive(Clone, Debug, PartialEq, Eq, Hash, From, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum AnyKeymap {
+    /// A Defy keymap.
+    Defy(DefyKeymap),
+    /// A keymap for the Raise family.
+    Raise(RaiseKeymap),
+}
+
+impl AnyKeymap {
+    /// Parses `keymap.custom` data as a keymap for the given model.
+    pub fn from_str_for(model: Model, s: &str) -> Result<Self, ParseKeymapError> {
+        match model {
+            Model::Defy => s.parse().map(Self::Defy),
+            Model::Raise | Model::Raise2 => s.parse().map(Self::Raise),
+        }
+    }
+
+    /// The name of the model family this keymap was made for.
+    pub fn family(&self) -> &'static str {
+        match self {
+            Self::Defy(_) => "Defy",
+            Self::Raise(_) => "Raise",
+        }
+    }
+
+    /// Converts this keymap into a form suitable for sending over to the keyboard
+    /// as the data of a `keymap.custom` command.
+    pub fn to_keymap_custom_data(&self) -> Result<String, KeymapDoesNotHave10LayersError> {
+        match self {
+            Self::Defy(keymap) => keymap.to_keymap_custom_data(),
+            Self::Raise(keymap) => keymap.to_keymap_custom_data(),
+        }
+    }
+
+    /// Clears the layer to the provided key.
+    pub fn clear_layer_to(
+        &mut self,
+        layer: usize,
+        key: crate::keycode_tables::KeyKind,
+    ) -> Result<(), ClearLayerError> {
+        match self {
+            Self::Defy(keymap) => keymap.clear_layer_to(layer, key),
+            Self::Raise(keymap) => keymap.clear_layer_to(layer, key),
+        }
+    }
+
+    /// Drops or adds layers at the end of the keymap, so that it has the
+    /// provided number of layers, with new layers cleared to the key.
+    pub fn resize_to(&mut self, layers: usize, key: crate::keycode_tables::KeyKind) {
+        match self {
+            Self::Defy(keymap) => keymap.resize_to(layers, key),
+            Self::Raise(keymap) => keymap.resize_to(layers, key),
+        }
+    }
+
+    /// The number of layers in the keymap.
+    pub fn layers(&self) -> usize {
+        match self {
+            Self::Defy(keymap) => keymap.len(),
+            Self::Raise(keymap) => keymap.len(),
+        }
+    }
+
+    /// The number of dual-function keys across every layer of the keymap.
+    pub fn dual_function_keys(&self) -> usize {
+        let keys = match self {
+            Self::Defy(keymap) => keymap
+                .iter()
+                .map(|layer| layer.to_keymap_data())
+                .collect_vec(),
+            Self::Raise(keymap) => keymap
+                .iter()
+                .map(|layer| layer.to_keymap_data())
+                .collect_vec(),
+        };
+
+        keys.iter()
+            .flatten()
+            .flatten()
+            .filter(|key| key.is_dual_function())
+            .count()
+    }
+}
+
+/// Trait used to abstract over keyboard models.
+#[allow(async_fn_in_trait)]
+pub trait Keyboard {
+    /// Gets the static description of the keyboard's model.
+    fn descriptor(&self) -> &'static ModelDescriptor;
+
+    /// Gets the connection used to talk to the keyboard.
+    fn focus_api(&mut self) -> &mut DynFocusApi;
+
+    /// Gets what the firmware on the keyboard supports.
+    ///
+    /// The capabilities are queried the first time they are needed, and
+    /// remembered for as long as the handle lives.
+    async fn capabilities(&mut self) -> Result<&Capabilities, GetCapabilitiesError>;
+
+    /// Checks that the firmware supports the command, so that features it
+    /// lacks fail early with a clear error.
+    async fn require_command(&mut self, command: &str) -> Result<(), RequireCommandError> {
+        self.capabilities().await?.require(command)?;
+
+        Ok(())
+    }
+
+    /// Get the custom keymap from the keyboard.
+    async fn get_keymap(&mut self) -> Result<AnyKeymap, GetKeymapError>;
+
+    /// Apply the keymap to the keyboard, which must have been made for the
+    /// keyboard's model.
+    async fn apply_keymap(&mut self, keymap: &AnyKeymap) -> Result<(), ApplyKeymapError>;
+
+    /// Get the factory keymap, which is built into the firmware and cannot
+    /// be changed.
+    ///
+    /// It usually has fewer layers than the custom keymap.
+    async fn get_default_keymap(&mut self) -> Result<AnyKeymap, GetKeymapError>;
+
+    /// Check whether the keyboard only uses the custom keymap, rather than
+    /// placing the layers of the default keymap before the custom ones.
+    async fn get_only_custom(&mut self) -> Result<bool, GetSettingError> {
+        let value = read_setting(self, keymap::ONLY_CUSTOM_COMMAND_NAME).await?;
+
+        Ok(parse_bool_setting(keymap::ONLY_CUSTOM_COMMAND_NAME, value)?)
+    }
+
+    /// Set whether the keyboard only uses the custom keymap, rather than
+    /// placing the layers of the default keymap before the custom ones.
+    async fn set_only_custom(&mut self, only_custom: bool) -> Result<(), SetSettingError> {
+        set_setting(
+            self,
+            keymap::ONLY_CUSTOM_COMMAND_NAME,
+            u8::from(only_custom),
+        )
+        .await
+    }
+
+    /// Replace the custom keymap with the default one, so that the keyboard
+    /// behaves like it did out of the factory.
+    ///
+    /// Layers missing from the default keymap are cleared to
+    /// [`Blank::NoKey`](crate::keycode_tables::Blank::NoKey).
+    async fn reset_keymap_to_default(&mut self) -> Result<(), ResetKeymapError> {
+        let mut keymap = self.get_default_keymap().await?;
+
+        keymap.resize_to(
+            self.descriptor().layers,
+            crate::keycode_tables::KeyKind::Blank(crate::keycode_tables::Blank::NoKey),
+        );
+
+        self.apply_keymap(&keymap).await?;
+
+        Ok(())
+    }
+
+    /// Get the superkey map from the keyboard.
+    async fn get_superkeys(&mut self) -> Result<SuperkeyMap, GetSuperkeyMapError> {
+        self.require_command(superkeys::MAP_COMMAND_NAME).await?;
+
+        let map = self
+            .focus_api()
+            .run_command(superkeys::MAP_COMMAND_NAME, None)
+            .await?
+            .parse::<SuperkeyMap>()?;
+
+        Ok(map)
+    }
+
+    /// Apply the superkeys map to the keyboard.
+    async fn apply_superkeys(&mut self, superkeys: &SuperkeyMap) -> Result<(), ApplySuperkeyError> {
+        self.require_command(superkeys::MAP_COMMAND_NAME).await?;
+
+        let data = parsing::superkeys::SuperkeyMap::from(superkeys)
+            .to_sized_command_data(self.descriptor().superkey_memory_size)?;
+
+        self.focus_api()
+            .run_command(superkeys::MAP_COMMAND_NAME, Some(&data))
+            .await?;
+
+        Ok(())
+    }
+
+    /// Get the macro map from the keyboard.
+    async fn get_macros(&mut self) -> Result<DefyMacroMap, GetMacroMapError> {
+        self.require_command(macros::MAP_COMMAND_NAME).await?;
+
+        let map = self
+            .focus_api()
+            .run_command(macros::MAP_COMMAND_NAME, None)
+            .await?
+            .parse::<DefyMacroMap>()?;
+
+        Ok(map)
+    }
+
+    /// Apply the macro map to the keyboard.
+    async fn apply_macros(&mut self, macros: &DefyMacroMap) -> Result<(), ApplyMacrosError> {
+        self.require_command(macros::MAP_COMMAND_NAME).await?;
+
+        let data = parsing::macros::MacroMap::from(macros)
+            .to_sized_command_data(self.descriptor().macro_memory_size)?;
+
+        self.focus_api()
+            .run_command(macros::MAP_COMMAND_NAME, Some(&data))
+            .await?;
+
+        Ok(())
+    }
+
+    /// Get the global timings shared by every superkey.
+    async fn get_superkey_settings(&mut self) -> Result<SuperkeySettings, GetSettingError> {
+        Ok(SuperkeySettings {
+            wait_time: get_setting(self, superkeys::WAIT_TIME_COMMAND_NAME, "duration").await?,
+            timeout: get_setting(self, superkeys::TIMEOUT_COMMAND_NAME, "duration").await?,
+            repeat: get_setting(self, superkeys::REPEAT_COMMAND_NAME, "duration").await?,
+            hold_start: get_setting(self, superkeys::HOLD_START_COMMAND_NAME, "duration").await?,
+            overlap: get_setting(self, superkeys::OVERLAP_COMMAND_NAME, "percentage").await?,
+        })
+    }
+
+    /// Apply the global timings shared by every superkey.
+    async fn apply_superkey_settings(
+        &mut self,
+        settings: &SuperkeySettings,
+    ) -> Result<(), SetSettingError> {
+        check_setting_range(
+            superkeys::OVERLAP_COMMAND_NAME,
+            settings.overlap.into(),
+            0,
+            superkeys::MAX_OVERLAP.into(),
+        )?;
+
+        set_setting(self, superkeys::WAIT_TIME_COMMAND_NAME, settings.wait_time).await?;
+        set_setting(self, superkeys::TIMEOUT_COMMAND_NAME, settings.timeout).await?;
+        set_setting(self, superkeys::REPEAT_COMMAND_NAME, settings.repeat).await?;
+        set_setting(
+            self,
+            superkeys::HOLD_START_COMMAND_NAME,
+            settings.hold_start,
+        )
+        .await?;
+        set_setting(self, superkeys::OVERLAP_COMMAND_NAME, settings.overlap).await
+    }
+
+    /// Get how mouse keys move the cursor and scroll.
+    async fn get_mouse_key_settings(&mut self) -> Result<MouseKeySettings, GetSettingError> {
+        Ok(MouseKeySettings {
+            speed: get_setting(self, mouse::SPEED_COMMAND_NAME, "speed").await?,
+            speed_delay: get_setting(self, mouse::SPEED_DELAY_COMMAND_NAME, "delay").await?,
+            accel_speed: get_setting(self, mouse::ACCEL_SPEED_COMMAND_NAME, "speed").await?,
+            accel_delay: get_setting(self, mouse::ACCEL_DELAY_COMMAND_NAME, "delay").await?,
+            wheel_speed: get_setting(self, mouse::WHEEL_SPEED_COMMAND_NAME, "speed").await?,
+            wheel_delay: get_setting(self, mouse::WHEEL_DELAY_COMMAND_NAME, "delay").await?,
+            speed_limit: get_setting(self, mouse::SPEED_LIMIT_COMMAND_NAME, "speed").await?,
+        })
+    }
+
+    /// Apply how mouse keys move the cursor and scroll, after checking that
+    /// every setting is within range.
+    async fn apply_mouse_key_settings(
+        &mut self,
+        settings: &MouseKeySettings,
+    ) -> Result<(), SetSettingError> {
+        settings.validate()?;
+
+        set_setting(self, mouse::SPEED_COMMAND_NAME, settings.speed).await?;
+        set_setting(self, mouse::SPEED_DELAY_COMMAND_NAME, settings.speed_delay).await?;
+        set_setting(self, mouse::ACCEL_SPEED_COMMAND_NAME, settings.accel_speed).await?;
+        set_setting(self, mouse::ACCEL_DELAY_COMMAND_NAME, settings.accel_delay).await?;
+        set_setting(self, mouse::WHEEL_SPEED_COMMAND_NAME, settings.wheel_speed).await?;
+        set_setting(self, mouse::WHEEL_DELAY_COMMAND_NAME, settings.wheel_delay).await?;
+        set_setting(self, mouse::SPEED_LIMIT_COMMAND_NAME, settings.speed_limit).await
+    }
+
+    /// Get when dual-function keys act as their modifier or layer.
+    async fn get_dual_function_settings(
+        &mut self,
+    ) -> Result<DualFunctionSettings, GetSettingError> {
+        Ok(DualFunctionSettings {
+            hold_timeout: get_setting(self, qukeys::HOLD_TIMEOUT_COMMAND_NAME, "duration").await?,
+            overlap_threshold: get_setting(
+                self,
+                qukeys::OVERLAP_THRESHOLD_COMMAND_NAME,
+                "percentage",
+            )
+            .await?,
+        })
+    }
+
+    /// Apply when dual-function keys act as their modifier or layer, after
+    /// checking that every setting is within range.
+    async fn apply_dual_function_settings(
+        &mut self,
+        settings: &DualFunctionSettings,
+    ) -> Result<(), SetSettingError> {
+        settings.validate()?;
+
+        set_setting(
+            self,
+            qukeys::HOLD_TIMEOUT_COMMAND_NAME,
+            settings.hold_timeout,
+        )
+        .await?;
+        set_setting(
+            self,
+            qukeys::OVERLAP_THRESHOLD_COMMAND_NAME,
+            settings.overlap_threshold,
+        )
+        .await
+    }
+
+    /// Get the palette of colours the colormap refers to.
+    async fn get_palette(&mut self) -> Result<Palette, GetPaletteError> {
+        self.require_command(lighting::PALETTE_COMMAND_NAME).await?;
+
+        let palette = self
+            .focus_api()
+            .run_command(lighting::PALETTE_COMMAND_NAME, None)
+            .await?
+            .parse()?;
+
+        Ok(palette)
+    }
+
+    /// Apply the palette to the keyboard, which must have RGBW colours if
+    /// the keyboard's LEDs do, and RGB colours otherwise.
+    async fn apply_palette(&mut self, palette: &Palette) -> Result<(), ApplyPaletteError> {
+        self.require_command(lighting::PALETTE_COMMAND_NAME).await?;
+
+        let data = palette.to_command_data(self.descriptor())?;
+
+        self.focus_api()
+            .run_command(lighting::PALETTE_COMMAND_NAME, Some(&data))
+            .await?;
+
+        Ok(())
+    }
+
+    /// Get the colormap, which assigns a palette colour to each LED of each
+    /// layer.
+    async fn get_colormap(&mut self) -> Result<AnyColormap, GetColormapError> {
+        self.require_command(lighting::COLORMAP_COMMAND_NAME)
+            .await?;
+
+        let data = self
+            .focus_api()
+            .run_command(lighting::COLORMAP_COMMAND_NAME, None)
+            .await?;
+
+        let descriptor = self.descriptor();
+        let colormap = Colormap::from_str_with_leds(&data, descriptor.leds_per_layer)?;
+
+        Ok(AnyColormap::for_model(descriptor.model, colormap))
+    }
+
+    /// Apply the colormap to the keyboard, which must have one entry per LED
+    /// of each layer.
+    async fn apply_colormap(&mut self, colormap: &AnyColormap) -> Result<(), ApplyColormapError> {
+        self.require_command(lighting::COLORMAP_COMMAND_NAME)
+            .await?;
+
+        let data = colormap.to_colormap().to_command_data(self.descriptor())?;
+
+        self.focus_api()
+            .run_command(lighting::COLORMAP_COMMAND_NAME, Some(&data))
+            .await?;
+
+        Ok(())
+    }
+
+    /// Get the active LED mode, where `0` usually draws the colormap.
+    async fn get_led_mode(&mut self) -> Result<u8, GetSettingError> {
+        get_setting(self, leds::MODE_COMMAND_NAME, "LED mode").await
+    }
+
+    /// Set the active LED mode, which redraws every LED.
+    async fn set_led_mode(&mut self, mode: u8) -> Result<(), SetSettingError> {
+        set_setting(self, leds::MODE_COMMAND_NAME, mode).await
+    }
+
+    /// Redraw every LED with the active LED mode, undoing any colours set
+    /// with [`set_led_color`](Self::set_led_color) and friends.
+    async fn refresh_leds(&mut self) -> Result<(), SetSettingError> {
+        self.require_command(leds::MODE_COMMAND_NAME).await?;
+
+        let mode = self
+            .focus_api()
+            .run_command(leds::MODE_COMMAND_NAME, None)
+            .await?;
+
+        self.focus_api()
+            .run_command(leds::MODE_COMMAND_NAME, Some(mode.trim()))
+            .await?;
+
+        Ok(())
+    }
+
+    /// Get the brightness of the key LEDs.
+    async fn get_led_brightness(&mut self) -> Result<u8, GetSettingError> {
+        get_setting(self, leds::BRIGHTNESS_COMMAND_NAME, "brightness").await
+    }
+
+    /// Set the brightness of the key LEDs.
+    async fn set_led_brightness(&mut self, brightness: u8) -> Result<(), SetSettingError> {
+        set_setting(self, leds::BRIGHTNESS_COMMAND_NAME, brightness).await
+    }
+
+    /// Get the brightness of the underglow LEDs.
+    async fn get_underglow_brightness(&mut self) -> Result<u8, GetSettingError> {
+        get_setting(self, leds::UNDERGLOW_BRIGHTNESS_COMMAND_NAME, "brightness").await
+    }
+
+    /// Set the brightness of the underglow LEDs.
+    async fn set_underglow_brightness(&mut self, brightness: u8) -> Result<(), SetSettingError> {
+        set_setting(self, leds::UNDERGLOW_BRIGHTNESS_COMMAND_NAME, brightness).await
+    }
+
+    /// Get the colour currently shown by the LED, indexed from 0 in the
+    /// order the firmware uses.
+    async fn get_led_color(&mut self, led: u16) -> Result<Color, GetLedError> {
+        check_led(self, led)?;
+
+        self.require_command(leds::AT_COMMAND_NAME).await?;
+
+        let value = self
+            .focus_api()
+            .run_command(leds::AT_COMMAND_NAME, Some(&led.to_string()))
+            .await?;
+
+        let colors = parse_colors(leds::AT_COMMAND_NAME, value, self.descriptor())?;
+
+        Ok(colors[0])
+    }
+
+    /// Show a colour on the LED, indexed from 0 in the order the firmware
+    /// uses, until the LED mode redraws it.
+    ///
+    /// The stored colormap is left untouched.
+    async fn set_led_color(&mut self, led: u16, color: Color) -> Result<(), SetLedError> {
+        check_led(self, led)?;
+
+        self.require_command(leds::AT_COMMAND_NAME).await?;
+
+        let data = colors_to_command_data(self.descriptor(), [&color])?;
+
+        self.focus_api()
+            .run_command(leds::AT_COMMAND_NAME, Some(&format!("{led} {data}")))
+            .await?;
+
+        Ok(())
+    }
+
+    /// Show a colour on every LED, until the LED mode redraws them.
+    ///
+    /// The stored colormap is left untouched.
+    async fn set_all_leds(&mut self, color: Color) -> Result<(), SetLedError> {
+        self.require_command(leds::SET_ALL_COMMAND_NAME).await?;
+
+        let data = colors_to_command_data(self.descriptor(), [&color])?;
+
+        self.focus_api()
+            .run_command(leds::SET_ALL_COMMAND_NAME, Some(&data))
+            .await?;
+
+        Ok(())
+    }
+
+    /// Get the colour currently shown by every LED, in the order the
+    /// firmware uses.
+    async fn get_led_theme(&mut self) -> Result<Vec<Color>, GetLedError> {
+        self.require_command(leds::THEME_COMMAND_NAME).await?;
+
+        let value = self
+            .focus_api()
+            .run_command(leds::THEME_COMMAND_NAME, None)
+            .await?;
+
+        Ok(parse_colors(
+            leds::THEME_COMMAND_NAME,
+            value,
+            self.descriptor(),
+        )?)
+    }
+
+    /// Show a colour on every LED, which must have one colour per LED in the
+    /// order the firmware uses, until the LED mode redraws them.
+    ///
+    /// The stored colormap is left untouched.
+    async fn set_led_theme(&mut self, colors: &[Color]) -> Result<(), SetLedError> {
+        let descriptor = self.descriptor();
+
+        if colors.len() != descriptor.leds_per_layer {
+            return Err(SetLedError::IncorrectNumberOfColors {
+                keyboard: descriptor.name,
+                expected: descriptor.leds_per_layer,
+                found: colors.len(),
+            });
+        }
+
+        self.require_command(leds::THEME_COMMAND_NAME).await?;
+
+        let data = colors_to_command_data(descriptor, colors)?;
+
+        self.focus_api()
+            .run_command(leds::THEME_COMMAND_NAME, Some(&data))
+            .await?;
+
+        Ok(())
+    }
+
+    /// Get how long the keyboard waits before turning the LEDs off when
+    /// idle, in seconds, where `0` means never.
+    async fn get_idle_leds_timeout(&mut self) -> Result<u16, GetSettingError> {
+        get_setting(self, leds::IDLE_TIMEOUT_COMMAND_NAME, "timeout").await
+    }
+
+    /// Set how long the keyboard waits before turning the LEDs off when
+    /// idle, in seconds, where `0` means never.
+    async fn set_idle_leds_timeout(&mut self, timeout: u16) -> Result<(), SetSettingError> {
+        set_setting(self, leds::IDLE_TIMEOUT_COMMAND_NAME, timeout).await
+    }
+
+    /// Get the version of the firmware running on the keyboard.
+    async fn get_firmware_version(&mut self) -> Result<FirmwareVersion, GetSettingError> {
+        get_setting(self, settings::VERSION_COMMAND_NAME, "firmware version").await
+    }
+
+    /// Get the layer the keyboard starts on, indexed from 0.
+    async fn get_default_layer(&mut self) -> Result<u8, GetSettingError> {
+        get_setting(self, settings::DEFAULT_LAYER_COMMAND_NAME, "layer").await
+    }
+
+    /// Set the layer the keyboard starts on, indexed from 0.
+    async fn set_default_layer(&mut self, layer: u8) -> Result<(), SetSettingError> {
+        let max = self.descriptor().layers as u32 - 1;
+
+        check_setting_range(settings::DEFAULT_LAYER_COMMAND_NAME, layer.into(), 0, max)?;
+
+        set_setting(self, settings::DEFAULT_LAYER_COMMAND_NAME, layer).await
+    }
+
+    /// Check whether the settings stored on the keyboard are valid.
+    async fn get_settings_valid(&mut self) -> Result<bool, GetSettingError> {
+        let value = read_setting(self, settings::VALID_COMMAND_NAME).await?;
+
+        Ok(parse_bool_setting(settings::VALID_COMMAND_NAME, value)?)
+    }
+
+    /// Get the version of the settings stored on the keyboard.
+    async fn get_settings_version(&mut self) -> Result<u16, GetSettingError> {
+        get_setting(self, settings::SETTINGS_VERSION_COMMAND_NAME, "version").await
+    }
+
+    /// Get the checksums of the settings stored on the keyboard.
+    async fn get_settings_crc(&mut self) -> Result<SettingsCrc, GetSettingError> {
+        get_setting(self, settings::CRC_COMMAND_NAME, "pair of checksums").await
+    }
+
+    /// Get how often the keyboard halves check on each other, in
+    /// milliseconds.
+    async fn get_alive_interval(&mut self) -> Result<u32, GetSettingError> {
+        get_setting(self, settings::ALIVE_INTERVAL_COMMAND_NAME, "interval").await
+    }
+
+    /// Set how often the keyboard halves check on each other, in
+    /// milliseconds.
+    async fn set_alive_interval(&mut self, interval: u32) -> Result<(), SetSettingError> {
+        set_setting(self, settings::ALIVE_INTERVAL_COMMAND_NAME, interval).await
+    }
+
+    /// Activate the layer, indexed from 0, on top of the active ones.
+    async fn activate_layer(&mut self, layer: u8) -> Result<(), ChangeLayerError> {
+        run_layer_command(self, layers::ACTIVATE_COMMAND_NAME, layer).await?;
+
+        Ok(())
+    }
+
+    /// Deactivate the layer, indexed from 0.
+    async fn deactivate_layer(&mut self, layer: u8) -> Result<(), ChangeLayerError> {
+        run_layer_command(self, layers::DEACTIVATE_COMMAND_NAME, layer).await?;
+
+        Ok(())
+    }
+
+    /// Check whether the layer, indexed from 0, is active.
+    async fn is_layer_active(&mut self, layer: u8) -> Result<bool, GetLayerStateError> {
+        let value = run_layer_command(self, layers::IS_ACTIVE_COMMAND_NAME, layer).await?;
+
+        Ok(parse_bool_setting(
+            layers::IS_ACTIVE_COMMAND_NAME,
+            value.trim().to_owned(),
+        )?)
+    }
+
+    /// Get which layers are active.
+    ///
+    /// The state only covers the layers the keyboard's keymap has.
+    async fn layer_state(&mut self) -> Result<LayerState, GetLayerStateError> {
+        self.require_command(layers::STATE_COMMAND_NAME).await?;
+
+        let value = self
+            .focus_api()
+            .run_command(layers::STATE_COMMAND_NAME, None)
+            .await?;
+
+        Ok(LayerState::parse(&value, self.descriptor().layers)?)
+    }
+
+    /// Get the active layers, indexed from 0, in ascending order.
+    async fn active_layers(&mut self) -> Result<Vec<u8>, GetLayerStateError> {
+        Ok(self.layer_state().await?.active_layers())
+    }
+
+    /// Get every setting, leaving out those the firmware doesn't support, or
+    /// which are in a format the SDK doesn't understand.
+    async fn get_settings(&mut self) -> Result<Settings, GetSettingError> {
+        Ok(Settings {
+            firmware_version: if_readable(self.get_firmware_version().await)?,
+            default_layer: if_readable(self.get_default_layer().await)?,
+            valid: if_readable(self.get_settings_valid().await)?,
+            version: if_readable(self.get_settings_version().await)?,
+            crc: if_readable(self.get_settings_crc().await)?,
+            alive_interval: if_readable(self.get_alive_interval().await)?,
+        })
+    }
+
+    /// Get the charge of one half's battery.
+    async fn get_battery(&mut self, side: Side) -> Result<Battery, GetSettingError> {
+        let level = get_setting(self, side.battery_level_command(), "battery level").await?;
+        let status = get_setting(self, side.battery_status_command(), "battery status").await?;
+
+        Ok(Battery { level, status })
+    }
+
+    /// Check whether the power-saving mode is on.
+    async fn get_power_saving(&mut self) -> Result<bool, GetSettingError> {
+        let value = read_setting(self, wireless::SAVING_MODE_COMMAND_NAME).await?;
+
+        Ok(parse_bool_setting(
+            wireless::SAVING_MODE_COMMAND_NAME,
+            value,
+        )?)
+    }
+
+    /// Get the transmission power of the RF connection.
+    async fn get_rf_power(&mut self) -> Result<RfPower, GetSettingError> {
+        get_setting(self, wireless::RF_POWER_COMMAND_NAME, "RF power").await
+    }
+
+    /// Check whether the RF connection hops between channels.
+    async fn get_rf_channel_hop(&mut self) -> Result<bool, GetSettingError> {
+        let value = read_setting(self, wireless::RF_CHANNEL_HOP_COMMAND_NAME).await?;
+
+        Ok(parse_bool_setting(
+            wireless::RF_CHANNEL_HOP_COMMAND_NAME,
+            value,
+        )?)
+    }
+
+    /// Get the names of the Bluetooth devices the keyboard is paired with.
+    async fn get_bluetooth_devices(&mut self) -> Result<Vec<String>, GetSettingError> {
+        let value = read_setting(self, wireless::BLUETOOTH_DEVICES_COMMAND_NAME).await?;
+
+        let devices = value
+            .lines()
+            .map(str::trim)
+            .filter(|device| !device.is_empty())
+            .map(ToOwned::to_owned)
+            .collect();
+
+        Ok(devices)
+    }
+
+    /// Get the battery, energy and wireless status, leaving out anything the
+    /// firmware doesn't support, or which is in a format the SDK doesn't
+    /// understand.
+    async fn get_wireless_status(&mut self) -> Result<WirelessStatus, GetSettingError> {
+        Ok(WirelessStatus {
+            left_battery: if_readable(self.get_battery(Side::Left).await)?,
+            right_battery: if_readable(self.get_battery(Side::Right).await)?,
+            power_saving: if_readable(self.get_power_saving().await)?,
+            rf_power: if_readable(self.get_rf_power().await)?,
+            rf_channel_hop: if_readable(self.get_rf_channel_hop().await)?,
+            bluetooth_devices: if_readable(self.get_bluetooth_devices().await)?,
+        })
+    }
+
+    /// Read everything stored on the keyboard into a backup, leaving out
+    /// anything the firmware doesn't support, and settings which are in a
+    /// format the SDK doesn't understand.
+    async fn get_backup(&mut self) -> Result<DeviceBackup, GetBackupError> {
+        let model = self.descriptor().model;
+        let device = BackupDevice::new(model, self.capabilities().await?);
+
+        let superkeys = match if_part_supported(self.get_superkeys().await)? {
+            Some(superkeys) => Some(SuperkeyConfig {
+                settings: if_readable(self.get_superkey_settings().await)?,
+                superkeys,
+            }),
+            None => None,
+        };
+
+        let settings = BackupSettings {
+            default_layer: if_readable(self.get_default_layer().await)?,
+            only_custom: if_readable(self.get_only_custom().await)?,
+            alive_interval: if_readable(self.get_alive_interval().await)?,
+            led_brightness: if_readable(self.get_led_brightness().await)?,
+            underglow_brightness: if_readable(self.get_underglow_brightness().await)?,
+            idle_leds_timeout: if_readable(self.get_idle_leds_timeout().await)?,
+            mouse_keys: if_readable(self.get_mouse_key_settings().await)?,
+            dual_functions: if_readable(self.get_dual_function_settings().await)?,
+        };
+
+        Ok(DeviceBackup {
+            format_version: BACKUP_FORMAT_VERSION,
+            device,
+            keymap: if_part_supported(self.get_keymap().await)?,
+            superkeys,
+            macros: if_part_supported(self.get_macros().await)?,
+            palette: if_part_supported(self.get_palette().await)?,
+            colormap: if_part_supported(self.get_colormap().await)?,
+            settings,
+        })
+    }
+
+    /// Restore a backup onto the keyboard, after checking that it was made
+    /// from a compatible keyboard.
+    ///
+    /// Anything left out of the backup is left untouched on the keyboard.
+    async fn restore_backup(&mut self, backup: &DeviceBackup) -> Result<(), RestoreBackupError> {
+        let model = self.descriptor().model;
+
+        backup.check_compatibility(model, self.capabilities().await?)?;
+
+        if let Some(keymap) = &backup.keymap {
+            self.apply_keymap(keymap).await?;
+        }
+
+        if let Some(config) = &backup.superkeys {
+            self.apply_superkeys(&config.superkeys).await?;
+
+            if let Some(settings) = &config.settings {
+                self.apply_superkey_settings(settings).await?;
+            }
+        }
+
+        if let Some(macros) = &backup.macros {
+            self.apply_macros(macros).await?;
+        }
+
+        if let Some(palette) = &backup.palette {
+            self.apply_palette(palette).await?;
+        }
+
+        if let Some(colormap) = &backup.colormap {
+            self.apply_colormap(colormap).await?;
+        }
+
+        let BackupSettings {
+            default_layer,
+            only_custom,
+            alive_interval,
+            led_brightness,
+            underglow_brightness,
+            idle_leds_timeout,
+            mouse_keys,
+            dual_functions,
+        } = &backup.settings;
+
+        if let Some(layer) = default_layer {
+            self.set_default_layer(*layer).await?;
+        }
+
+        if let Some(only_custom) = only_custom {
+            self.set_only_custom(*only_custom).await?;
+        }
+
+        if let Some(interval) = alive_interval {
+            self.set_alive_interval(*interval).await?;
+        }
+
+        if let Some(brightness) = led_brightness {
+            self.set_led_brightness(*brightness).await?;
+        }
+
+        if let Some(brightness) = underglow_brightness {
+            self.set_underglow_brightness(*brightness).await?;
+        }
+
+        if let Some(timeout) = idle_leds_timeout {
+            self.set_idle_leds_timeout(*timeout).await?;
+        }
+
+        if let Some(settings) = mouse_keys {
+            self.apply_mouse_key_settings(settings).await?;
+        }
+
+        if let Some(settings) = dual_functions {
+            self.apply_dual_function_settings(settings).await?;
+        }
+
+        Ok(())
+    }
+}
+
+/// A handle to a keyboard of any supported model.
+#[derive(Debug, From)]
+pub enum AnyKeyboard {
+    /// A Dygma Defy.
+    Defy(DefyKeyboard),
+    /// An original Dygma Raise.
+    Raise(RaiseKeyboard),
+    /// A Dygma Raise 2.
+    Raise2(Raise2Keyboard),
+}
+
+impl AnyKeyboard {
+    /// Connects to the first connected keyboard, whatever its model.
+    pub async fn detect() -> Result<Self, DetectKeyboardError> {
+        Self::detect_with_selector(&DeviceSelector::Any).await
+    }
+
+    /// Connects to the keyboard chosen by the selector, telling its model
+    /// apart by its USB product id.
+    ///
+    /// Serial ports selected by path may not show up when listing devices,
+    /// such as symlinks or pseudo-terminals, so those are assumed to be a
+    /// Defy when they can't be found. Use [`AnyKeyboard::connect`] to
+    /// connect to a specific model instead.
+    pub async fn detect_with_selector(
+        selector: &DeviceSelector,
+    ) -> Result<Self, DetectKeyboardError> {
+        let device = discover_devices()
+            .await?
+            .into_iter()
+            .find(|device| device.matches(selector));
+
+        let model = match device {
+            Some(device) => Model::from_product_id(device.product_id)
+                .ok_or(DetectKeyboardError::UnknownModel(device.product_id))?,
+            None if matches!(selector, DeviceSelector::Path(_)) => Model::Defy,
+            None => return Err(DetectKeyboardError::NoDeviceFound(selector.clone())),
+        };
+
+        Ok(Self::connect(model, selector).await?)
+    }
+
+    /// Connects to the keyboard chosen by the selector, which must be of the
+    /// given model.
+    pub async fn connect(
+        model: Model,
+        selector: &DeviceSelector,
+    ) -> Result<Self, ConnectKeyboardError> {
+        let keyboard = match model {
+            Model::Defy => DefyKeyboard::with_selector(selector).await?.into(),
+            Model::Raise => RaiseKeyboard::with_selector(selector).await?.into(),
+            Model::Raise2 => Raise2Keyboard::with_selector(selector).await?.into(),
+        };
+
+        Ok(keyboard)
+    }
+
+    /// Creates a handle to a virtual keyboard of the given model, emulated
+    /// entirely in memory.
+    ///
+    /// Virtual keyboards of the Raise family have the ANSI layout.
+    pub fn new_virtual(model: Model) -> Self {
+        use raise::RaiseLayoutVariant;
+
+        match model {
+            Model::Defy => DefyKeyboard::new_virtual().into(),
+            Model::Raise => RaiseKeyboard::new_virtual(RaiseLayoutVariant::Ansi).into(),
+            Model::Raise2 => Raise2Keyboard::new_virtual(RaiseLayoutVariant::Ansi).into(),
+        }
+    }
+}
+
+impl Deref for AnyKeyboard {
+    type Target = DynFocusApi;
+
+    fn deref(&self) -> &Self::Target {
+        match self {
+            Self::Defy(defy) => defy,
+            Self::Raise(raise) => raise,
+            Self::Raise2(raise2) => raise2,
+        }
+    }
+}
+
+impl DerefMut for AnyKeyboard {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        match self {
+            Self::Defy(defy) => defy,
+            Self::Raise(raise) => raise,
+            Self::Raise2(raise2) => raise2,
+        }
+    }
+}
+
+impl Keyboard for AnyKeyboard {
+    fn descriptor(&self) -> &'static ModelDescriptor {
+        match self {
+            Self::Defy(defy) => defy.descriptor(),
+            Self::Raise(raise) => raise.descriptor(),
+            Self::Raise2(raise2) => raise2.descriptor(),
+        }
+    }
+
+    fn focus_api(&mut self) -> &mut DynFocusApi {
+        self
+    }
+
+    async fn capabilities(&mut self) -> Result<&Capabilities, GetCapabilitiesError> {
+        match self {
+            Self::Defy(defy) => defy.capabilities().await,
+            Self::Raise(raise) => raise.capabilities().await,
+            Self::Raise2(raise2) => raise2.capabilities().await,
+        }
+    }
+
+    async fn get_keymap(&mut self) -> Result<AnyKeymap, GetKeymapError> {
+        match self {
+            Self::Defy(defy) => defy.get_keymap().await,
+            Self::Raise(raise) => raise.get_keymap().await,
+            Self::Raise2(raise2) => raise2.get_keymap().await,
+        }
+    }
+
+    async fn get_default_keymap(&mut self) -> Result<AnyKeymap, GetKeymapError> {
+        match self {
+            Self::Defy(defy) => defy.get_default_keymap().await,
+            Self::Raise(raise) => raise.get_default_keymap().await,
+            Self::Raise2(raise2) => raise2.get_default_keymap().await,
+        }
+    }
+
+    async fn apply_keymap(&mut self, keymap: &AnyKeymap) -> Result<(), ApplyKeymapError> {
+        match self {
+            Self::Defy(defy) => defy.apply_keymap(keymap).await,
+            Self::Raise(raise) => raise.apply_keymap(keymap).await,
+            Self::Raise2(raise2) => raise2.apply_keymap(keymap).await,
+        }
+    }
+}
+
+/// Static dispatch for focus API connections.
+#[derive(Debug, From)]
+pub enum DynFocusApi {
+    /// Connections to the device over serial port,
+    /// which includes both wired over USB, as well as
+    /// wireless over RF.
+    Serial(SerialPortFocusApi),
+    /// Connections to the device over BTLE.
+    Bluetooth(HidFocusApi),
+    /// Connections to a device emulated in memory.
+    Mock(MockFocusApi),
+}
+
+impl FocusApiConnection for DynFocusApi {
+    async fn run_command(
+        &mut self,
+        command: &str,
+        data: Option<&str>,
+    ) -> Result<String, RunCommandError> {
+        match self {
+            Self::Serial(sp) => sp.run_command(command, data).await,
+            Self::Bluetooth(hid) => hid.run_command(command, data).await,
+            Self::Mock(mock) => mock.run_command(command, data).await,
+        }
+    }
+
+    async fn run_command_with_timeout(
+        &mut self,
+        command: &str,
+        data: Option<&str>,
+        timeout: Duration,
+    ) -> Result<String, RunCommandError> {
+        match self {
+            Self::Serial(sp) => sp.run_command_with_timeout(command, data, timeout).await,
+            Self::Bluetooth(hid) => hid.run_command_with_timeout(command, data, timeout).await,
+            Self::Mock(mock) => mock.run_command_with_timeout(command, data, timeout).await,
+        }
+    }
+
+    async fn resync(&mut self) -> Result<(), RunCommandError> {
+        match self {
+            Self::Serial(sp) => sp.resync().await,
+            Self::Bluetooth(hid) => hid.resync().await,
+            Self::Mock(mock) => mock.resync().await,
+        }
+    }
+
+    fn take_unsolicited_data(&mut self) -> Vec<u8> {
+        match self {
+            Self::Serial(sp) => sp.take_unsolicited_data(),
+            Self::Bluetooth(hid) => hid.take_unsolicited_data(),
+            Self::Mock(mock) => mock.take_unsolicited_data(),
+        }
+    }
+
+    fn take_events(&mut self) -> Option<mpsc::Receiver<ConnectionEvent>> {
+        match self {
+            Self::Serial(sp) => sp.take_events(),
+            Self::Bluetooth(hid) => hid.take_events(),
+            Self::Mock(mock) => mock.take_events(),
+        }
+    }
+}
+
+impl DynFocusApi {
+    /// Sets how long commands may take before failing with
+    /// [`RunCommandError::TimedOut`], or disables the timeout if `None`.
+    ///
+    /// Devices emulated in memory always respond immediately, so this has no
+    /// effect on them.
+    pub fn set_timeout(&mut self, timeout: Option<Duration>) {
+        match self {
+            Self::Serial(sp) => sp.set_timeout(timeout),
+            Self::Bluetooth(hid) => hid.set_timeout(timeout),
+            Self::Mock(_) => {}
+        }
+    }
+}
+
+/// Builds the `keymap.default` data of virtual keyboards, which has two
+/// layers: one with every key set to `A`, followed by a blank one.
+fn virtual_default_keymap(keys_per_layer: usize) -> String {
+    std::iter::repeat_n(4, keys_per_layer)
+        .chain(std::iter::repeat_n(0, keys_per_layer))
+        .join(" ")
+}
+
+/// Adds the commands supported by every virtual keyboard to the emulated
+/// device, sized for the model.
+fn with_virtual_commands(mock: MockFocusApi, descriptor: &ModelDescriptor) -> MockFocusApi {
+    let palette = Palette::new_black(descriptor)
+        .to_command_data(descriptor)
+        .unwrap();
+    let colormap = Colormap::new_cleared(descriptor)
+        .to_command_data(descriptor)
+        .unwrap();
+
+    let mock = with_virtual_superkey_settings(with_virtual_layers(mock));
+    let mock = with_virtual_qukeys(with_virtual_mouse_keys(mock));
+    let mock = with_virtual_wireless(mock, descriptor);
+
+    with_virtual_leds(mock, descriptor)
+        .with_command(lighting::PALETTE_COMMAND_NAME, palette)
+        .with_command(lighting::COLORMAP_COMMAND_NAME, colormap)
+        .with_read_only_command(settings::VERSION_COMMAND_NAME, "v1.0.0-virtual")
+        .with_command(settings::DEFAULT_LAYER_COMMAND_NAME, "0")
+        .with_read_only_command(settings::VALID_COMMAND_NAME, "1")
+        .with_read_only_command(settings::SETTINGS_VERSION_COMMAND_NAME, "1")
+        .with_read_only_command(settings::CRC_COMMAND_NAME, "0/0")
+        .with_command(settings::ALIVE_INTERVAL_COMMAND_NAME, "2000")
+        .with_command(keymap::ONLY_CUSTOM_COMMAND_NAME, "1")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::keycode_tables::KeyKind;
+
+    fn blank_keymap(model: Model) -> AnyKeymap {
+        let data = vec!["4"; model.descriptor().keys_per_layer * model.descriptor().layers];
+
+        AnyKeymap::from_str_for(model, &data.join(" ")).unwrap()
+    }
+
+    #[test]
+    fn models_are_found_by_product_id() {
+        assert_eq!(Model::from_product_id(0x0012), Some(Model::Defy));
+        assert_eq!(Model::from_product_id(0x2201), Some(Model::Raise));
+        assert_eq!(Model::from_product_id(0x0021), Some(Model::Raise2));
+        assert_eq!(Model::from_product_id(0xffff), None);
+
+        for model in Model::ALL {
+            assert_eq!(model.descriptor().model, model);
+            assert_eq!(model.to_string().parse::<Model>().unwrap(), model);
+        }
+    }
+
+    #[test]
+    fn keymap_family_is_told_apart_when_deserializing() {
+        for model in Model::ALL {
+            let keymap = blank_keymap(model);
+
+            let json = serde_json::to_string(&keymap).unwrap();
+
+            assert_eq!(serde_json::from_str::<AnyKeymap>(&json).unwrap(), keymap);
+        }
+    }
+
+    #[tokio::test]
+    async fn virtual_keyboards_work_alike() {
+        for model in Model::ALL {
+            let mut keyboard = AnyKeyboard::new_virtual(model);
+            let mut keymap = keyboard.get_keymap().await.unwrap();
+
+            keymap.clear_layer_to(2, KeyKind::from(5)).unwrap();
+
+            keyboard.apply_keymap(&keymap).await.unwrap();
+
+            assert_eq!(keyboard.descriptor().model, model);
+            assert_eq!(keyboard.get_keymap().await.unwrap(), keymap);
+        }
+    }
+
+    #[tokio::test]
+    async fn keymaps_for_other_models_are_rejected() {
+        let mut raise = AnyKeyboard::new_virtual(Model::Raise);
+
+        let err = raise
+            .apply_keymap(&blank_keymap(Model::Defy))
+            .await
+            .unwrap_err();
+
+        assert!(matches!(err, ApplyKeymapError::WrongModel { .. }));
+    }
+
+    #[tokio::test]
+    async fn commands_unsupported_by_the_firmware_fail_early() {
+        let mock = MockFocusApi::new()
+            .with_read_only_command(settings::VERSION_COMMAND_NAME, "v0.9.0")
+            .with_command(macros::MAP_COMMAND_NAME, "255");
+        let mut keyboard = AnyKeyboard::Defy(DefyKeyboard::from(DynFocusApi::from(mock)));
+
+        let err = keyboard.get_superkeys().await.unwrap_err();
+
+        assert!(matches!(err, GetSuperkeyMapError::Unsupported(_)));
+        assert_eq!(
+            err.to_string(),
+            "`superkeys.map` is unsupported by firmware v0.9.0"
+        );
+
+        assert!(
+            keyboard
+                .capabilities()
+                .await
+                .unwrap()
+                .supports(macros::MAP_COMMAND_NAME)
+        );
+    }
+
+    #[tokio::test]
+    async fn settings_are_typed() {
+        let mut keyboard = AnyKeyboard::new_virtual(Model::Raise2);
+
+        keyboard.set_default_layer(3).await.unwrap();
+        keyboard.set_alive_interval(500).await.unwrap();
+
+        let settings = keyboard.get_settings().await.unwrap();
+
+        assert_eq!(settings.default_layer, Some(3));
+        assert_eq!(settings.alive_interval, Some(500));
+        assert_eq!(settings.valid, Some(true));
+        assert_eq!(
+            settings.firmware_version.unwrap().to_string(),
+            "v1.0.0-virtual"
+        );
+
+        let err = keyboard.set_default_layer(10).await.unwrap_err();
+
+        assert!(matches!(err, SetSettingError::OutOfRange(_)));
+    }
+
+    #[tokio::test]
+    async fn layers_are_switched() {
+        let mut keyboard = AnyKeyboard::new_virtual(Model::Defy);
+
+        keyboard.activate_layer(3).await.unwrap();
+        keyboard.deactivate_layer(0).await.unwrap();
+
+        assert!(keyboard.is_layer_active(3).await.unwrap());
+        assert!(!keyboard.is_layer_active(0).await.unwrap());
+        assert_eq!(keyboard.active_layers().await.unwrap(), [3]);
+        assert_eq!(keyboard.layer_state().await.unwrap().0.len(), 10);
+
+        let err = keyboard.activate_layer(10).await.unwrap_err();
+
+        assert!(matches!(err, ChangeLayerError::OutOfRange(_)));
+    }
+
+    #[tokio::test]
+    async fn keymaps_are_reset_to_default() {
+        for model in Model::ALL {
+            let mut keyboard = AnyKeyboard::new_virtual(model);
+
+            let default = keyboard.get_default_keymap().await.unwrap();
+
+            assert_eq!(default.layers(), 2);
+
+            keyboard.reset_keymap_to_default().await.unwrap();
+
+            let mut keymap = keyboard.get_keymap().await.unwrap();
+
+            assert_eq!(keymap.layers(), model.descriptor().layers);
+
+            keymap.resize_to(2, KeyKind::from(0));
+
+            assert_eq!(keymap, default);
+        }
+    }
+
+    #[tokio::test]
+    async fn only_custom_is_toggled() {
+        let mut keyboard = AnyKeyboard::new_virtual(Model::Defy);
+
+        assert!(keyboard.get_only_custom().await.unwrap());
+
+        keyboard.set_only_custom(false).await.unwrap();
+
+        assert!(!keyboard.get_only_custom().await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn lighting_round_trips() {
+        for model in Model::ALL {
+            let mut keyboard = AnyKeyboard::new_virtual(model);
+
+            let mut palette = keyboard.get_palette().await.unwrap();
+
+            palette[3] = "#ff0000".parse().unwrap();
+            palette[3].white = model.descriptor().rgbw.then_some(0x10);
+
+            keyboard.apply_palette(&palette).await.unwrap();
+
+            assert_eq!(keyboard.get_palette().await.unwrap(), palette);
+
+            let mut colormap = keyboard.get_colormap().await.unwrap().to_colormap();
+
+            // The first LED belongs to a key on every model, while the eighth
+            // isn't part of the Defy's layout.
+            colormap[1][0] = 3;
+            colormap[1][7] = 4;
+
+            keyboard
+                .apply_colormap(&AnyColormap::for_model(model, colormap.clone()))
+                .await
+                .unwrap();
+
+            assert_eq!(
+                keyboard.get_colormap().await.unwrap().to_colormap(),
+                colormap
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn leds_are_set_without_touching_the_colormap() {
+        for model in Model::ALL {
+            let mut keyboard = AnyKeyboard::new_virtual(model);
+            let leds = model.descriptor().leds_per_layer as u16;
+            let colormap = keyboard.get_colormap().await.unwrap();
+
+            let red = Color::rgb(255, 0, 0);
+            let green = Color::rgb(0, 255, 0);
+
+            keyboard.set_all_leds(green).await.unwrap();
+            keyboard.set_led_color(5, red).await.unwrap();
+
+            let expected = |color: Color| Color {
+                white: model.descriptor().rgbw.then_some(0),
+                ..color
+            };
+
+            assert_eq!(keyboard.get_led_color(5).await.unwrap(), expected(red));
+            assert_eq!(keyboard.get_led_color(6).await.unwrap(), expected(green));
+
+            let theme = keyboard.get_led_theme().await.unwrap();
+
+            assert_eq!(theme.len(), leds as usize);
+            assert_eq!(theme[5], expected(red));
+
+            keyboard.set_led_theme(&theme).await.unwrap();
+
+            assert!(matches!(
+                keyboard.set_led_color(leds, red).await,
+                Err(SetLedError::OutOfRange(_))
+            ));
+            assert!(matches!(
+                keyboard.set_led_theme(&theme[1..]).await,
+                Err(SetLedError::IncorrectNumberOfColors { .. })
+            ));
+
+            keyboard.set_led_brightness(64).await.unwrap();
+            keyboard.set_idle_leds_timeout(0).await.unwrap();
+            keyboard.refresh_leds().await.unwrap();
+
+            assert_eq!(keyboard.get_led_brightness().await.unwrap(), 64);
+            assert_eq!(keyboard.get_idle_leds_timeout().await.unwrap(), 0);
+            assert_eq!(keyboard.get_colormap().await.unwrap(), colormap);
+        }
+    }
+
+    #[tokio::test]
+    async fn superkey_settings_round_trip() {
+        let mut keyboard = AnyKeyboard::new_virtual(Model::Defy);
+
+        let mut settings = keyboard.get_superkey_settings().await.unwrap();
+
+        settings.wait_time = 500;
+        settings.overlap = 40;
+
+        keyboard.apply_superkey_settings(&settings).await.unwrap();
+
+        assert_eq!(keyboard.get_superkey_settings().await.unwrap(), settings);
+
+        settings.overlap = 101;
+
+        assert!(matches!(
+            keyboard.apply_superkey_settings(&settings).await,
+            Err(SetSettingError::OutOfRange(_))
+        ));
+    }
+
+    #[tokio::test]
+    async fn dual_function_settings_round_trip() {
+        let mut keyboard = AnyKeyboard::new_virtual(Model::Defy);
+
+        let mut settings = keyboard.get_dual_function_settings().await.unwrap();
+
+        assert_eq!(settings, DualFunctionSettings::DEFAULT);
+
+        settings.hold_timeout = 180;
+
+        keyboard
+            .apply_dual_function_settings(&settings)
+            .await
+            .unwrap();
+
+        assert_eq!(
+            keyboard.get_dual_function_settings().await.unwrap(),
+            settings
+        );
+
+        settings.overlap_threshold = 101;
+
+        assert!(matches!(
+            keyboard.apply_dual_function_settings(&settings).await,
+            Err(SetSettingError::OutOfRange(_))
+        ));
+    }
+
+    #[test]
+    fn dual_function_keys_are_counted() {
+        let mut keymap = blank_keymap(Model::Defy);
+
+        assert_eq!(keymap.dual_function_keys(), 0);
+
+        keymap
+            .clear_layer_to(
+                1,
+                KeyKind::from(crate::keycode_tables::CONTROL_DUAL_FUNCTION + 4),
+            )
+            .unwrap();
+
+        assert_eq!(keymap.dual_function_keys(), 70);
+    }
+
+    #[tokio::test]
+    async fn backups_are_restored_onto_compatible_keyboards() {
+        for model in Model::ALL {
+            let mut keyboard = AnyKeyboard::new_virtual(model);
+
+            keyboard.set_default_layer(2).await.unwrap();
+            keyboard.set_led_brightness(40).await.unwrap();
+
+            let backup = keyboard.get_backup().await.unwrap();
+
+            assert_eq!(backup.device.model, model);
+            assert!(backup.keymap.is_some());
+            assert_eq!(backup.settings.default_layer, Some(2));
+
+            let mut other = AnyKeyboard::new_virtual(model);
+
+            other.restore_backup(&backup).await.unwrap();
+
+            assert_eq!(other.get_backup().await.unwrap(), backup);
+        }
+
+        let mut keyboard = AnyKeyboard::new_virtual(Model::Defy);
+
+        keyboard
+            .focus_api()
+            .run_command(leds::IDLE_TIMEOUT_COMMAND_NAME, Some("garbage"))
+            .await
+            .unwrap();
+
+        let backup = keyboard.get_backup().await.unwrap();
+
+        assert_eq!(backup.settings.idle_leds_timeout, None);
+        assert!(backup.settings.led_brightness.is_some());
+        assert!(matches!(
+            AnyKeyboard::new_virtual(Model::Raise2)
+                .restore_backup(&backup)
+                .await,
+            Err(RestoreBackupError::Incompatible(_))
+        ));
+    }
+
+    #[tokio::test]
+    async fn mouse_key_settings_are_validated() {
+        let mut keyboard = AnyKeyboard::new_virtual(Model::Defy);
+
+        let mut settings = keyboard.get_mouse_key_settings().await.unwrap();
+
+        settings.speed = 20;
+        settings.wheel_delay = 100;
+
+        keyboard.apply_mouse_key_settings(&settings).await.unwrap();
+
+        assert_eq!(keyboard.get_mouse_key_settings().await.unwrap(), settings);
+
+        for invalid in [
+            MouseKeySettings {
+                speed: 128,
+                ..settings
+            },
+            MouseKeySettings {
+                speed_limit: 0,
+                ..settings
+            },
+        ] {
+            assert!(matches!(
+                keyboard.apply_mouse_key_settings(&invalid).await,
+                Err(SetSettingError::OutOfRange(_))
+            ));
+        }
+
+        assert_eq!(keyboard.get_mouse_key_settings().await.unwrap(), settings);
+    }
+
+    #[tokio::test]
+    async fn wireless_status_is_read() {
+        let mut keyboard = AnyKeyboard::new_virtual(Model::Defy);
+
+        let status = keyboard.get_wireless_status().await.unwrap();
+
+        assert_eq!(
+            status.left_battery,
+            Some(Battery {
+                level: 100,
+                status: wireless::BatteryStatus::Charged,
+            })
+        );
+        assert_eq!(status.rf_power, Some(RfPower::Medium));
+        assert_eq!(status.bluetooth_devices, Some(vec![]));
+
+        keyboard
+            .focus_api()
+            .run_command(wireless::RF_POWER_COMMAND_NAME, Some("7"))
+            .await
+            .unwrap();
+
+        let status = keyboard.get_wireless_status().await.unwrap();
+
+        assert_eq!(status.rf_power, None);
+        assert!(status.left_battery.is_some());
+
+        let mut keyboard = AnyKeyboard::new_virtual(Model::Raise);
+
+        assert_eq!(
+            keyboard.get_wireless_status().await.unwrap(),
+            WirelessStatus::default()
+        );
+    }
+
+    #[tokio::test]
+    async fn unsupported_settings_are_left_out() {
+        let mock =
+            MockFocusApi::new().with_read_only_command(settings::VERSION_COMMAND_NAME, "v0.9.0");
+        let mut keyboard = AnyKeyboard::Defy(DefyKeyboard::from(DynFocusApi::from(mock)));
+
+        let settings = keyboard.get_settings().await.unwrap();
+
+        assert_eq!(settings.firmware_version.unwrap().minor, 9);
+        assert_eq!(settings.default_layer, None);
+        assert!(matches!(
+            keyboard.get_default_layer().await.unwrap_err(),
+            GetSettingError::Unsupported(_)
+        ));
+    }
+
+    #[tokio::test]
+    async fn unreadable_settings_are_left_out() {
+        let mock = MockFocusApi::new()
+            .with_read_only_command(settings::VERSION_COMMAND_NAME, "nightly")
+            .with_read_only_command(settings::CRC_COMMAND_NAME, "garbage")
+            .with_command(settings::DEFAULT_LAYER_COMMAND_NAME, "2");
+        let mut keyboard = AnyKeyboard::Defy(DefyKeyboard::from(DynFocusApi::from(mock)));
+
+        let settings = keyboard.get_settings().await.unwrap();
+
+        assert_eq!(settings.firmware_version, None);
+        assert_eq!(settings.crc, None);
+        assert_eq!(settings.default_layer, Some(2));
+        assert!(matches!(
+            keyboard.get_firmware_version().await.unwrap_err(),
+            GetSettingError::ParsingFailure(_)
+        ));
+    }
+}
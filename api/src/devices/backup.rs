@@ -0,0 +1,361 @@
+//! Provides the [`DeviceBackup`] type, a snapshot of everything the firmware
+//! lets the SDK read back, which can be saved as a single JSON file and
+//! restored onto a compatible keyboard.
+//!
+//! The methods themselves live on the [`Keyboard`](super::Keyboard) trait.
+
+use super::{
+    AnyKeymap, ApplyKeymapError, GetKeymapError, Model,
+    defy::{
+        ApplyMacrosError, ApplySuperkeyError, DefyMacroMap, GetMacroMapError, GetSuperkeyMapError,
+    },
+    lighting::{
+        AnyColormap, ApplyColormapError, ApplyPaletteError, GetColormapError, GetPaletteError,
+        Palette,
+    },
+    mouse::MouseKeySettings,
+    qukeys::DualFunctionSettings,
+    settings::{FirmwareVersion, GetSettingError, SetSettingError},
+    superkeys::SuperkeyConfig,
+};
+use crate::focus_api::{Capabilities, GetCapabilitiesError, RequireCommandError};
+pub use error::*;
+
+mod error {
+    use super::*;
+
+    /// Error returned when a backup cannot be restored onto a keyboard.
+    #[derive(Clone, Debug, Display, Error)]
+    pub enum IncompatibleBackupError {
+        /// The backup was made by a newer version of the SDK.
+        #[display(
+            "the backup uses format version {found}, but only versions up to {supported} are supported"
+        )]
+        UnsupportedFormatVersion {
+            /// The format version of the backup.
+            found: u32,
+            /// The newest format version supported.
+            supported: u32,
+        },
+        /// The backup was made from a different model.
+        #[display("a backup of a {backup} cannot be restored onto a {keyboard}")]
+        WrongModel {
+            /// The model the backup was made from.
+            backup: Model,
+            /// The model of the keyboard.
+            keyboard: Model,
+        },
+        /// The backup was made from a different physical layout.
+        #[display("a backup of an {backup} keyboard cannot be restored onto an {keyboard} one")]
+        WrongLayout {
+            /// The layout the backup was made from.
+            backup: String,
+            /// The layout of the keyboard.
+            keyboard: String,
+        },
+    }
+
+    /// Error returned from
+    /// [`Keyboard::get_backup`](crate::devices::Keyboard::get_backup).
+    #[derive(Debug, Display, From, Error)]
+    pub enum GetBackupError {
+        /// The firmware and hardware could not be described.
+        #[display("{_0}")]
+        Capabilities(GetCapabilitiesError),
+        /// The keymap could not be read.
+        #[display("failed to back up the keymap: {_0}")]
+        Keymap(GetKeymapError),
+        /// The superkeys could not be read.
+        #[display("failed to back up the superkeys: {_0}")]
+        Superkeys(GetSuperkeyMapError),
+        /// The macros could not be read.
+        #[display("failed to back up the macros: {_0}")]
+        Macros(GetMacroMapError),
+        /// The palette could not be read.
+        #[display("failed to back up the palette: {_0}")]
+        Palette(GetPaletteError),
+        /// The colormap could not be read.
+        #[display("failed to back up the colormap: {_0}")]
+        Colormap(GetColormapError),
+        /// A setting could not be read.
+        #[display("failed to back up the settings: {_0}")]
+        Setting(GetSettingError),
+    }
+
+    /// Error returned from
+    /// [`Keyboard::restore_backup`](crate::devices::Keyboard::restore_backup).
+    #[derive(Debug, Display, From, Error)]
+    pub enum RestoreBackupError {
+        /// The firmware and hardware could not be described.
+        #[display("{_0}")]
+        Capabilities(GetCapabilitiesError),
+        /// The backup does not fit the keyboard.
+        #[display("{_0}")]
+        Incompatible(IncompatibleBackupError),
+        /// The keymap could not be restored.
+        #[display("failed to restore the keymap: {_0}")]
+        Keymap(ApplyKeymapError),
+        /// The superkeys could not be restored.
+        #[display("failed to restore the superkeys: {_0}")]
+        Superkeys(ApplySuperkeyError),
+        /// The macros could not be restored.
+        #[display("failed to restore the macros: {_0}")]
+        Macros(ApplyMacrosError),
+        /// The palette could not be restored.
+        #[display("failed to restore the palette: {_0}")]
+        Palette(ApplyPaletteError),
+        /// The colormap could not be restored.
+        #[display("failed to restore the colormap: {_0}")]
+        Colormap(ApplyColormapError),
+        /// A setting could not be restored.
+        #[display("failed to restore the settings: {_0}")]
+        Setting(SetSettingError),
+    }
+}
+
+/// The version of the backup format written by this version of the SDK.
+///
+/// It is bumped whenever a change would stop older versions from reading
+/// new backups correctly.
+pub const BACKUP_FORMAT_VERSION: u32 = 1;
+
+/// Everything stored on a keyboard, along with a description of the
+/// keyboard it was read from.
+///
+/// Anything the firmware does not support is left as [`None`], and is left
+/// untouched when restoring.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DeviceBackup {
+    /// The version of the backup format, see [`BACKUP_FORMAT_VERSION`].
+    pub format_version: u32,
+    /// The keyboard the backup was read from.
+    pub device: BackupDevice,
+    /// The custom keymap.
+    pub keymap: Option<AnyKeymap>,
+    /// The superkeys, along with their timings.
+    pub superkeys: Option<SuperkeyConfig>,
+    /// The macros.
+    pub macros: Option<DefyMacroMap>,
+    /// The LED palette.
+    pub palette: Option<Palette>,
+    /// The LED colormap.
+    pub colormap: Option<AnyColormap>,
+    /// The settings which aren't part of the above.
+    pub settings: BackupSettings,
+}
+
+/// The keyboard a [`DeviceBackup`] was read from.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct BackupDevice {
+    /// The model of the keyboard.
+    pub model: Model,
+    /// The version of the firmware, if the firmware reports it.
+    pub firmware_version: Option<String>,
+    /// The hardware version, if the firmware reports it.
+    pub hardware_version: Option<String>,
+    /// The physical layout, such as `ANSI` or `ISO`, if the firmware
+    /// reports it.
+    pub layout: Option<String>,
+    /// The unique id of the keyboard's chip, if the firmware reports it.
+    pub chip_id: Option<String>,
+}
+
+impl BackupDevice {
+    /// Describes a keyboard of the given model from its capabilities.
+    pub fn new(model: Model, capabilities: &Capabilities) -> Self {
+        Self {
+            model,
+            firmware_version: capabilities.firmware_version.clone(),
+            hardware_version: capabilities.hardware_version.clone(),
+            layout: capabilities.layout.clone(),
+            chip_id: capabilities.chip_id.clone(),
+        }
+    }
+}
+
+/// The settings saved in a [`DeviceBackup`].
+///
+/// Settings the firmware does not support are left as [`None`].
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct BackupSettings {
+    /// The layer the keyboard starts on, indexed from 0.
+    pub default_layer: Option<u8>,
+    /// Whether the keyboard only uses the custom keymap.
+    pub only_custom: Option<bool>,
+    /// How often the keyboard halves check on each other, in milliseconds.
+    pub alive_interval: Option<u32>,
+    /// The brightness of the key LEDs.
+    pub led_brightness: Option<u8>,
+    /// The brightness of the underglow LEDs.
+    pub underglow_brightness: Option<u8>,
+    /// How long the LEDs stay on while the keyboard is idle, in seconds.
+    pub idle_leds_timeout: Option<u16>,
+    /// How mouse keys move the cursor and scroll.
+    pub mouse_keys: Option<MouseKeySettings>,
+    /// When dual-function keys act as their modifier or layer.
+    pub dual_functions: Option<DualFunctionSettings>,
+}
+
+impl DeviceBackup {
+    /// Checks that the backup can be restored onto a keyboard of the given
+    /// model, with the given capabilities.
+    ///
+    /// Firmware versions are not compared, as the firmware keeps the format
+    /// of what is backed up stable across versions. Use
+    /// [`DeviceBackup::firmware_differs`] to warn about it instead.
+    pub fn check_compatibility(
+        &self,
+        model: Model,
+        capabilities: &Capabilities,
+    ) -> Result<(), IncompatibleBackupError> {
+        if self.format_version > BACKUP_FORMAT_VERSION {
+            return Err(IncompatibleBackupError::UnsupportedFormatVersion {
+                found: self.format_version,
+                supported: BACKUP_FORMAT_VERSION,
+            });
+        }
+
+        if self.device.model != model {
+            return Err(IncompatibleBackupError::WrongModel {
+                backup: self.device.model,
+                keyboard: model,
+            });
+        }
+
+        if let (Some(backup), Some(keyboard)) = (&self.device.layout, &capabilities.layout)
+            && !backup.eq_ignore_ascii_case(keyboard)
+        {
+            return Err(IncompatibleBackupError::WrongLayout {
+                backup: backup.clone(),
+                keyboard: keyboard.clone(),
+            });
+        }
+
+        Ok(())
+    }
+
+    /// Checks whether the backup was made with a different major firmware
+    /// version than the one in the capabilities.
+    ///
+    /// Versions which fail to parse are assumed to be the same.
+    pub fn firmware_differs(&self, capabilities: &Capabilities) -> bool {
+        let major = |version: &Option<String>| {
+            version
+                .as_deref()
+                .and_then(|version| version.parse::<FirmwareVersion>().ok())
+                .map(|version| version.major)
+        };
+
+        match (
+            major(&self.device.firmware_version),
+            major(&capabilities.firmware_version),
+        ) {
+            (Some(backup), Some(keyboard)) => backup != keyboard,
+            _ => false,
+        }
+    }
+}
+
+/// Errors which tell apart the firmware lacking a command, so that the
+/// parts of a backup it doesn't support can be left out.
+pub(crate) trait UnsupportedError {
+    /// Checks whether the error is caused by the firmware lacking the
+    /// command.
+    fn is_unsupported(&self) -> bool;
+}
+
+macro_rules! impl_unsupported_error {
+    ($($error:ty),* $(,)?) => {
+        $(
+            impl UnsupportedError for $error {
+                fn is_unsupported(&self) -> bool {
+                    matches!(self, Self::Unsupported(RequireCommandError::Unsupported(_)))
+                }
+            }
+        )*
+    };
+}
+
+impl_unsupported_error!(
+    GetKeymapError,
+    GetSuperkeyMapError,
+    GetMacroMapError,
+    GetPaletteError,
+    GetColormapError,
+    GetSettingError,
+);
+
+/// Turns the error for a part the firmware doesn't support into [`None`],
+/// like [`if_supported`](super::settings::if_supported) does for settings.
+pub(crate) fn if_part_supported<T, E>(res: Result<T, E>) -> Result<Option<T>, E>
+where
+    E: UnsupportedError,
+{
+    match res {
+        Ok(value) => Ok(Some(value)),
+        Err(err) if err.is_unsupported() => Ok(None),
+        Err(err) => Err(err),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn backup(model: Model, layout: Option<&str>) -> DeviceBackup {
+        DeviceBackup {
+            format_version: BACKUP_FORMAT_VERSION,
+            device: BackupDevice {
+                model,
+                firmware_version: Some("v1.0.0".to_owned()),
+                hardware_version: None,
+                layout: layout.map(ToOwned::to_owned),
+                chip_id: None,
+            },
+            keymap: None,
+            superkeys: None,
+            macros: None,
+            palette: None,
+            colormap: None,
+            settings: BackupSettings::default(),
+        }
+    }
+
+    #[test]
+    fn compatibility_is_checked() {
+        let capabilities = Capabilities {
+            firmware_version: Some("v2.0.0".to_owned()),
+            layout: Some("ISO".to_owned()),
+            ..Capabilities::default()
+        };
+
+        let iso = backup(Model::Raise, Some("iso"));
+
+        assert!(iso.check_compatibility(Model::Raise, &capabilities).is_ok());
+        assert!(iso.firmware_differs(&capabilities));
+
+        assert!(matches!(
+            iso.check_compatibility(Model::Defy, &capabilities),
+            Err(IncompatibleBackupError::WrongModel { .. })
+        ));
+        assert!(matches!(
+            backup(Model::Raise, Some("ANSI")).check_compatibility(Model::Raise, &capabilities),
+            Err(IncompatibleBackupError::WrongLayout { .. })
+        ));
+        assert!(
+            backup(Model::Raise, None)
+                .check_compatibility(Model::Raise, &capabilities)
+                .is_ok()
+        );
+
+        let newer = DeviceBackup {
+            format_version: BACKUP_FORMAT_VERSION + 1,
+            ..iso
+        };
+
+        assert!(matches!(
+            newer.check_compatibility(Model::Raise, &capabilities),
+            Err(IncompatibleBackupError::UnsupportedFormatVersion { .. })
+        ));
+    }
+}
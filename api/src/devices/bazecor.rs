@@ -0,0 +1,581 @@
+//! Provides the [`BazecorBackup`] type, used for reading and writing the
+//! backup files Bazecor saves, and converting them from and to a
+//! [`DeviceBackup`].
+//!
+//! Bazecor backups hold the raw data of every Focus API command, next to the
+//! neuron id of the keyboard they were read from. Only the commands which
+//! make up a [`DeviceBackup`] are converted, and anything else in the file is
+//! ignored.
+//!
+//! Layouts shared from Bazecor's layout editor use a different format, which
+//! isn't supported yet. [`BazecorBackup::from_json_value`] recognises them,
+//! so that they are rejected with [`ReadBazecorBackupError::LayoutFile`]
+//! rather than a confusing parsing error.
+
+use super::{
+    AnyKeymap, Model,
+    backup::{BACKUP_FORMAT_VERSION, BackupDevice, BackupSettings, DeviceBackup},
+    defy::{
+        KeymapDoesNotHave10LayersError, ParseKeymapError, ParseMacroMapError, ParseSuperkeyMapError,
+    },
+    keymap, leds,
+    lighting::{
+        self, AnyColormap, ApplyColormapError, ApplyPaletteError, Colormap, PALETTE_SIZE,
+        ParseColormapError, ParsePaletteError,
+    },
+    macros,
+    mouse::{self, MouseKeySettings},
+    qukeys::{self, DualFunctionSettings},
+    raise::{RaiseKeymap, RaiseLayoutVariant},
+    settings::{self, ParseSettingError, parse_bool_setting, parse_setting},
+    superkeys::{self, SuperkeyConfig, SuperkeySettings},
+};
+use crate::focus_api::parsing;
+pub use error::*;
+use itertools::Itertools;
+use std::{fmt, str::FromStr};
+
+mod error {
+    use super::*;
+
+    /// Error returned from [`BazecorBackup::from_json_value`].
+    #[derive(Debug, Display, From, Error)]
+    pub enum ReadBazecorBackupError {
+        /// The file is a layout shared from Bazecor's layout editor, rather
+        /// than a backup.
+        #[display(
+            "the file is a layout from Bazecor's layout editor, which isn't supported, use a backup saved from Bazecor's preferences instead"
+        )]
+        LayoutFile,
+        /// The file is not a Bazecor backup.
+        #[display("{_0}")]
+        Json(serde_json::Error),
+    }
+
+    /// Error returned from [`BazecorBackup::detect_model`].
+    #[derive(Clone, Debug, Display, Error)]
+    pub enum DetectModelError {
+        /// The backup fits more than one model.
+        #[display(
+            "the backup could be from any of {}, pass the model explicitly",
+            candidates.iter().join(", ")
+        )]
+        Ambiguous {
+            /// The models the backup fits.
+            candidates: Vec<Model>,
+        },
+        /// The backup doesn't fit any model.
+        #[display("the colormap and palette of the backup don't fit any known model")]
+        Unknown,
+    }
+
+    /// Error returned when converting a [`BazecorBackup`] into a
+    /// [`DeviceBackup`].
+    #[derive(Debug, Display, From, Error)]
+    pub enum ImportBazecorError {
+        /// The keymap failed to parse.
+        #[display("{_0}")]
+        Keymap(ParseKeymapError),
+        /// The superkeys failed to parse.
+        #[display("{_0}")]
+        Superkeys(ParseSuperkeyMapError),
+        /// The macros failed to parse.
+        #[display("{_0}")]
+        Macros(ParseMacroMapError),
+        /// The palette failed to parse.
+        #[display("{_0}")]
+        Palette(ParsePaletteError),
+        /// The colormap failed to parse.
+        #[display("{_0}")]
+        Colormap(ParseColormapError),
+        /// A setting failed to parse.
+        #[display("{_0}")]
+        Setting(ParseSettingError),
+    }
+
+    /// Error returned when converting a [`DeviceBackup`] into a
+    /// [`BazecorBackup`].
+    #[derive(Debug, Display, From, Error)]
+    pub enum ExportBazecorError {
+        /// The keymap doesn't have the number of layers the firmware expects.
+        #[display("{_0}")]
+        Keymap(KeymapDoesNotHave10LayersError),
+        /// The superkeys don't fit in the keyboard's memory.
+        #[display("{_0}")]
+        Superkeys(parsing::superkeys::TooManySuperkeysError),
+        /// The macros don't fit in the keyboard's memory.
+        #[display("{_0}")]
+        Macros(parsing::macros::MacroCommandDataError),
+        /// The palette doesn't fit the keyboard's LEDs.
+        #[display("{_0}")]
+        Palette(ApplyPaletteError),
+        /// The colormap doesn't fit the keyboard's LEDs.
+        #[display("{_0}")]
+        Colormap(ApplyColormapError),
+    }
+}
+
+/// A backup file saved by Bazecor.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct BazecorBackup {
+    /// The unique id of the keyboard's chip, which Bazecor calls the
+    /// neuron id.
+    #[serde(rename = "neuronID")]
+    pub neuron_id: String,
+    /// The data of each command, in the order Bazecor restores them.
+    pub backup: Vec<BazecorCommand>,
+}
+
+/// The data of one command in a [`BazecorBackup`].
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct BazecorCommand {
+    /// The name of the command.
+    pub command: String,
+    /// The raw data of the command, as sent over the Focus API.
+    pub data: String,
+}
+
+impl BazecorBackup {
+    /// Reads a backup from the contents of a Bazecor JSON file.
+    ///
+    /// Layouts from Bazecor's layout editor are told apart from backups by
+    /// having a keymap, but no list of commands.
+    pub fn from_json_value(value: serde_json::Value) -> Result<Self, ReadBazecorBackupError> {
+        if value.get("backup").is_none() && value.get("keymap").is_some() {
+            return Err(ReadBazecorBackupError::LayoutFile);
+        }
+
+        Ok(serde_json::from_value(value)?)
+    }
+
+    /// Gets the data of a command, if the backup holds it.
+    pub fn get(&self, command: &str) -> Option<&str> {
+        self.backup
+            .iter()
+            .find(|entry| entry.command == command)
+            .map(|entry| entry.data.trim())
+    }
+
+    /// Adds the data of a command to the end of the backup.
+    pub fn push(&mut self, command: &str, data: impl fmt::Display) {
+        self.backup.push(BazecorCommand {
+            command: command.to_owned(),
+            data: data.to_string(),
+        });
+    }
+
+    /// Works out which model the backup was made from, as Bazecor doesn't
+    /// store it.
+    ///
+    /// The models are told apart by the number of LEDs in the colormap, and
+    /// whether the palette is RGB or RGBW. The keymap can't be used, as every
+    /// model has the same number of keys per layer.
+    pub fn detect_model(&self) -> Result<Model, DetectModelError> {
+        let values = |command| {
+            self.get(command)
+                .map(|data| data.split_whitespace().count())
+        };
+        let colormap = values(lighting::COLORMAP_COMMAND_NAME);
+        let palette = values(lighting::PALETTE_COMMAND_NAME);
+
+        let candidates = Model::ALL
+            .into_iter()
+            .filter(|model| {
+                let descriptor = model.descriptor();
+                let channels = if descriptor.rgbw { 4 } else { 3 };
+
+                colormap.is_none_or(|len| len > 0 && len % descriptor.leds_per_layer == 0)
+                    && palette.is_none_or(|len| len == PALETTE_SIZE * channels)
+            })
+            .collect::<Vec<_>>();
+
+        match candidates[..] {
+            [model] => Ok(model),
+            [] => Err(DetectModelError::Unknown),
+            _ => Err(DetectModelError::Ambiguous { candidates }),
+        }
+    }
+
+    /// Converts the backup into a [`DeviceBackup`] for the given model,
+    /// leaving out anything the backup doesn't hold.
+    ///
+    /// Keymaps of the Raise family are read with the layout variant stored
+    /// in the backup, or as ISO when there is none, so that no key is lost.
+    pub fn to_device_backup(&self, model: Model) -> Result<DeviceBackup, ImportBazecorError> {
+        let descriptor = model.descriptor();
+
+        let keymap = match self.get(keymap::CUSTOM_COMMAND_NAME) {
+            Some(data) => Some(match model {
+                Model::Defy => AnyKeymap::Defy(data.parse()?),
+                Model::Raise | Model::Raise2 => {
+                    let variant = self
+                        .get(keymap::LAYOUT_COMMAND_NAME)
+                        .and_then(|layout| layout.parse().ok())
+                        .unwrap_or(RaiseLayoutVariant::Iso);
+
+                    AnyKeymap::Raise(RaiseKeymap::from_str_with_variant(data, variant)?)
+                }
+            }),
+            None => None,
+        };
+
+        let superkeys = match self.get(superkeys::MAP_COMMAND_NAME) {
+            Some(data) => Some(SuperkeyConfig {
+                settings: self.superkey_settings()?,
+                superkeys: data.parse()?,
+            }),
+            None => None,
+        };
+
+        let colormap = match self.get(lighting::COLORMAP_COMMAND_NAME) {
+            Some(data) => Some(AnyColormap::for_model(
+                model,
+                Colormap::from_str_with_leds(data, descriptor.leds_per_layer)?,
+            )),
+            None => None,
+        };
+
+        let settings = BackupSettings {
+            default_layer: self.setting(settings::DEFAULT_LAYER_COMMAND_NAME, "layer")?,
+            only_custom: self.bool_setting(keymap::ONLY_CUSTOM_COMMAND_NAME)?,
+            alive_interval: self.setting(settings::ALIVE_INTERVAL_COMMAND_NAME, "interval")?,
+            led_brightness: self.setting(leds::BRIGHTNESS_COMMAND_NAME, "brightness")?,
+            underglow_brightness: self
+                .setting(leds::UNDERGLOW_BRIGHTNESS_COMMAND_NAME, "brightness")?,
+            idle_leds_timeout: self.setting(leds::IDLE_TIMEOUT_COMMAND_NAME, "timeout")?,
+            mouse_keys: self.mouse_key_settings()?,
+            dual_functions: self.dual_function_settings()?,
+        };
+
+        Ok(DeviceBackup {
+            format_version: BACKUP_FORMAT_VERSION,
+            device: BackupDevice {
+                model,
+                firmware_version: self
+                    .get(settings::VERSION_COMMAND_NAME)
+                    .map(ToOwned::to_owned),
+                hardware_version: None,
+                layout: self.get(keymap::LAYOUT_COMMAND_NAME).map(ToOwned::to_owned),
+                chip_id: Some(self.neuron_id.clone()).filter(|id| !id.is_empty()),
+            },
+            keymap,
+            superkeys,
+            macros: self
+                .get(macros::MAP_COMMAND_NAME)
+                .map(str::parse)
+                .transpose()?,
+            palette: self
+                .get(lighting::PALETTE_COMMAND_NAME)
+                .map(str::parse)
+                .transpose()?,
+            colormap,
+            settings,
+        })
+    }
+
+    /// Converts a [`DeviceBackup`] into a backup Bazecor can restore.
+    ///
+    /// Only the commands the keyboard accepts data for are written, so the
+    /// firmware version and layout are left out.
+    pub fn from_device_backup(backup: &DeviceBackup) -> Result<Self, ExportBazecorError> {
+        let descriptor = backup.device.model.descriptor();
+
+        let mut bazecor = Self {
+            neuron_id: backup.device.chip_id.clone().unwrap_or_default(),
+            backup: vec![],
+        };
+
+        if let Some(keymap) = &backup.keymap {
+            bazecor.push(keymap::CUSTOM_COMMAND_NAME, keymap.to_keymap_custom_data()?);
+        }
+
+        if let Some(config) = &backup.superkeys {
+            let data = parsing::superkeys::SuperkeyMap::from(&config.superkeys)
+                .to_sized_command_data(descriptor.superkey_memory_size)?;
+
+            bazecor.push(superkeys::MAP_COMMAND_NAME, data);
+
+            if let Some(settings) = &config.settings {
+                bazecor.push(superkeys::WAIT_TIME_COMMAND_NAME, settings.wait_time);
+                bazecor.push(superkeys::TIMEOUT_COMMAND_NAME, settings.timeout);
+                bazecor.push(superkeys::REPEAT_COMMAND_NAME, settings.repeat);
+                bazecor.push(superkeys::HOLD_START_COMMAND_NAME, settings.hold_start);
+                bazecor.push(superkeys::OVERLAP_COMMAND_NAME, settings.overlap);
+            }
+        }
+
+        if let Some(macros) = &backup.macros {
+            let data = parsing::macros::MacroMap::from(macros)
+                .to_sized_command_data(descriptor.macro_memory_size)?;
+
+            bazecor.push(macros::MAP_COMMAND_NAME, data);
+        }
+
+        if let Some(palette) = &backup.palette {
+            bazecor.push(
+                lighting::PALETTE_COMMAND_NAME,
+                palette.to_command_data(descriptor)?,
+            );
+        }
+
+        if let Some(colormap) = &backup.colormap {
+            bazecor.push(
+                lighting::COLORMAP_COMMAND_NAME,
+                colormap.to_colormap().to_command_data(descriptor)?,
+            );
+        }
+
+        let BackupSettings {
+            default_layer,
+            only_custom,
+            alive_interval,
+            led_brightness,
+            underglow_brightness,
+            idle_leds_timeout,
+            mouse_keys,
+            dual_functions,
+        } = &backup.settings;
+
+        let settings = [
+            (
+                settings::DEFAULT_LAYER_COMMAND_NAME,
+                default_layer.map(|layer| layer.to_string()),
+            ),
+            (
+                keymap::ONLY_CUSTOM_COMMAND_NAME,
+                only_custom.map(|only_custom| u8::from(only_custom).to_string()),
+            ),
+            (
+                settings::ALIVE_INTERVAL_COMMAND_NAME,
+                alive_interval.map(|interval| interval.to_string()),
+            ),
+            (
+                leds::BRIGHTNESS_COMMAND_NAME,
+                led_brightness.map(|brightness| brightness.to_string()),
+            ),
+            (
+                leds::UNDERGLOW_BRIGHTNESS_COMMAND_NAME,
+                underglow_brightness.map(|brightness| brightness.to_string()),
+            ),
+            (
+                leds::IDLE_TIMEOUT_COMMAND_NAME,
+                idle_leds_timeout.map(|timeout| timeout.to_string()),
+            ),
+        ];
+
+        for (command, value) in settings {
+            if let Some(value) = value {
+                bazecor.push(command, value);
+            }
+        }
+
+        if let Some(settings) = mouse_keys {
+            bazecor.push(mouse::SPEED_COMMAND_NAME, settings.speed);
+            bazecor.push(mouse::SPEED_DELAY_COMMAND_NAME, settings.speed_delay);
+            bazecor.push(mouse::ACCEL_SPEED_COMMAND_NAME, settings.accel_speed);
+            bazecor.push(mouse::ACCEL_DELAY_COMMAND_NAME, settings.accel_delay);
+            bazecor.push(mouse::WHEEL_SPEED_COMMAND_NAME, settings.wheel_speed);
+            bazecor.push(mouse::WHEEL_DELAY_COMMAND_NAME, settings.wheel_delay);
+            bazecor.push(mouse::SPEED_LIMIT_COMMAND_NAME, settings.speed_limit);
+        }
+
+        if let Some(settings) = dual_functions {
+            bazecor.push(qukeys::HOLD_TIMEOUT_COMMAND_NAME, settings.hold_timeout);
+            bazecor.push(
+                qukeys::OVERLAP_THRESHOLD_COMMAND_NAME,
+                settings.overlap_threshold,
+            );
+        }
+
+        Ok(bazecor)
+    }
+
+    /// Parses the data of a setting, if the backup holds it.
+    fn setting<T>(
+        &self,
+        command: &'static str,
+        expected: &'static str,
+    ) -> Result<Option<T>, ParseSettingError>
+    where
+        T: FromStr,
+    {
+        self.get(command)
+            .map(|value| parse_setting(command, value.to_owned(), expected))
+            .transpose()
+    }
+
+    /// Parses the data of a boolean setting, if the backup holds it.
+    fn bool_setting(&self, command: &'static str) -> Result<Option<bool>, ParseSettingError> {
+        self.get(command)
+            .map(|value| parse_bool_setting(command, value.to_owned()))
+            .transpose()
+    }
+
+    /// Parses the superkey timings, if the backup holds all of them.
+    fn superkey_settings(&self) -> Result<Option<SuperkeySettings>, ParseSettingError> {
+        let duration = "duration";
+
+        let settings = match (
+            self.setting(superkeys::WAIT_TIME_COMMAND_NAME, duration)?,
+            self.setting(superkeys::TIMEOUT_COMMAND_NAME, duration)?,
+            self.setting(superkeys::REPEAT_COMMAND_NAME, duration)?,
+            self.setting(superkeys::HOLD_START_COMMAND_NAME, duration)?,
+            self.setting(superkeys::OVERLAP_COMMAND_NAME, "percentage")?,
+        ) {
+            (Some(wait_time), Some(timeout), Some(repeat), Some(hold_start), Some(overlap)) => {
+                Some(SuperkeySettings {
+                    wait_time,
+                    timeout,
+                    repeat,
+                    hold_start,
+                    overlap,
+                })
+            }
+            _ => None,
+        };
+
+        Ok(settings)
+    }
+
+    /// Parses the mouse key settings, if the backup holds all of them.
+    fn mouse_key_settings(&self) -> Result<Option<MouseKeySettings>, ParseSettingError> {
+        let (speed, delay) = ("speed", "delay");
+
+        let settings = match (
+            self.setting(mouse::SPEED_COMMAND_NAME, speed)?,
+            self.setting(mouse::SPEED_DELAY_COMMAND_NAME, delay)?,
+            self.setting(mouse::ACCEL_SPEED_COMMAND_NAME, speed)?,
+            self.setting(mouse::ACCEL_DELAY_COMMAND_NAME, delay)?,
+            self.setting(mouse::WHEEL_SPEED_COMMAND_NAME, speed)?,
+            self.setting(mouse::WHEEL_DELAY_COMMAND_NAME, delay)?,
+            self.setting(mouse::SPEED_LIMIT_COMMAND_NAME, speed)?,
+        ) {
+            (
+                Some(speed),
+                Some(speed_delay),
+                Some(accel_speed),
+                Some(accel_delay),
+                Some(wheel_speed),
+                Some(wheel_delay),
+                Some(speed_limit),
+            ) => Some(MouseKeySettings {
+                speed,
+                speed_delay,
+                accel_speed,
+                accel_delay,
+                wheel_speed,
+                wheel_delay,
+                speed_limit,
+            }),
+            _ => None,
+        };
+
+        Ok(settings)
+    }
+
+    /// Parses the dual-function key settings, if the backup holds all of
+    /// them.
+    fn dual_function_settings(&self) -> Result<Option<DualFunctionSettings>, ParseSettingError> {
+        let settings = match (
+            self.setting(qukeys::HOLD_TIMEOUT_COMMAND_NAME, "duration")?,
+            self.setting(qukeys::OVERLAP_THRESHOLD_COMMAND_NAME, "percentage")?,
+        ) {
+            (Some(hold_timeout), Some(overlap_threshold)) => Some(DualFunctionSettings {
+                hold_timeout,
+                overlap_threshold,
+            }),
+            _ => None,
+        };
+
+        Ok(settings)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::devices::{AnyKeyboard, Keyboard};
+
+    #[tokio::test]
+    async fn backups_round_trip_through_bazecor() {
+        for model in Model::ALL {
+            let mut backup = AnyKeyboard::new_virtual(model).get_backup().await.unwrap();
+
+            backup.device.chip_id = Some("abc123".to_owned());
+
+            let bazecor = BazecorBackup::from_device_backup(&backup).unwrap();
+            let json = serde_json::to_string(&bazecor).unwrap();
+
+            assert!(json.contains(r#""neuronID":"abc123""#));
+
+            let imported = serde_json::from_str::<BazecorBackup>(&json).unwrap();
+
+            assert_eq!(imported.detect_model().unwrap(), model);
+
+            let imported = imported.to_device_backup(model).unwrap();
+
+            assert_eq!(imported.device.chip_id, backup.device.chip_id);
+
+            // The layout variant isn't part of the backup, so Raise keymaps
+            // come back as ISO, with the same command data
+            let keymap_data = |backup: &DeviceBackup| {
+                backup
+                    .keymap
+                    .as_ref()
+                    .unwrap()
+                    .to_keymap_custom_data()
+                    .unwrap()
+            };
+
+            assert_eq!(keymap_data(&imported), keymap_data(&backup));
+            assert_eq!(
+                DeviceBackup {
+                    device: backup.device.clone(),
+                    keymap: backup.keymap.clone(),
+                    ..imported
+                },
+                backup
+            );
+        }
+    }
+
+    #[test]
+    fn partial_backups_are_imported() {
+        let json = r#"{
+            "neuronID": "",
+            "neuron": { "name": "My Defy" },
+            "backup": [
+                { "command": "settings.defaultLayer", "data": "3\r\n" },
+                { "command": "qukeys.holdTimeout", "data": "200" }
+            ]
+        }"#;
+
+        let backup = serde_json::from_str::<BazecorBackup>(json).unwrap();
+
+        assert!(matches!(
+            backup.detect_model(),
+            Err(DetectModelError::Ambiguous { .. })
+        ));
+
+        let backup = backup.to_device_backup(Model::Defy).unwrap();
+
+        assert_eq!(backup.device.chip_id, None);
+        assert_eq!(backup.keymap, None);
+        assert_eq!(backup.settings.default_layer, Some(3));
+        assert_eq!(backup.settings.dual_functions, None);
+    }
+
+    #[test]
+    fn layout_files_are_rejected() {
+        let layout = serde_json::json!({ "keymap": [[41, 30, 31]], "palette": [] });
+
+        assert!(matches!(
+            BazecorBackup::from_json_value(layout),
+            Err(ReadBazecorBackupError::LayoutFile)
+        ));
+        assert!(matches!(
+            BazecorBackup::from_json_value(serde_json::json!({ "neuronID": "" })),
+            Err(ReadBazecorBackupError::Json(_))
+        ));
+    }
+}
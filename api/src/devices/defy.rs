@@ -1,14 +1,22 @@
 //! Provides the [`DefyKeyboard`] struct for programatically interacting with
 //! the keyboard.
 
+use super::{
+    AnyKeymap, ApplyKeymapError, DynFocusApi, GetKeymapError, Keyboard, Model, ModelDescriptor,
+    ModelLayout, keymap,
+    lighting::{Colormap, ColormapLayer},
+    macros, superkeys, virtual_default_keymap, with_virtual_commands,
+};
 use crate::{
     focus_api::{
-        CreateHidFoducApiError, FocusApiConnection, HidFocusApi, RunCommandError,
+        Capabilities, ConnectionEvent, CreateHidFoducApiError, DeviceSelector, FocusApiConnection,
+        GetCapabilitiesError, HidFocusApi, MockFocusApi, RequireCommandError, RunCommandError,
         SerialPortFocusApi, parsing,
     },
     keycode_tables::KeyKind,
 };
 pub use error::*;
+use itertools::Itertools;
 use std::{array, str::FromStr};
 
 mod error {
@@ -29,6 +37,11 @@ mod error {
     #[display("failed to parse superkeys map: {_0}")]
     pub struct ParseSuperkeyMapError(parsing::superkeys::ParseSuperkeyMapError);
 
+    /// Error when parsing a macro map from a string slice.
+    #[derive(Clone, Debug, Display, From, Error)]
+    #[display("failed to parse macro map: {_0}")]
+    pub struct ParseMacroMapError(parsing::macros::ParseMacroMapError);
+
     /// Error returned when there are not exactly 10 layers in a [`DefyKeymap`] necessary for
     /// creating the command data.
     #[derive(Clone, Copy, Debug, Display, Error)]
@@ -64,6 +77,10 @@ mod error {
         CommandFailed(RunCommandError),
         /// Keymap returned by the keyboard failed to parse.
         KeymapParsingFailure(ParseSuperkeyMapError),
+        /// The firmware does not support the command, or its capabilities
+        /// could not be determined.
+        #[display("{_0}")]
+        Unsupported(RequireCommandError),
     }
 
     /// Error returned from [`DefyKeyboard::apply_superkeys`].
@@ -75,6 +92,39 @@ mod error {
         /// Command failed to run.
         #[display("{_0}")]
         CommandFailed(RunCommandError),
+        /// The firmware does not support the command, or its capabilities
+        /// could not be determined.
+        #[display("{_0}")]
+        Unsupported(RequireCommandError),
+    }
+
+    /// Error returned from [`DefyKeyboard::get_macros`].
+    #[derive(Debug, Display, From, Error)]
+    pub enum GetMacroMapError {
+        /// Failed to run command.
+        #[display("{_0}")]
+        CommandFailed(RunCommandError),
+        /// Macro map returned by the keyboard failed to parse.
+        MacroMapParsingFailure(ParseMacroMapError),
+        /// The firmware does not support the command, or its capabilities
+        /// could not be determined.
+        #[display("{_0}")]
+        Unsupported(RequireCommandError),
+    }
+
+    /// Error returned from [`DefyKeyboard::apply_macros`].
+    #[derive(Debug, Display, From, Error)]
+    pub enum ApplyMacrosError {
+        /// The macros could not be converted into command data.
+        #[display("{_0}")]
+        InvalidMacros(parsing::macros::MacroCommandDataError),
+        /// Command failed to run.
+        #[display("{_0}")]
+        CommandFailed(RunCommandError),
+        /// The firmware does not support the command, or its capabilities
+        /// could not be determined.
+        #[display("{_0}")]
+        Unsupported(RequireCommandError),
     }
 
     /// Possible errors when clearing a [`DefyKeymap`] layer.
@@ -98,6 +148,10 @@ pub const KEYS_PER_LAYER: usize = 80;
 /// Number of layers in a `keymap.custom` command.
 pub const KEYMAP_CUSTOM_COMMAND_LAYERS: usize = 10;
 
+/// Number of LEDs per layer in a `colormap.map` command, starting with the
+/// LEDs under the keys, followed by the underglow.
+pub const LEDS_PER_LAYER: usize = 176;
+
 /// Constant providing the Defy keymap layout.
 pub const LAYOUT: &DefyLayout = &DefyLayout {
     left: DefyLayoutHalf {
@@ -123,9 +177,21 @@ pub const LAYOUT: &DefyLayout = &DefyLayout {
 };
 
 /// A handle to the Dygma Defy keyboard, allowing for programatic control.
-#[derive(Debug, Deref, DerefMut, From)]
+#[derive(Debug, Deref, DerefMut)]
 pub struct DefyKeyboard {
+    #[deref]
+    #[deref_mut]
     focus_api: DynFocusApi,
+    capabilities: Option<Capabilities>,
+}
+
+impl From<DynFocusApi> for DefyKeyboard {
+    fn from(focus_api: DynFocusApi) -> Self {
+        Self {
+            focus_api,
+            capabilities: None,
+        }
+    }
 }
 
 impl DefyKeyboard {
@@ -133,27 +199,79 @@ impl DefyKeyboard {
     const PRODUCT_NAME: &str = "DEFY";
     const BAUD_RATE: u32 = 115_200;
 
-    const KEYMAP_CUSTOM_COMMAND_NAME: &str = "keymap.custom";
-    const SUPERKEY_MAP_COMMAND_NAME: &str = "superkeys.map";
-
     /// The memory size of the superkey map.
     pub const SUPERKEY_MEMORY_SIZE: usize = 512;
 
+    /// The memory size of the macro map, in bytes.
+    pub const MACRO_MEMORY_SIZE: usize = 2048;
+
+    /// Static description of the Defy.
+    pub const DESCRIPTOR: ModelDescriptor = ModelDescriptor {
+        model: Model::Defy,
+        name: "Defy",
+        product_ids: &[0x0010, Self::HID_PRODUCT_ID],
+        wireless: true,
+        layout: ModelLayout::Defy(LAYOUT),
+        keys_per_layer: KEYS_PER_LAYER,
+        layers: KEYMAP_CUSTOM_COMMAND_LAYERS,
+        leds_per_layer: LEDS_PER_LAYER,
+        rgbw: true,
+        superkey_memory_size: Self::SUPERKEY_MEMORY_SIZE,
+        macro_memory_size: Self::MACRO_MEMORY_SIZE,
+    };
+
     /// Creates a handle to the keyboard.
     pub async fn new() -> Result<Self, CreateDefyKeyboardError> {
-        let sp_focus_api_res = SerialPortFocusApi::new(Self::PRODUCT_NAME, Self::BAUD_RATE)
-            .await
-            .map(Into::into);
-        let bt_focus_api_res = HidFocusApi::new(Self::HID_PRODUCT_ID).await.map(Into::into);
+        Self::with_selector(&DeviceSelector::Any).await
+    }
 
-        let focus_api = sp_focus_api_res.or(bt_focus_api_res)?;
+    /// Creates a handle to the keyboard chosen by the selector.
+    ///
+    /// Serial port connections are tried first, falling back to HID.
+    pub async fn with_selector(selector: &DeviceSelector) -> Result<Self, CreateDefyKeyboardError> {
+        let sp_focus_api_res =
+            SerialPortFocusApi::with_selector(Self::PRODUCT_NAME, Self::BAUD_RATE, selector).await;
 
-        Ok(Self { focus_api })
+        let focus_api: DynFocusApi = match sp_focus_api_res {
+            Ok(sp) => sp.into(),
+            Err(err) => {
+                let hid = HidFocusApi::with_selector(Self::HID_PRODUCT_ID, selector).await?;
+
+                hid.emit_event(ConnectionEvent::FellBackToHid {
+                    reason: err.to_string(),
+                });
+
+                hid.into()
+            }
+        };
+
+        Ok(Self::from(focus_api))
+    }
+
+    /// Creates a handle to a virtual keyboard emulated entirely in memory.
+    ///
+    /// The virtual keyboard starts with a blank keymap, and no superkeys
+    /// or macros, and is useful for testing without a physical keyboard.
+    pub fn new_virtual() -> Self {
+        let keymap = [0; KEYS_PER_LAYER * KEYMAP_CUSTOM_COMMAND_LAYERS]
+            .iter()
+            .join(" ");
+        let default_keymap = virtual_default_keymap(KEYS_PER_LAYER);
+        let superkeys = [u16::MAX; Self::SUPERKEY_MEMORY_SIZE].iter().join(" ");
+        let macros = [u8::MAX; Self::MACRO_MEMORY_SIZE].iter().join(" ");
+
+        let mock = with_virtual_commands(MockFocusApi::new(), &Self::DESCRIPTOR)
+            .with_command(keymap::CUSTOM_COMMAND_NAME, keymap)
+            .with_read_only_command(keymap::DEFAULT_COMMAND_NAME, default_keymap)
+            .with_command(superkeys::MAP_COMMAND_NAME, superkeys)
+            .with_command(macros::MAP_COMMAND_NAME, macros);
+
+        Self::from(DynFocusApi::from(mock))
     }
 
     /// Get the custom keymap from the keyboard.
     pub async fn get_custom_keymap(&mut self) -> Result<DefyKeymap, GetCustomKeymapError> {
-        self.run_command(Self::KEYMAP_CUSTOM_COMMAND_NAME, None)
+        self.run_command(keymap::CUSTOM_COMMAND_NAME, None)
             .await?
             .parse()
             .map_err(Into::into)
@@ -166,57 +284,52 @@ impl DefyKeyboard {
     ) -> Result<(), ApplyCustomKeymapError> {
         let data = keymap.to_keymap_custom_data()?;
 
-        self.run_command(Self::KEYMAP_CUSTOM_COMMAND_NAME, Some(&data))
+        self.run_command(keymap::CUSTOM_COMMAND_NAME, Some(&data))
             .await?;
 
         Ok(())
     }
+}
 
-    /// Apply the superkeys map to the keyboard.
-    pub async fn apply_superkeys(
-        &mut self,
-        superkeys: &SuperkeyMap,
-    ) -> Result<(), ApplySuperkeyError> {
-        let data = parsing::superkeys::SuperkeyMap::from(superkeys)
-            .to_command_data::<{ Self::SUPERKEY_MEMORY_SIZE }>()?;
+impl Keyboard for DefyKeyboard {
+    fn descriptor(&self) -> &'static ModelDescriptor {
+        &Self::DESCRIPTOR
+    }
 
-        self.run_command(Self::SUPERKEY_MAP_COMMAND_NAME, Some(&data))
-            .await?;
+    fn focus_api(&mut self) -> &mut DynFocusApi {
+        self
+    }
 
-        Ok(())
+    async fn capabilities(&mut self) -> Result<&Capabilities, GetCapabilitiesError> {
+        if self.capabilities.is_none() {
+            self.capabilities = Some(self.focus_api.capabilities().await?);
+        }
+
+        Ok(self.capabilities.as_ref().unwrap())
+    }
+
+    async fn get_keymap(&mut self) -> Result<AnyKeymap, GetKeymapError> {
+        Ok(self.get_custom_keymap().await?.into())
     }
 
-    /// Get the keyperkey map from the keyboard.
-    pub async fn get_superkeys(&mut self) -> Result<SuperkeyMap, GetSuperkeyMapError> {
-        let map = self
-            .run_command(Self::SUPERKEY_MAP_COMMAND_NAME, None)
+    async fn get_default_keymap(&mut self) -> Result<AnyKeymap, GetKeymapError> {
+        self.require_command(keymap::DEFAULT_COMMAND_NAME).await?;
+
+        let keymap = self
+            .run_command(keymap::DEFAULT_COMMAND_NAME, None)
             .await?
-            .parse::<SuperkeyMap>()?;
+            .parse::<DefyKeymap>()?;
 
-        Ok(map)
+        Ok(keymap.into())
     }
-}
-
-/// Static dispatch for focus API connections.
-#[derive(Debug, From)]
-pub enum DynFocusApi {
-    /// Connections to the device over serial port,
-    /// which includes both wired over USB, as well as
-    /// wireless over RF.
-    Serial(SerialPortFocusApi),
-    /// Connections to the device over BTLE.
-    Bluetooth(HidFocusApi),
-}
 
-impl FocusApiConnection for DynFocusApi {
-    async fn run_command(
-        &mut self,
-        command: &str,
-        data: Option<&str>,
-    ) -> Result<String, crate::focus_api::RunCommandError> {
-        match self {
-            Self::Serial(sp) => sp.run_command(command, data).await,
-            Self::Bluetooth(hid) => hid.run_command(command, data).await,
+    async fn apply_keymap(&mut self, keymap: &AnyKeymap) -> Result<(), ApplyKeymapError> {
+        match keymap {
+            AnyKeymap::Defy(keymap) => Ok(self.apply_custom_keymap(keymap).await?),
+            keymap => Err(ApplyKeymapError::WrongModel {
+                keymap: keymap.family(),
+                keyboard: Self::DESCRIPTOR.name,
+            }),
         }
     }
 }
@@ -329,6 +442,12 @@ impl DefyKeymap {
 
         Ok(())
     }
+
+    /// Drops or adds layers at the end of the keymap, so that it has the
+    /// provided number of layers, with new layers cleared to the key.
+    pub fn resize_to(&mut self, layers: usize, key: KeyKind) {
+        self.resize(layers, DefyKeymapLayer::new_cleared_to(key));
+    }
 }
 
 /// A single human-readable Defy layer.
@@ -674,16 +793,379 @@ impl From<Superkey> for parsing::superkeys::Superkey {
     }
 }
 
+/// Holds a human-readable macro map.
+#[derive(Clone, Debug, Default, Deref, DerefMut, PartialEq, Eq, Deserialize)]
+pub struct DefyMacroMap(pub Vec<DefyMacro>);
+
+impl serde::Serialize for DefyMacroMap {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: serde::Serializer,
+    {
+        self.0
+            .iter()
+            .enumerate()
+            .map(|(i, macro_)| DefyMacro {
+                macro_number: DefyMacro::number(i),
+                actions: macro_.actions.clone(),
+            })
+            .collect::<Vec<_>>()
+            .serialize(serializer)
+    }
+}
+
+impl FromStr for DefyMacroMap {
+    type Err = ParseMacroMapError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let map = s.parse::<parsing::macros::MacroMap>()?;
+
+        Ok(Self::from(&map))
+    }
+}
+
+impl From<&parsing::macros::MacroMap> for DefyMacroMap {
+    fn from(map: &parsing::macros::MacroMap) -> Self {
+        let macros = map
+            .macros
+            .iter()
+            .enumerate()
+            .map(|(i, macro_)| DefyMacro {
+                macro_number: DefyMacro::number(i),
+                actions: macro_.actions.iter().cloned().map(Into::into).collect(),
+            })
+            .collect();
+
+        Self(macros)
+    }
+}
+
+impl From<&DefyMacroMap> for parsing::macros::MacroMap {
+    fn from(map: &DefyMacroMap) -> Self {
+        let macros = map
+            .0
+            .iter()
+            .map(|macro_| parsing::macros::Macro {
+                actions: macro_.actions.iter().cloned().map(Into::into).collect(),
+            })
+            .collect::<Vec<_>>();
+
+        Self::from(macros)
+    }
+}
+
+/// Represents a single macro.
+#[derive(Clone, Debug, Default, Eq, Serialize, Deserialize)]
+pub struct DefyMacro {
+    /// User-facing ID used to make reading macro map arrays easier.
+    ///
+    /// **Note**: This is purely for UX. When deserializing, the ID the user
+    /// writes is entirely ignored. Use this number to know which
+    /// `Macro N` [`KeyKind`] triggers this particular macro.
+    #[serde(skip_deserializing)]
+    pub macro_number: u16,
+    /// The actions performed, in order, when the macro is triggered.
+    pub actions: Vec<DefyMacroAction>,
+}
+
+impl DefyMacro {
+    /// The [`DefyMacro::macro_number`] of the macro at the given index,
+    /// saturating for maps too long to ever fit on a keyboard.
+    fn number(index: usize) -> u16 {
+        u16::try_from(index + 1).unwrap_or(u16::MAX)
+    }
+}
+
+impl PartialEq for DefyMacro {
+    fn eq(&self, other: &Self) -> bool {
+        self.actions == other.actions
+    }
+}
+
+/// A human-readable macro action.
+///
+/// In JSON, each action is an object with a single key naming the action,
+/// such as `{ "press": "A" }` or `{ "delay": 200 }`.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum DefyMacroAction {
+    /// Delay in ms.
+    Delay(u16),
+    /// Applies a random delay between the min and max range, in ms.
+    RandomDelay {
+        /// The minimum delay amount in ms.
+        min: u16,
+        /// The maximum delay amount in ms.
+        max: u16,
+    },
+    /// Presses and releases the key.
+    ///
+    /// Keys which fit in a single byte are sent as a key code press, all other
+    /// keys are sent as a [`parsing::macros::MacroAction::Special`] press.
+    Press(KeyKind),
+    /// Holds down the key.
+    ///
+    /// Keys which fit in a single byte are sent as a key code key down, all
+    /// other keys are sent as a [`parsing::macros::MacroAction::SpecialKeyDown`].
+    KeyDown(KeyKind),
+    /// Releases the held key.
+    ///
+    /// Keys which fit in a single byte are sent as a key code key up, all
+    /// other keys are sent as a [`parsing::macros::MacroAction::SpecialKeyUp`].
+    KeyUp(KeyKind),
+    /// An action type this crate doesn't know about, kept as raw bytes.
+    ///
+    /// See [`parsing::macros::MacroAction::Unknown`].
+    Unknown {
+        /// The raw action type.
+        kind: u8,
+        /// The bytes following the action type, up to the end of the macro.
+        data: Vec<u8>,
+    },
+}
+
+impl From<parsing::macros::MacroAction> for DefyMacroAction {
+    fn from(action: parsing::macros::MacroAction) -> Self {
+        use parsing::macros::MacroAction;
+
+        match action {
+            MacroAction::Delay(delay) => Self::Delay(delay),
+            MacroAction::RandomDelay { min, max } => Self::RandomDelay { min, max },
+            MacroAction::Special(key) | MacroAction::Press(key) => Self::Press(key),
+            MacroAction::SpecialKeyDown(key) | MacroAction::KeyDown(key) => Self::KeyDown(key),
+            MacroAction::SpecialKeyUp(key) | MacroAction::KeyUp(key) => Self::KeyUp(key),
+            MacroAction::Unknown { kind, data } => Self::Unknown { kind, data },
+        }
+    }
+}
+
+impl From<DefyMacroAction> for parsing::macros::MacroAction {
+    fn from(action: DefyMacroAction) -> Self {
+        match action {
+            DefyMacroAction::Delay(delay) => Self::Delay(delay),
+            DefyMacroAction::RandomDelay { min, max } => Self::RandomDelay { min, max },
+            DefyMacroAction::Press(key) if u16::from(key) > u8::MAX as u16 => Self::Special(key),
+            DefyMacroAction::Press(key) => Self::Press(key),
+            DefyMacroAction::KeyDown(key) if u16::from(key) > u8::MAX as u16 => {
+                Self::SpecialKeyDown(key)
+            }
+            DefyMacroAction::KeyDown(key) => Self::KeyDown(key),
+            DefyMacroAction::KeyUp(key) if u16::from(key) > u8::MAX as u16 => {
+                Self::SpecialKeyUp(key)
+            }
+            DefyMacroAction::KeyUp(key) => Self::KeyUp(key),
+            DefyMacroAction::Unknown { kind, data } => Self::Unknown { kind, data },
+        }
+    }
+}
+
+/// Full Defy colormap, following the keyboard's physical layout.
+#[derive(Clone, Debug, PartialEq, Eq, Hash, From, Deref, DerefMut, Deserialize)]
+pub struct DefyColormap(pub Vec<DefyColormapLayer>);
+
+impl From<&Colormap> for DefyColormap {
+    fn from(colormap: &Colormap) -> Self {
+        Self(colormap.iter().map(DefyColormapLayer::from).collect())
+    }
+}
+
+impl serde::Serialize for DefyColormap {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: serde::Serializer,
+    {
+        self.0
+            .iter()
+            .cloned()
+            .enumerate()
+            .map(|(i, layer)| DefyColormapLayer {
+                layer_number: i as u8 + 1,
+                ..layer
+            })
+            .collect::<Vec<_>>()
+            .serialize(serializer)
+    }
+}
+
+impl DefyColormap {
+    /// Converts the colormap into the order the firmware uses.
+    pub fn to_colormap(&self) -> Colormap {
+        Colormap(
+            self.iter()
+                .map(DefyColormapLayer::to_colormap_layer)
+                .collect(),
+        )
+    }
+}
+
+/// The palette index of each LED of a single Defy layer.
+///
+/// The LEDs under the keys follow the same [`LAYOUT`] as keymaps, while the
+/// remaining LEDs are listed in the order the firmware uses.
+#[derive(Clone, Debug, Eq, Serialize, Deserialize)]
+pub struct DefyColormapLayer {
+    /// A human-readable label for knowing what layer your editing in the
+    /// JSON file.
+    ///
+    /// **Note**: This number is only a UX aid, and is not taken
+    /// into account when deserializing from the config file.
+    #[serde(skip_deserializing)]
+    pub layer_number: u8,
+    /// LEDs under the keys of the left half.
+    pub left: DefyColormapHalf,
+    /// LEDs under the keys of the right half.
+    pub right: DefyColormapHalf,
+    /// Slots before the underglow which aren't part of the [`LAYOUT`], kept
+    /// so that they are written back unchanged.
+    #[serde(default)]
+    pub unmapped: Vec<u8>,
+    /// Underglow LEDs of both halves.
+    pub underglow: Vec<u8>,
+}
+
+impl From<&ColormapLayer> for DefyColormapLayer {
+    fn from(layer: &ColormapLayer) -> Self {
+        Self {
+            layer_number: 0,
+            left: DefyColormapHalf::from_leds(&LAYOUT.left, layer),
+            right: DefyColormapHalf::from_leds(&LAYOUT.right, layer),
+            unmapped: unmapped_slots()
+                .map(|index| layer.get(index).copied().unwrap_or_default())
+                .collect(),
+            underglow: layer.iter().skip(KEYS_PER_LAYER).copied().collect(),
+        }
+    }
+}
+
+impl PartialEq for DefyColormapLayer {
+    fn eq(&self, other: &Self) -> bool {
+        self.left == other.left
+            && self.right == other.right
+            && self.unmapped == other.unmapped
+            && self.underglow == other.underglow
+    }
+}
+
+impl std::hash::Hash for DefyColormapLayer {
+    fn hash<H: std::hash::Hasher>(&self, state: &mut H) {
+        self.left.hash(state);
+        self.right.hash(state);
+        self.unmapped.hash(state);
+        self.underglow.hash(state);
+    }
+}
+
+impl DefyColormapLayer {
+    /// Converts the layer into the order the firmware uses.
+    ///
+    /// Slots missing from [`DefyColormapLayer::unmapped`] use the first
+    /// palette colour.
+    pub fn to_colormap_layer(&self) -> ColormapLayer {
+        let mut leds = vec![0; KEYS_PER_LAYER];
+
+        for (index, value) in unmapped_slots().zip(&self.unmapped) {
+            leds[index] = *value;
+        }
+
+        self.left.write_leds(&LAYOUT.left, &mut leds);
+        self.right.write_leds(&LAYOUT.right, &mut leds);
+
+        leds.extend(&self.underglow);
+
+        ColormapLayer(leds)
+    }
+}
+
+/// The colormap slots before the underglow which no key of the [`LAYOUT`]
+/// uses, in the order the firmware uses.
+fn unmapped_slots() -> impl Iterator<Item = usize> {
+    let mut mapped = [false; KEYS_PER_LAYER];
+
+    for half in [&LAYOUT.left, &LAYOUT.right] {
+        let rows = [
+            &half.row_1[..],
+            &half.row_2,
+            &half.row_3,
+            &half.row_4,
+            &half.thumb_cluster.top,
+            &half.thumb_cluster.bottom,
+        ];
+
+        for index in rows.into_iter().flatten() {
+            mapped[*index as usize] = true;
+        }
+    }
+
+    (0..KEYS_PER_LAYER).filter(move |index| !mapped[*index])
+}
+
+/// The palette index of the LED under each key of one half of the Defy.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct DefyColormapHalf {
+    /// Row 1.
+    pub row_1: [u8; 7],
+    /// Row 2.
+    pub row_2: [u8; 7],
+    /// Row 3.
+    pub row_3: [u8; 7],
+    /// Row 4.
+    pub row_4: [u8; 6],
+    /// Thumb cluster.
+    pub thumb_cluster: DefyThumbClusterColormap,
+}
+
+/// The palette index of the LED under each key of a Defy thumb cluster.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct DefyThumbClusterColormap {
+    /// The top four keys of the thumb cluster, from left to right.
+    pub top: [u8; 4],
+    /// The bottom four keys of the thumb cluster, from left to right.
+    pub bottom: [u8; 4],
+}
+
+impl DefyColormapHalf {
+    fn from_leds(layout: &DefyLayoutHalf, leds: &[u8]) -> Self {
+        let led = |index: u8| leds.get(index as usize).copied().unwrap_or_default();
+
+        Self {
+            row_1: layout.row_1.map(led),
+            row_2: layout.row_2.map(led),
+            row_3: layout.row_3.map(led),
+            row_4: layout.row_4.map(led),
+            thumb_cluster: DefyThumbClusterColormap {
+                top: layout.thumb_cluster.top.map(led),
+                bottom: layout.thumb_cluster.bottom.map(led),
+            },
+        }
+    }
+
+    fn write_leds(&self, layout: &DefyLayoutHalf, leds: &mut [u8]) {
+        let rows = [
+            (&layout.row_1[..], &self.row_1[..]),
+            (&layout.row_2, &self.row_2),
+            (&layout.row_3, &self.row_3),
+            (&layout.row_4, &self.row_4),
+            (&layout.thumb_cluster.top, &self.thumb_cluster.top),
+            (&layout.thumb_cluster.bottom, &self.thumb_cluster.bottom),
+        ];
+
+        for (indices, values) in rows {
+            for (index, value) in indices.iter().zip(values) {
+                leds[*index as usize] = *value;
+            }
+        }
+    }
+}
+
 #[cfg(test)]
 mod tests {
-    use itertools::Itertools;
-
     use crate::keycode_tables::Blank;
 
     use super::*;
 
     const KEYMAP_DATA: &str = "41 30 31 32 33 34 0 0 0 0 35 36 37 38 39 0 43 20 26 8 21 23 0 0 0 0 28 24 12 18 19 0 57 4 22 7 9 10 17152 0 0 0 11 13 14 15 51 52 53980 29 27 6 25 5 0 0 0 0 17 16 54 55 56 0 53853 17452 44 49467 49209 226 227 0 0 231 76 49209 52028 44 49162 230 41 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 43 85 95 96 97 87 0 0 0 0 75 74 82 77 0 0 0 84 92 93 94 86 83 0 0 0 78 80 81 79 70 0 0 46 89 90 91 99 0 0 0 0 0 0 0 0 0 0 0 0 98 65535 65535 65535 0 0 0 0 0 65535 65535 65535 65535 0 0 58 59 60 61 62 63 65535 65535 64 65 66 67 68 69 0 0 0 0 22710 22709 23785 0 65535 65535 0 0 23663 0 0 65535 0 0 0 22713 22711 22733 23785 0 65535 65535 0 0 23664 20866 20865 0 0 0 0 0 0 0 19682 65535 65535 65535 65535 0 0 0 0 0 0 0 65535 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 65535 65535 0 0 0 0 0 0 0 0 53 2079 2080 2081 2101 0 65535 65535 0 2083 2095 2096 2093 2094 0 0 2078 56 2102 2103 2082 0 65535 65535 0 2084 2086 2087 45 46 0 0 0 0 49 2097 0 65535 65535 65535 65535 0 47 48 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 65535 0 0 0 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 ";
     const SUPERKEY_DATA: &str = "262 281 1 1 1 0 0 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 65535 ";
+    const MACRO_DATA: &str = "8 44 6 225 8 11 7 225 8 8 8 28 8 54 8 44 2 3 232 6 225 8 7 7 225 8 28 8 10 8 16 8 4 8 23 8 8 5 68 43 5 68 44 5 68 86 5 210 93 5 67 2 5 80 65 0 6 225 8 23 7 225 8 11 8 12 8 22 8 44 8 12 8 22 8 44 8 4 8 44 8 23 8 8 8 22 8 23 8 55 0 0 23 8 8 8 7 8 44 8 15 8 12 8 22 8 23 8 40 0 6 227 8 80 7 227 8 84 8 5 8 4 8 17 8 17 8 8 8 21 8 44 8 28 8 8 8 15 8 15 8 18 8 26 8 40 0 6 227 8 80 7 227 8 84 8 11 8 32 8 40 6 227 8 79 7 227 0 6 227 8 80 7 227 8 84 8 11 8 33 8 40 6 227 8 79 7 227 0 6 227 8 80 7 227 8 84 8 6 8 11 8 8 8 6 8 14 8 15 8 12 8 22 8 23 8 40 0 6 227 8 80 7 227 8 84 8 5 8 4 8 17 8 17 8 8 8 21 8 44 8 10 8 21 8 8 8 8 8 17 8 40 6 227 8 79 7 227 0 6 227 8 80 7 227 8 84 8 5 8 4 8 17 8 17 8 8 8 21 8 44 8 21 8 8 8 7 8 40 6 227 8 79 7 227 0 6 227 8 80 7 227 8 84 8 5 8 4 8 17 8 17 8 8 8 21 8 44 8 5 8 15 8 24 8 8 8 40 6 227 8 79 7 227 0 8 84 8 10 8 12 8 19 8 11 8 28 2 0 200 8 40 0 0 8 10 8 21 8 8 8 8 8 17 8 40 0 8 84 8 5 8 4 8 17 8 17 8 8 8 21 8 44 8 21 8 8 8 7 8 40 0 0 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255 255";
 
     fn defy_keymap_layers<R>(layers: R) -> (Vec<u16>, DefyKeymap)
     where
@@ -755,4 +1237,62 @@ mod tests {
 
         assert_eq!(format!("{str_data} "), SUPERKEY_DATA);
     }
+
+    #[test]
+    fn macros_round_trip_through_json() {
+        let map = MACRO_DATA.parse::<DefyMacroMap>().unwrap();
+
+        let json = serde_json::to_string(&map).unwrap();
+
+        assert_eq!(serde_json::from_str::<DefyMacroMap>(&json).unwrap(), map);
+
+        let str_data = parsing::macros::MacroMap::from(&map)
+            .to_command_data::<{ DefyKeyboard::MACRO_MEMORY_SIZE }>()
+            .unwrap();
+
+        assert_eq!(str_data.parse::<DefyMacroMap>().unwrap(), map);
+    }
+
+    #[test]
+    fn long_macro_maps_are_numbered() {
+        let map = DefyMacroMap(vec![DefyMacro::default(); 300]);
+
+        let json = serde_json::to_value(&map).unwrap();
+
+        assert_eq!(json[299]["macro_number"], 300);
+    }
+
+    #[test]
+    fn macro_actions_use_tagged_json() {
+        let actions = serde_json::from_str::<Vec<DefyMacroAction>>(
+            r#"[{ "press": "A" }, { "delay": 200 }, { "random_delay": { "min": 10, "max": 20 } }]"#,
+        )
+        .unwrap();
+
+        assert_eq!(
+            actions,
+            [
+                DefyMacroAction::Press(KeyKind::from(4)),
+                DefyMacroAction::Delay(200),
+                DefyMacroAction::RandomDelay { min: 10, max: 20 },
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn virtual_keyboard_round_trips_keymap_superkeys_and_macros() {
+        let mut defy = DefyKeyboard::new_virtual();
+
+        let (_, keymap) = defy_keymap_layers(..);
+        let superkeys = SUPERKEY_DATA.parse::<SuperkeyMap>().unwrap();
+        let macros = MACRO_DATA.parse::<DefyMacroMap>().unwrap();
+
+        defy.apply_custom_keymap(&keymap).await.unwrap();
+        defy.apply_superkeys(&superkeys).await.unwrap();
+        defy.apply_macros(&macros).await.unwrap();
+
+        assert_eq!(defy.get_custom_keymap().await.unwrap(), keymap);
+        assert_eq!(defy.get_superkeys().await.unwrap(), superkeys);
+        assert_eq!(defy.get_macros().await.unwrap(), macros);
+    }
 }
@@ -0,0 +1,18 @@
+//! Provides the commands used for storing keymaps on the keyboard.
+//!
+//! The keymap types live in each model's module, and the methods themselves
+//! live on the [`Keyboard`](super::Keyboard) trait.
+
+/// Command used to get and set the custom keymap.
+pub const CUSTOM_COMMAND_NAME: &str = "keymap.custom";
+
+/// Command used to get the keymap the firmware ships with.
+pub const DEFAULT_COMMAND_NAME: &str = "keymap.default";
+
+/// Command used to get and set whether the keyboard only uses the custom
+/// keymap.
+pub const ONLY_CUSTOM_COMMAND_NAME: &str = "keymap.onlyCustom";
+
+/// Command used to get the physical layout, such as `ANSI` or `ISO`, which
+/// decides where the keys of the Raise family's keymaps are.
+pub const LAYOUT_COMMAND_NAME: &str = "hardware.layout";
@@ -0,0 +1,211 @@
+//! Provides the types used for switching between keymap layers at runtime,
+//! with the `layer.*` family of commands.
+//!
+//! The methods themselves live on the [`Keyboard`] trait.
+
+use super::{
+    Keyboard,
+    settings::{ParseSettingError, parse_bool_setting},
+};
+use crate::focus_api::{FocusApiConnection, MockFocusApi, RequireCommandError, RunCommandError};
+pub use error::*;
+
+mod error {
+    use super::*;
+
+    /// Error returned when a layer does not exist on the keyboard.
+    #[derive(Clone, Copy, Debug, Display, Error)]
+    #[display("layer {layer} does not exist, expected 0 to {}", layers - 1)]
+    pub struct LayerOutOfRangeError {
+        /// The requested layer, indexed from 0.
+        pub layer: u8,
+        /// How many layers the keyboard has.
+        pub layers: usize,
+    }
+
+    /// Error returned when activating or deactivating a layer.
+    #[derive(Debug, Display, From, Error)]
+    pub enum ChangeLayerError {
+        /// The firmware does not support switching layers.
+        #[display("{_0}")]
+        Unsupported(RequireCommandError),
+        /// The layer does not exist on the keyboard.
+        #[display("{_0}")]
+        OutOfRange(LayerOutOfRangeError),
+        /// Failed to run command.
+        #[display("{_0}")]
+        CommandFailed(RunCommandError),
+    }
+
+    /// Error returned when querying which layers are active.
+    #[derive(Debug, Display, From, Error)]
+    pub enum GetLayerStateError {
+        /// The firmware does not support querying layers.
+        #[display("{_0}")]
+        Unsupported(RequireCommandError),
+        /// The layer does not exist on the keyboard.
+        #[display("{_0}")]
+        OutOfRange(LayerOutOfRangeError),
+        /// Failed to run command.
+        #[display("{_0}")]
+        CommandFailed(RunCommandError),
+        /// The response returned by the keyboard failed to parse.
+        #[display("{_0}")]
+        ParsingFailure(ParseSettingError),
+    }
+
+    impl From<ChangeLayerError> for GetLayerStateError {
+        fn from(err: ChangeLayerError) -> Self {
+            match err {
+                ChangeLayerError::Unsupported(err) => Self::Unsupported(err),
+                ChangeLayerError::OutOfRange(err) => Self::OutOfRange(err),
+                ChangeLayerError::CommandFailed(err) => Self::CommandFailed(err),
+            }
+        }
+    }
+}
+
+/// Command used to activate a layer.
+pub const ACTIVATE_COMMAND_NAME: &str = "layer.activate";
+
+/// Command used to deactivate a layer.
+pub const DEACTIVATE_COMMAND_NAME: &str = "layer.deactivate";
+
+/// Command used to check whether a layer is active.
+pub const IS_ACTIVE_COMMAND_NAME: &str = "layer.isActive";
+
+/// Command used to get whether each layer is active.
+pub const STATE_COMMAND_NAME: &str = "layer.state";
+
+/// Which layers of the keyboard are active.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct LayerState(pub Vec<bool>);
+
+impl LayerState {
+    /// Checks whether the layer, indexed from 0, is active.
+    pub fn is_active(&self, layer: u8) -> bool {
+        self.0.get(layer as usize).copied().unwrap_or_default()
+    }
+
+    /// Gets the active layers, indexed from 0, in ascending order.
+    pub fn active_layers(&self) -> Vec<u8> {
+        self.0
+            .iter()
+            .enumerate()
+            .filter(|(_, active)| **active)
+            .map(|(layer, _)| layer as u8)
+            .collect()
+    }
+
+    /// Parses the response of the `layer.state` command, which lists
+    /// whether each layer is active as `1` or `0`.
+    pub(crate) fn parse(value: &str, layers: usize) -> Result<Self, ParseSettingError> {
+        let state = value
+            .split_whitespace()
+            .take(layers)
+            .map(|active| parse_bool_setting(STATE_COMMAND_NAME, active.to_owned()))
+            .collect::<Result<Vec<_>, _>>()?;
+
+        Ok(Self(state))
+    }
+}
+
+/// Checks that the layer exists on the keyboard.
+fn check_layer<K>(keyboard: &K, layer: u8) -> Result<(), LayerOutOfRangeError>
+where
+    K: Keyboard + ?Sized,
+{
+    let layers = keyboard.descriptor().layers;
+
+    if (layer as usize) < layers {
+        return Ok(());
+    }
+
+    Err(LayerOutOfRangeError { layer, layers })
+}
+
+/// Runs a command taking a layer as its argument, after checking that the
+/// layer exists and that the firmware supports the command.
+pub(crate) async fn run_layer_command<K>(
+    keyboard: &mut K,
+    command: &'static str,
+    layer: u8,
+) -> Result<String, ChangeLayerError>
+where
+    K: Keyboard + ?Sized,
+{
+    check_layer(keyboard, layer)?;
+
+    keyboard.require_command(command).await?;
+
+    let res = keyboard
+        .focus_api()
+        .run_command(command, Some(&layer.to_string()))
+        .await?;
+
+    Ok(res)
+}
+
+/// Adds the `layer.*` commands, emulated like the firmware does, to a
+/// virtual keyboard, starting with only the first layer active.
+pub(crate) fn with_virtual_layers(mock: MockFocusApi) -> MockFocusApi {
+    fn set_layer(mock: &mut MockFocusApi, layer: Option<&str>, active: bool) {
+        let Some(layer) = layer.and_then(|layer| layer.trim().parse::<usize>().ok()) else {
+            return;
+        };
+
+        let mut state = mock
+            .get(STATE_COMMAND_NAME)
+            .unwrap_or_default()
+            .split_whitespace()
+            .map(ToOwned::to_owned)
+            .collect::<Vec<_>>();
+
+        if let Some(value) = state.get_mut(layer) {
+            *value = if active { "1" } else { "0" }.to_owned();
+        }
+
+        mock.set(STATE_COMMAND_NAME, state.join(" "));
+    }
+
+    let state = std::iter::once("1")
+        .chain(std::iter::repeat_n("0", 31))
+        .collect::<Vec<_>>()
+        .join(" ");
+
+    mock.with_command(STATE_COMMAND_NAME, state)
+        .with_handler(ACTIVATE_COMMAND_NAME, |mock, layer| {
+            set_layer(mock, layer, true);
+
+            None
+        })
+        .with_handler(DEACTIVATE_COMMAND_NAME, |mock, layer| {
+            set_layer(mock, layer, false);
+
+            None
+        })
+        .with_handler(IS_ACTIVE_COMMAND_NAME, |mock, layer| {
+            let layer = layer?.trim().parse::<usize>().ok()?;
+
+            let active = mock.get(STATE_COMMAND_NAME)?.split_whitespace().nth(layer) == Some("1");
+
+            Some(active.to_string())
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn layer_state_parses() {
+        let state = LayerState::parse("1 0 1 0 0 0 0 0 0 0 0 0 0 0 0 0", 10).unwrap();
+
+        assert_eq!(state.0.len(), 10);
+        assert_eq!(state.active_layers(), [0, 2]);
+        assert!(state.is_active(2));
+        assert!(!state.is_active(12));
+
+        assert!(LayerState::parse("1 2", 10).is_err());
+    }
+}
@@ -0,0 +1,288 @@
+//! Provides the types used for controlling the LEDs at runtime, with the
+//! `led.*` family of commands.
+//!
+//! Unlike the [palette and colormap](super::lighting), nothing set this way
+//! is stored on the keyboard. Colours set on LEDs last until the LED mode
+//! redraws them, such as when switching layers, or when calling
+//! [`Keyboard::refresh_leds`].
+//!
+//! The methods themselves live on the [`Keyboard`] trait.
+
+use super::{Keyboard, ModelDescriptor, lighting::Color, settings::ParseSettingError};
+use crate::focus_api::{MockFocusApi, MockHandler, RequireCommandError, RunCommandError};
+pub use error::*;
+use itertools::Itertools;
+
+mod error {
+    use super::*;
+
+    /// Error returned when an LED does not exist on the keyboard.
+    #[derive(Clone, Copy, Debug, Display, Error)]
+    #[display("LED {led} does not exist, expected 0 to {}", leds - 1)]
+    pub struct LedOutOfRangeError {
+        /// The requested LED, indexed from 0.
+        pub led: u16,
+        /// How many LEDs the keyboard has.
+        pub leds: usize,
+    }
+
+    /// Error returned when reading the colour of LEDs.
+    #[derive(Debug, Display, From, Error)]
+    pub enum GetLedError {
+        /// The firmware does not support reading LEDs.
+        #[display("{_0}")]
+        Unsupported(RequireCommandError),
+        /// The LED does not exist on the keyboard.
+        #[display("{_0}")]
+        OutOfRange(LedOutOfRangeError),
+        /// Failed to run command.
+        #[display("{_0}")]
+        CommandFailed(RunCommandError),
+        /// The colours returned by the keyboard failed to parse.
+        #[display("{_0}")]
+        ParsingFailure(ParseSettingError),
+    }
+
+    /// Error returned when changing the colour of LEDs.
+    #[derive(Debug, Display, From, Error)]
+    pub enum SetLedError {
+        /// The firmware does not support changing LEDs.
+        #[display("{_0}")]
+        Unsupported(RequireCommandError),
+        /// The LED does not exist on the keyboard.
+        #[display("{_0}")]
+        OutOfRange(LedOutOfRangeError),
+        /// A colour has a white channel, but the keyboard's LEDs don't.
+        #[display("the {keyboard} has RGB LEDs, expected `#rrggbb` colours")]
+        WrongColorFormat {
+            /// The model of the keyboard.
+            keyboard: &'static str,
+        },
+        /// The theme doesn't have one colour per LED.
+        #[display("the {keyboard} has {expected} LEDs, but the theme has {found} colours")]
+        IncorrectNumberOfColors {
+            /// The model of the keyboard.
+            keyboard: &'static str,
+            /// How many LEDs the keyboard has.
+            expected: usize,
+            /// How many colours the theme has.
+            found: usize,
+        },
+        /// Failed to run command.
+        #[display("{_0}")]
+        CommandFailed(RunCommandError),
+    }
+}
+
+/// Command used to get and set the active LED mode.
+pub const MODE_COMMAND_NAME: &str = "led.mode";
+
+/// Command used to get and set the brightness of the key LEDs.
+pub const BRIGHTNESS_COMMAND_NAME: &str = "led.brightness";
+
+/// Command used to get and set the brightness of the underglow LEDs.
+pub const UNDERGLOW_BRIGHTNESS_COMMAND_NAME: &str = "led.brightnessUG";
+
+/// Command used to get and set the colour of a single LED.
+pub const AT_COMMAND_NAME: &str = "led.at";
+
+/// Command used to set every LED to the same colour.
+pub const SET_ALL_COMMAND_NAME: &str = "led.setAll";
+
+/// Command used to get and set the colour of every LED at once.
+pub const THEME_COMMAND_NAME: &str = "led.theme";
+
+/// Command used to get and set how long the keyboard waits, in seconds,
+/// before turning the LEDs off when idle.
+pub const IDLE_TIMEOUT_COMMAND_NAME: &str = "idleleds.time_limit";
+
+/// Checks that the LED exists on the keyboard.
+pub(crate) fn check_led<K>(keyboard: &K, led: u16) -> Result<(), LedOutOfRangeError>
+where
+    K: Keyboard + ?Sized,
+{
+    let leds = keyboard.descriptor().leds_per_layer;
+
+    if (led as usize) < leds {
+        return Ok(());
+    }
+
+    Err(LedOutOfRangeError { led, leds })
+}
+
+/// Converts colours into the format the keyboard's LEDs expect, and joins
+/// them into command data.
+///
+/// RGB colours are sent to RGBW LEDs with the white channel off, while
+/// RGBW colours are rejected by RGB LEDs.
+pub(crate) fn colors_to_command_data<'a>(
+    descriptor: &ModelDescriptor,
+    colors: impl IntoIterator<Item = &'a Color>,
+) -> Result<String, SetLedError> {
+    let colors = colors
+        .into_iter()
+        .map(|color| match (descriptor.rgbw, color.white) {
+            (true, None) => Ok(Color {
+                white: Some(0),
+                ..*color
+            }),
+            (false, Some(_)) => Err(SetLedError::WrongColorFormat {
+                keyboard: descriptor.name,
+            }),
+            _ => Ok(*color),
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+
+    Ok(colors.iter().flat_map(Color::channels).join(" "))
+}
+
+/// Parses the colours returned by a command, with three or four channels
+/// each, depending on the keyboard's LEDs.
+pub(crate) fn parse_colors(
+    command: &'static str,
+    value: String,
+    descriptor: &ModelDescriptor,
+) -> Result<Vec<Color>, ParseSettingError> {
+    let channels = if descriptor.rgbw { 4 } else { 3 };
+
+    let err = |value| ParseSettingError {
+        command,
+        value,
+        expected: "list of colours",
+    };
+
+    let Ok(values) = value
+        .split_whitespace()
+        .map(str::parse::<u8>)
+        .collect::<Result<Vec<_>, _>>()
+    else {
+        return Err(err(value));
+    };
+
+    if values.is_empty() || values.len() % channels != 0 {
+        return Err(err(value));
+    }
+
+    let colors = values
+        .chunks(channels)
+        .map(|color| Color {
+            red: color[0],
+            green: color[1],
+            blue: color[2],
+            white: color.get(3).copied(),
+        })
+        .collect();
+
+    Ok(colors)
+}
+
+/// Adds the `led.*` commands, emulated like the firmware does, to a virtual
+/// keyboard, starting with every LED off.
+pub(crate) fn with_virtual_leds(mock: MockFocusApi, descriptor: &ModelDescriptor) -> MockFocusApi {
+    /// Sets the colour of a single LED, or gets it when only the LED is
+    /// given.
+    fn led_at<const CHANNELS: usize>(
+        mock: &mut MockFocusApi,
+        data: Option<&str>,
+    ) -> Option<String> {
+        let mut args = data?.split_whitespace();
+        let led = args.next()?.parse::<usize>().ok()?;
+        let color = args.collect::<Vec<_>>();
+
+        let mut theme = mock
+            .get(THEME_COMMAND_NAME)?
+            .split_whitespace()
+            .map(ToOwned::to_owned)
+            .collect::<Vec<_>>();
+
+        let channels = theme.get_mut(led * CHANNELS..(led + 1) * CHANNELS)?;
+
+        if color.is_empty() {
+            return Some(channels.join(" "));
+        }
+
+        for (channel, value) in channels.iter_mut().zip(color) {
+            *channel = value.to_owned();
+        }
+
+        mock.set(THEME_COMMAND_NAME, theme.join(" "));
+
+        None
+    }
+
+    /// Sets every LED to the same colour.
+    fn set_all<const CHANNELS: usize>(
+        mock: &mut MockFocusApi,
+        data: Option<&str>,
+    ) -> Option<String> {
+        let color = data?.split_whitespace().collect::<Vec<_>>();
+
+        let leds = mock.get(THEME_COMMAND_NAME)?.split_whitespace().count() / CHANNELS;
+
+        let theme = (0..leds)
+            .flat_map(|_| (0..CHANNELS).map(|i| color.get(i).copied().unwrap_or("0")))
+            .join(" ");
+
+        mock.set(THEME_COMMAND_NAME, theme);
+
+        None
+    }
+
+    let (led_at, set_all): (MockHandler, MockHandler) = if descriptor.rgbw {
+        (led_at::<4>, set_all::<4>)
+    } else {
+        (led_at::<3>, set_all::<3>)
+    };
+
+    let channels = if descriptor.rgbw { 4 } else { 3 };
+    let theme = vec!["0"; descriptor.leds_per_layer * channels].join(" ");
+
+    mock.with_command(MODE_COMMAND_NAME, "0")
+        .with_command(BRIGHTNESS_COMMAND_NAME, "255")
+        .with_command(UNDERGLOW_BRIGHTNESS_COMMAND_NAME, "255")
+        .with_command(THEME_COMMAND_NAME, theme)
+        .with_handler(AT_COMMAND_NAME, led_at)
+        .with_handler(SET_ALL_COMMAND_NAME, set_all)
+        .with_command(IDLE_TIMEOUT_COMMAND_NAME, "600")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::devices::{defy::DefyKeyboard, raise::RaiseKeyboard};
+
+    #[test]
+    fn colors_are_converted_to_the_led_format() {
+        let red = Color::rgb(255, 0, 0);
+
+        assert_eq!(
+            colors_to_command_data(&DefyKeyboard::DESCRIPTOR, [&red]).unwrap(),
+            "255 0 0 0"
+        );
+        assert_eq!(
+            colors_to_command_data(&RaiseKeyboard::DESCRIPTOR, [&red]).unwrap(),
+            "255 0 0"
+        );
+        assert!(matches!(
+            colors_to_command_data(&RaiseKeyboard::DESCRIPTOR, [&Color::rgbw(0, 0, 0, 255)]),
+            Err(SetLedError::WrongColorFormat { .. })
+        ));
+
+        let colors = parse_colors(
+            THEME_COMMAND_NAME,
+            "1 2 3 4 5 6".to_owned(),
+            &RaiseKeyboard::DESCRIPTOR,
+        )
+        .unwrap();
+
+        assert_eq!(colors, [Color::rgb(1, 2, 3), Color::rgb(4, 5, 6)]);
+        assert!(
+            parse_colors(
+                THEME_COMMAND_NAME,
+                "1 2 3 4 5 6".to_owned(),
+                &DefyKeyboard::DESCRIPTOR
+            )
+            .is_err()
+        );
+    }
+}
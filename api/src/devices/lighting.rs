@@ -0,0 +1,477 @@
+//! Provides the types used for the LED lighting stored on the keyboard: the
+//! [`Palette`] of colours, and the [`Colormap`] assigning a palette colour to
+//! each LED of each layer.
+//!
+//! The methods themselves live on the [`Keyboard`](super::Keyboard) trait.
+
+use super::{Model, ModelDescriptor, defy::DefyColormap};
+use crate::focus_api::{RequireCommandError, RunCommandError};
+pub use error::*;
+use itertools::Itertools;
+use std::{fmt, str::FromStr};
+
+mod error {
+    use super::*;
+
+    /// Error when parsing a [`Color`] from a hex string.
+    #[derive(Clone, Debug, Display, From, Error)]
+    #[display("`{_0}` is not a colour, expected `#rrggbb` or `#rrggbbww`")]
+    pub struct ParseColorError(#[error(not(source))] String);
+
+    /// Error when parsing a [`Palette`] from `palette` command data.
+    #[derive(Clone, Debug, Display, From, Error)]
+    #[display("failed to parse palette: {_0}")]
+    pub struct ParsePaletteError(#[error(not(source))] String);
+
+    /// Error when parsing a [`Colormap`] from `colormap.map` command data.
+    #[derive(Clone, Debug, Display, From, Error)]
+    #[display("failed to parse colormap: {_0}")]
+    pub struct ParseColormapError(#[error(not(source))] String);
+
+    /// Error returned from [`Keyboard::get_palette`](crate::devices::Keyboard::get_palette).
+    #[derive(Debug, Display, From, Error)]
+    pub enum GetPaletteError {
+        /// The firmware does not support palettes.
+        #[display("{_0}")]
+        Unsupported(RequireCommandError),
+        /// Failed to run command.
+        #[display("{_0}")]
+        CommandFailed(RunCommandError),
+        /// Palette returned by the keyboard failed to parse.
+        #[display("{_0}")]
+        ParsingFailure(ParsePaletteError),
+    }
+
+    /// Error returned from [`Keyboard::apply_palette`](crate::devices::Keyboard::apply_palette).
+    #[derive(Debug, Display, From, Error)]
+    pub enum ApplyPaletteError {
+        /// The firmware does not support palettes.
+        #[display("{_0}")]
+        Unsupported(RequireCommandError),
+        /// The palette doesn't have exactly [`PALETTE_SIZE`] colours.
+        #[display("a palette must have {PALETTE_SIZE} colours, but this one has {_0}")]
+        IncorrectNumberOfColors(#[error(not(source))] usize),
+        /// The colours don't match the LEDs of the keyboard.
+        #[display(
+            "the {keyboard} expects {} colours",
+            if *rgbw { "RGBW (`#rrggbbww`)" } else { "RGB (`#rrggbb`)" }
+        )]
+        WrongColorFormat {
+            /// The model of the keyboard.
+            keyboard: &'static str,
+            /// Whether the keyboard expects RGBW colours.
+            rgbw: bool,
+        },
+        /// Command failed to run.
+        #[display("{_0}")]
+        CommandFailed(RunCommandError),
+    }
+
+    /// Error returned from [`Keyboard::get_colormap`](crate::devices::Keyboard::get_colormap).
+    #[derive(Debug, Display, From, Error)]
+    pub enum GetColormapError {
+        /// The firmware does not support colormaps.
+        #[display("{_0}")]
+        Unsupported(RequireCommandError),
+        /// Failed to run command.
+        #[display("{_0}")]
+        CommandFailed(RunCommandError),
+        /// Colormap returned by the keyboard failed to parse.
+        #[display("{_0}")]
+        ParsingFailure(ParseColormapError),
+    }
+
+    /// Error returned from [`Keyboard::apply_colormap`](crate::devices::Keyboard::apply_colormap).
+    #[derive(Debug, Display, From, Error)]
+    pub enum ApplyColormapError {
+        /// The firmware does not support colormaps.
+        #[display("{_0}")]
+        Unsupported(RequireCommandError),
+        /// The colormap doesn't have one layer per keymap layer.
+        #[display("the {keyboard} has {expected} layers, but the colormap has {found}")]
+        IncorrectNumberOfLayers {
+            /// The model of the keyboard.
+            keyboard: &'static str,
+            /// How many layers the keyboard has.
+            expected: usize,
+            /// How many layers the colormap has.
+            found: usize,
+        },
+        /// A layer doesn't have one entry per LED.
+        #[display("the {keyboard} has {expected} LEDs, but layer {layer} has {found}")]
+        IncorrectNumberOfLeds {
+            /// The model of the keyboard.
+            keyboard: &'static str,
+            /// The layer, indexed from 1.
+            layer: usize,
+            /// How many LEDs the keyboard has.
+            expected: usize,
+            /// How many LEDs the layer has.
+            found: usize,
+        },
+        /// An entry refers to a colour outside of the palette.
+        #[display("palette index {_0} is out of range, expected 0 to {}", PALETTE_SIZE - 1)]
+        InvalidPaletteIndex(#[error(not(source))] u8),
+        /// Command failed to run.
+        #[display("{_0}")]
+        CommandFailed(RunCommandError),
+    }
+}
+
+/// Command used to get and set the palette.
+pub const PALETTE_COMMAND_NAME: &str = "palette";
+
+/// Command used to get and set the colormap.
+pub const COLORMAP_COMMAND_NAME: &str = "colormap.map";
+
+/// The number of colours in a palette.
+pub const PALETTE_SIZE: usize = 16;
+
+/// A colour, with an optional white channel for keyboards with RGBW LEDs.
+///
+/// Colours are written as hex strings, `#rrggbb` or `#rrggbbww`.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub struct Color {
+    /// Red channel.
+    pub red: u8,
+    /// Green channel.
+    pub green: u8,
+    /// Blue channel.
+    pub blue: u8,
+    /// White channel, only present on RGBW LEDs.
+    pub white: Option<u8>,
+}
+
+impl Color {
+    /// Creates an RGB colour.
+    pub const fn rgb(red: u8, green: u8, blue: u8) -> Self {
+        Self {
+            red,
+            green,
+            blue,
+            white: None,
+        }
+    }
+
+    /// Creates an RGBW colour.
+    pub const fn rgbw(red: u8, green: u8, blue: u8, white: u8) -> Self {
+        Self {
+            red,
+            green,
+            blue,
+            white: Some(white),
+        }
+    }
+
+    /// The channels of the colour, as sent to the keyboard.
+    pub fn channels(&self) -> impl Iterator<Item = u8> {
+        [self.red, self.green, self.blue]
+            .into_iter()
+            .chain(self.white)
+    }
+}
+
+impl fmt::Display for Color {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "#{:02x}{:02x}{:02x}", self.red, self.green, self.blue)?;
+
+        if let Some(white) = self.white {
+            write!(f, "{white:02x}")?;
+        }
+
+        Ok(())
+    }
+}
+
+impl FromStr for Color {
+    type Err = ParseColorError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let err = || ParseColorError::from(s.to_owned());
+
+        let hex = s.trim().strip_prefix('#').ok_or_else(err)?;
+
+        if !matches!(hex.len(), 6 | 8) || !hex.is_ascii() {
+            return Err(err());
+        }
+
+        let channels = (0..hex.len())
+            .step_by(2)
+            .map(|i| u8::from_str_radix(&hex[i..i + 2], 16))
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(|_| err())?;
+
+        Ok(Self {
+            red: channels[0],
+            green: channels[1],
+            blue: channels[2],
+            white: channels.get(3).copied(),
+        })
+    }
+}
+
+impl serde::Serialize for Color {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: serde::Serializer,
+    {
+        self.to_string().serialize(serializer)
+    }
+}
+
+impl<'de> serde::Deserialize<'de> for Color {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: serde::Deserializer<'de>,
+    {
+        String::deserialize(deserializer)?
+            .parse()
+            .map_err(serde::de::Error::custom)
+    }
+}
+
+/// The colours the [`Colormap`] refers to, by index.
+#[derive(Clone, Debug, PartialEq, Eq, Hash, From, Deref, DerefMut, Serialize, Deserialize)]
+pub struct Palette(pub Vec<Color>);
+
+impl FromStr for Palette {
+    type Err = ParsePaletteError;
+
+    /// Parses `palette` command data, which is RGB or RGBW depending on the
+    /// keyboard, as told by the number of values.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let values = s
+            .split_whitespace()
+            .map(str::parse::<u8>)
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(|err| ParsePaletteError::from(err.to_string()))?;
+
+        let channels = match values.len() {
+            len if len == PALETTE_SIZE * 3 => 3,
+            len if len == PALETTE_SIZE * 4 => 4,
+            len => {
+                return Err(ParsePaletteError::from(format!(
+                    "expected {} RGB or {} RGBW values, got {len}",
+                    PALETTE_SIZE * 3,
+                    PALETTE_SIZE * 4
+                )));
+            }
+        };
+
+        let colors = values
+            .chunks(channels)
+            .map(|color| Color {
+                red: color[0],
+                green: color[1],
+                blue: color[2],
+                white: color.get(3).copied(),
+            })
+            .collect();
+
+        Ok(Self(colors))
+    }
+}
+
+impl Palette {
+    /// Creates a palette where every colour is black, in the format the
+    /// model expects.
+    pub fn new_black(descriptor: &ModelDescriptor) -> Self {
+        let black = Color {
+            white: descriptor.rgbw.then_some(0),
+            ..Color::default()
+        };
+
+        Self(vec![black; PALETTE_SIZE])
+    }
+
+    /// Converts this palette into a form suitable for sending over to the
+    /// keyboard as the data of a `palette` command.
+    pub fn to_command_data(
+        &self,
+        descriptor: &ModelDescriptor,
+    ) -> Result<String, ApplyPaletteError> {
+        if self.len() != PALETTE_SIZE {
+            return Err(ApplyPaletteError::IncorrectNumberOfColors(self.len()));
+        }
+
+        if self
+            .iter()
+            .any(|color| color.white.is_some() != descriptor.rgbw)
+        {
+            return Err(ApplyPaletteError::WrongColorFormat {
+                keyboard: descriptor.name,
+                rgbw: descriptor.rgbw,
+            });
+        }
+
+        Ok(self.iter().flat_map(Color::channels).join(" "))
+    }
+}
+
+/// The palette index of each LED of a single layer, in the order the
+/// firmware uses.
+#[derive(Clone, Debug, PartialEq, Eq, Hash, From, Deref, DerefMut, Serialize, Deserialize)]
+pub struct ColormapLayer(pub Vec<u8>);
+
+/// The palette index of each LED, for every layer, in the order the
+/// firmware uses.
+#[derive(Clone, Debug, PartialEq, Eq, Hash, From, Deref, DerefMut, Serialize, Deserialize)]
+pub struct Colormap(pub Vec<ColormapLayer>);
+
+impl Colormap {
+    /// Parses `colormap.map` command data, with the given number of LEDs in
+    /// each layer.
+    pub fn from_str_with_leds(s: &str, leds_per_layer: usize) -> Result<Self, ParseColormapError> {
+        let values = s
+            .split_whitespace()
+            .map(str::parse::<u8>)
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(|err| ParseColormapError::from(err.to_string()))?;
+
+        if values.is_empty() || values.len() % leds_per_layer != 0 {
+            return Err(ParseColormapError::from(format!(
+                "expected a multiple of {leds_per_layer} values, got {}",
+                values.len()
+            )));
+        }
+
+        let layers = values
+            .chunks(leds_per_layer)
+            .map(|layer| ColormapLayer(layer.to_vec()))
+            .collect();
+
+        Ok(Self(layers))
+    }
+
+    /// Creates a colormap where every LED of every layer uses the first
+    /// palette colour.
+    pub fn new_cleared(descriptor: &ModelDescriptor) -> Self {
+        let layer = ColormapLayer(vec![0; descriptor.leds_per_layer]);
+
+        Self(vec![layer; descriptor.layers])
+    }
+
+    /// Converts this colormap into a form suitable for sending over to the
+    /// keyboard as the data of a `colormap.map` command.
+    pub fn to_command_data(
+        &self,
+        descriptor: &ModelDescriptor,
+    ) -> Result<String, ApplyColormapError> {
+        if self.len() != descriptor.layers {
+            return Err(ApplyColormapError::IncorrectNumberOfLayers {
+                keyboard: descriptor.name,
+                expected: descriptor.layers,
+                found: self.len(),
+            });
+        }
+
+        for (i, layer) in self.iter().enumerate() {
+            if layer.len() != descriptor.leds_per_layer {
+                return Err(ApplyColormapError::IncorrectNumberOfLeds {
+                    keyboard: descriptor.name,
+                    layer: i + 1,
+                    expected: descriptor.leds_per_layer,
+                    found: layer.len(),
+                });
+            }
+
+            if let Some(index) = layer.iter().find(|index| **index as usize >= PALETTE_SIZE) {
+                return Err(ApplyColormapError::InvalidPaletteIndex(*index));
+            }
+        }
+
+        Ok(self.iter().flat_map(|layer| layer.iter()).join(" "))
+    }
+}
+
+/// A colormap for any supported model.
+///
+/// Defy colormaps follow the keyboard's physical layout, while other models
+/// list the LEDs in the order the firmware uses. When deserializing, the
+/// two are told apart by the shape of the layers.
+#[derive(Clone, Debug, PartialEq, Eq, Hash, From, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum AnyColormap {
+    /// A Defy colormap.
+    Defy(DefyColormap),
+    /// A colormap in the order the firmware uses.
+    Raw(Colormap),
+}
+
+impl AnyColormap {
+    /// Wraps the colormap into the form used for the given model.
+    pub fn for_model(model: Model, colormap: Colormap) -> Self {
+        match model {
+            Model::Defy => Self::Defy(DefyColormap::from(&colormap)),
+            Model::Raise | Model::Raise2 => Self::Raw(colormap),
+        }
+    }
+
+    /// Converts the colormap into the order the firmware uses.
+    pub fn to_colormap(&self) -> Colormap {
+        match self {
+            Self::Defy(colormap) => colormap.to_colormap(),
+            Self::Raw(colormap) => colormap.clone(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::devices::{defy::DefyKeyboard, raise::RaiseKeyboard};
+
+    #[test]
+    fn colors_parse_from_hex() {
+        assert_eq!("#ff8000".parse::<Color>().unwrap(), Color::rgb(255, 128, 0));
+        assert_eq!(
+            "#ff800010".parse::<Color>().unwrap(),
+            Color::rgbw(255, 128, 0, 16)
+        );
+        assert_eq!(Color::rgbw(1, 2, 3, 4).to_string(), "#01020304");
+
+        assert!("ff8000".parse::<Color>().is_err());
+        assert!("#ff80".parse::<Color>().is_err());
+    }
+
+    #[test]
+    fn palettes_round_trip() {
+        let data = (0..64).join(" ");
+
+        let palette = data.parse::<Palette>().unwrap();
+
+        assert_eq!(palette[1], Color::rgbw(4, 5, 6, 7));
+        assert_eq!(
+            palette.to_command_data(&DefyKeyboard::DESCRIPTOR).unwrap(),
+            data
+        );
+
+        assert!(matches!(
+            palette.to_command_data(&RaiseKeyboard::DESCRIPTOR),
+            Err(ApplyPaletteError::WrongColorFormat { rgbw: false, .. })
+        ));
+    }
+
+    #[test]
+    fn colormaps_are_validated() {
+        let descriptor = &RaiseKeyboard::DESCRIPTOR;
+
+        let mut colormap = Colormap::new_cleared(descriptor);
+
+        assert!(colormap.to_command_data(descriptor).is_ok());
+
+        colormap[2][5] = 16;
+
+        assert!(matches!(
+            colormap.to_command_data(descriptor),
+            Err(ApplyColormapError::InvalidPaletteIndex(16))
+        ));
+
+        colormap.pop();
+
+        assert!(matches!(
+            colormap.to_command_data(descriptor),
+            Err(ApplyColormapError::IncorrectNumberOfLayers { found: 9, .. })
+        ));
+    }
+}
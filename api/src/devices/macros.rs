@@ -0,0 +1,7 @@
+//! Provides the command used for storing macros on the keyboard.
+//!
+//! The macro types live in [`defy`](super::defy), as every model shares them,
+//! and the methods themselves live on the [`Keyboard`](super::Keyboard) trait.
+
+/// Command used to get and set the macro map.
+pub const MAP_COMMAND_NAME: &str = "macros.map";
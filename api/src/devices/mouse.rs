@@ -0,0 +1,92 @@
+//! Provides the types used for configuring how mouse keys move the cursor
+//! and scroll, with the `mouse.*` family of commands.
+//!
+//! The methods themselves live on the [`Keyboard`](super::Keyboard) trait.
+
+use super::settings::{SettingOutOfRangeError, check_setting_range};
+use crate::focus_api::MockFocusApi;
+
+/// Command used to get and set how far the cursor moves each step.
+pub const SPEED_COMMAND_NAME: &str = "mouse.speed";
+
+/// Command used to get and set how long the cursor waits between steps.
+pub const SPEED_DELAY_COMMAND_NAME: &str = "mouse.speedDelay";
+
+/// Command used to get and set how much faster the cursor gets each step.
+pub const ACCEL_SPEED_COMMAND_NAME: &str = "mouse.accelSpeed";
+
+/// Command used to get and set how long the cursor waits between speeding
+/// up.
+pub const ACCEL_DELAY_COMMAND_NAME: &str = "mouse.accelDelay";
+
+/// Command used to get and set how far the wheel scrolls each step.
+pub const WHEEL_SPEED_COMMAND_NAME: &str = "mouse.wheelSpeed";
+
+/// Command used to get and set how long the wheel waits between steps.
+pub const WHEEL_DELAY_COMMAND_NAME: &str = "mouse.wheelDelay";
+
+/// Command used to get and set the fastest the cursor can move.
+pub const SPEED_LIMIT_COMMAND_NAME: &str = "mouse.speedLimit";
+
+/// The largest speed accepted by the firmware, which moves the cursor with
+/// signed 8-bit steps.
+pub const MAX_SPEED: u8 = 127;
+
+/// How mouse keys move the cursor and scroll.
+///
+/// Delays are in milliseconds, while speeds are in steps sent to the
+/// computer, from 0 to [`MAX_SPEED`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct MouseKeySettings {
+    /// How far the cursor moves each step.
+    pub speed: u8,
+    /// How long the cursor waits between steps.
+    pub speed_delay: u8,
+    /// How much faster the cursor gets each step.
+    pub accel_speed: u8,
+    /// How long the cursor waits between speeding up.
+    pub accel_delay: u8,
+    /// How far the wheel scrolls each step.
+    pub wheel_speed: u8,
+    /// How long the wheel waits between steps.
+    pub wheel_delay: u8,
+    /// The fastest the cursor can move, from 1 to 255.
+    pub speed_limit: u8,
+}
+
+impl MouseKeySettings {
+    /// Checks that every setting is within the range accepted by the
+    /// firmware.
+    pub fn validate(&self) -> Result<(), SettingOutOfRangeError> {
+        let max_speed = MAX_SPEED.into();
+
+        check_setting_range(SPEED_COMMAND_NAME, self.speed.into(), 0, max_speed)?;
+        check_setting_range(
+            ACCEL_SPEED_COMMAND_NAME,
+            self.accel_speed.into(),
+            0,
+            max_speed,
+        )?;
+        check_setting_range(
+            WHEEL_SPEED_COMMAND_NAME,
+            self.wheel_speed.into(),
+            0,
+            max_speed,
+        )?;
+        check_setting_range(SPEED_LIMIT_COMMAND_NAME, self.speed_limit.into(), 1, 255)?;
+
+        Ok(())
+    }
+}
+
+/// Adds the `mouse.*` commands to a virtual keyboard, with the firmware's
+/// default values.
+pub(crate) fn with_virtual_mouse_keys(mock: MockFocusApi) -> MockFocusApi {
+    mock.with_command(SPEED_COMMAND_NAME, "1")
+        .with_command(SPEED_DELAY_COMMAND_NAME, "1")
+        .with_command(ACCEL_SPEED_COMMAND_NAME, "1")
+        .with_command(ACCEL_DELAY_COMMAND_NAME, "64")
+        .with_command(WHEEL_SPEED_COMMAND_NAME, "1")
+        .with_command(WHEEL_DELAY_COMMAND_NAME, "50")
+        .with_command(SPEED_LIMIT_COMMAND_NAME, "127")
+}
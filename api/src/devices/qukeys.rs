@@ -0,0 +1,73 @@
+//! Provides the types used for configuring when dual-function keys, such as
+//! [`DualAAndCtrl`](crate::keycode_tables::Alpha::DualAAndCtrl), count as
+//! held, with the `qukeys.*` family of commands.
+//!
+//! The methods themselves live on the [`Keyboard`](super::Keyboard) trait.
+
+use super::settings::{SettingOutOfRangeError, check_setting_range};
+use crate::focus_api::MockFocusApi;
+
+/// Command used to get and set how long a dual-function key must be held
+/// before it acts as its modifier or layer.
+pub const HOLD_TIMEOUT_COMMAND_NAME: &str = "qukeys.holdTimeout";
+
+/// Command used to get and set how much a dual-function key may overlap
+/// with the next key before it counts as held.
+pub const OVERLAP_THRESHOLD_COMMAND_NAME: &str = "qukeys.overlapThreshold";
+
+/// The largest overlap threshold accepted by the firmware, in percent.
+pub const MAX_OVERLAP_THRESHOLD: u8 = 100;
+
+/// When dual-function keys act as their modifier or layer, rather than as
+/// their tap key.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct DualFunctionSettings {
+    /// How long a dual-function key must be held before it acts as its
+    /// modifier or layer, in milliseconds.
+    pub hold_timeout: u16,
+    /// How much a dual-function key may overlap with the next key before it
+    /// counts as held, in percent.
+    pub overlap_threshold: u8,
+}
+
+impl DualFunctionSettings {
+    /// The values the firmware ships with.
+    pub const DEFAULT: Self = Self {
+        hold_timeout: 250,
+        overlap_threshold: 80,
+    };
+}
+
+impl DualFunctionSettings {
+    /// Checks that every setting is within the range accepted by the
+    /// firmware.
+    pub fn validate(&self) -> Result<(), SettingOutOfRangeError> {
+        check_setting_range(
+            OVERLAP_THRESHOLD_COMMAND_NAME,
+            self.overlap_threshold.into(),
+            0,
+            MAX_OVERLAP_THRESHOLD.into(),
+        )
+    }
+}
+
+impl Default for DualFunctionSettings {
+    fn default() -> Self {
+        Self::DEFAULT
+    }
+}
+
+/// Adds the `qukeys.*` commands to a virtual keyboard, with the firmware's
+/// default values.
+pub(crate) fn with_virtual_qukeys(mock: MockFocusApi) -> MockFocusApi {
+    let DualFunctionSettings {
+        hold_timeout,
+        overlap_threshold,
+    } = DualFunctionSettings::DEFAULT;
+
+    mock.with_command(HOLD_TIMEOUT_COMMAND_NAME, hold_timeout.to_string())
+        .with_command(
+            OVERLAP_THRESHOLD_COMMAND_NAME,
+            overlap_threshold.to_string(),
+        )
+}
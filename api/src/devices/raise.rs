@@ -0,0 +1,712 @@
+//! Provides the [`RaiseKeyboard`] struct for programatically interacting with
+//! the original Dygma Raise, along with the keymap types shared with the
+//! [Raise 2](super::raise2).
+
+use super::{
+    AnyKeymap, ApplyKeymapError, DynFocusApi, GetKeymapError, Keyboard, Model, ModelDescriptor,
+    ModelLayout,
+    defy::{
+        ApplyCustomKeymapError, ClearLayerError, KeymapDoesNotHave10LayersError, ParseKeymapError,
+    },
+    keymap, macros, superkeys, virtual_default_keymap, with_virtual_commands,
+};
+use crate::{
+    focus_api::{
+        Capabilities, CreateSerialPortFocusApiError, DeviceSelector, FocusApiConnection,
+        GetCapabilitiesError, MockFocusApi, RunCommandError, SerialPortFocusApi, parsing,
+    },
+    keycode_tables::KeyKind,
+};
+pub use error::*;
+use itertools::Itertools;
+use std::{array, str::FromStr};
+
+mod error {
+    use super::*;
+
+    /// Error returned when creating a handle to the keyboard.
+    #[derive(Debug, Display, From, Error)]
+    #[display("failed to create handle to the Dygma Raise keyboard: {_0}")]
+    pub struct CreateRaiseKeyboardError(CreateSerialPortFocusApiError);
+
+    /// Error when parsing a [`RaiseLayoutVariant`] from a string slice.
+    #[derive(Clone, Debug, Display, From, Error)]
+    #[display("unknown layout variant `{_0}`, expected `ANSI` or `ISO`")]
+    pub struct ParseLayoutVariantError(#[error(not(source))] String);
+
+    /// Error returned from [`RaiseKeyboard::get_layout_variant`].
+    #[derive(Debug, Display, From, Error)]
+    pub enum GetLayoutVariantError {
+        /// Failed to run command.
+        #[display("{_0}")]
+        CommandFailed(RunCommandError),
+        /// Layout variant returned by the keyboard failed to parse.
+        #[display("{_0}")]
+        LayoutVariantParsingFailure(ParseLayoutVariantError),
+    }
+
+    /// Error returned from [`RaiseKeyboard::get_custom_keymap`].
+    #[derive(Debug, Display, From, Error)]
+    pub enum GetCustomKeymapError {
+        /// Failed to run command.
+        #[display("{_0}")]
+        CommandFailed(RunCommandError),
+        /// The layout variant, needed to interpret the keymap, could not
+        /// be determined.
+        #[display("{_0}")]
+        LayoutVariant(GetLayoutVariantError),
+        /// Keymap returned by the keyboard failed to parse.
+        KeymapParsingFailure(ParseKeymapError),
+    }
+}
+
+/// Type alias for the raw keymap data.
+pub type RaiseLayerData = [KeyKind; KEYS_PER_LAYER];
+
+/// Number of keys per layer.
+pub const KEYS_PER_LAYER: usize = 80;
+
+/// Number of layers in a `keymap.custom` command.
+pub const KEYMAP_CUSTOM_COMMAND_LAYERS: usize = 10;
+
+/// Number of LEDs per layer in a `colormap.map` command, starting with the
+/// LEDs under the keys, followed by the underglow.
+pub const LEDS_PER_LAYER: usize = 132;
+
+/// Constant providing the ANSI Raise keymap layout.
+pub const LAYOUT_ANSI: &RaiseLayout = &RaiseLayout {
+    left: RaiseLayoutLeft {
+        row_1: [0, 1, 2, 3, 4, 5, 6],
+        row_2: [16, 17, 18, 19, 20, 21],
+        row_3: [32, 33, 34, 35, 36, 37],
+        row_4: [48, 50, 51, 52, 53, 54],
+        iso_key: None,
+        bottom: [64, 65, 66, 67],
+        thumb_cluster: [68, 69, 70, 71],
+    },
+    right: RaiseLayoutRight {
+        row_1: [9, 10, 11, 12, 13, 14, 15],
+        row_2: [24, 25, 26, 27, 28, 29, 30, 31],
+        row_3: [41, 42, 43, 44, 45, 46, 47],
+        row_4: [58, 59, 60, 61, 62, 63],
+        thumb_cluster: [72, 73, 74, 75],
+        bottom: [76, 77, 78, 79],
+    },
+};
+
+/// Constant providing the ISO Raise keymap layout.
+///
+/// This is the ANSI layout with an extra key between the left shift and
+/// `Z`. The ISO enter key takes the place of the ANSI backslash key, and the
+/// `#` key that of the ANSI enter key, so they share their indices.
+pub const LAYOUT_ISO: &RaiseLayout = &RaiseLayout {
+    left: RaiseLayoutLeft {
+        iso_key: Some(49),
+        ..LAYOUT_ANSI.left
+    },
+    right: LAYOUT_ANSI.right,
+};
+
+/// The physical layout variants the Raise is sold in.
+#[derive(Clone, Copy, Debug, Default, Display, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "UPPERCASE")]
+pub enum RaiseLayoutVariant {
+    /// The US layout.
+    #[default]
+    #[display("ANSI")]
+    Ansi,
+    /// The European layout, with an extra key next to the left shift.
+    #[display("ISO")]
+    Iso,
+}
+
+impl RaiseLayoutVariant {
+    /// Gets the physical layout of this variant.
+    pub fn layout(self) -> &'static RaiseLayout {
+        match self {
+            Self::Ansi => LAYOUT_ANSI,
+            Self::Iso => LAYOUT_ISO,
+        }
+    }
+}
+
+impl FromStr for RaiseLayoutVariant {
+    type Err = ParseLayoutVariantError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim() {
+            s if s.eq_ignore_ascii_case("ansi") => Ok(Self::Ansi),
+            s if s.eq_ignore_ascii_case("iso") => Ok(Self::Iso),
+            s => Err(ParseLayoutVariantError::from(s.to_string())),
+        }
+    }
+}
+
+/// A handle to the Dygma Raise keyboard, allowing for programatic control.
+#[derive(Debug, Deref, DerefMut)]
+pub struct RaiseKeyboard {
+    #[deref]
+    #[deref_mut]
+    focus_api: DynFocusApi,
+    capabilities: Option<Capabilities>,
+}
+
+impl From<DynFocusApi> for RaiseKeyboard {
+    fn from(focus_api: DynFocusApi) -> Self {
+        Self {
+            focus_api,
+            capabilities: None,
+        }
+    }
+}
+
+impl RaiseKeyboard {
+    const PRODUCT_NAME: &str = "Raise";
+    pub(crate) const BAUD_RATE: u32 = 115_200;
+
+    /// The memory size of the superkey map.
+    pub const SUPERKEY_MEMORY_SIZE: usize = 512;
+
+    /// The memory size of the macro map, in bytes.
+    pub const MACRO_MEMORY_SIZE: usize = 2048;
+
+    /// Static description of the Raise.
+    pub const DESCRIPTOR: ModelDescriptor = ModelDescriptor {
+        model: Model::Raise,
+        name: "Raise",
+        product_ids: &[0x2201],
+        wireless: false,
+        layout: ModelLayout::Raise {
+            ansi: LAYOUT_ANSI,
+            iso: LAYOUT_ISO,
+        },
+        keys_per_layer: KEYS_PER_LAYER,
+        layers: KEYMAP_CUSTOM_COMMAND_LAYERS,
+        leds_per_layer: LEDS_PER_LAYER,
+        rgbw: false,
+        superkey_memory_size: Self::SUPERKEY_MEMORY_SIZE,
+        macro_memory_size: Self::MACRO_MEMORY_SIZE,
+    };
+
+    /// Creates a handle to the keyboard.
+    pub async fn new() -> Result<Self, CreateRaiseKeyboardError> {
+        Self::with_selector(&DeviceSelector::Any).await
+    }
+
+    /// Creates a handle to the keyboard chosen by the selector.
+    ///
+    /// The Raise can only be connected with a USB cable, so only serial
+    /// port connections are tried.
+    pub async fn with_selector(
+        selector: &DeviceSelector,
+    ) -> Result<Self, CreateRaiseKeyboardError> {
+        let focus_api =
+            SerialPortFocusApi::with_selector(Self::PRODUCT_NAME, Self::BAUD_RATE, selector)
+                .await?;
+
+        Ok(Self::from(DynFocusApi::from(focus_api)))
+    }
+
+    /// Creates a handle to a virtual keyboard emulated entirely in memory.
+    ///
+    /// The virtual keyboard has the given layout variant, and starts with a
+    /// blank keymap, and no superkeys or macros.
+    pub fn new_virtual(variant: RaiseLayoutVariant) -> Self {
+        Self::from(DynFocusApi::from(Self::virtual_focus_api(
+            variant,
+            &Self::DESCRIPTOR,
+        )))
+    }
+
+    /// Emulates the Focus API of a keyboard from the Raise family, sized for
+    /// the model.
+    pub(super) fn virtual_focus_api(
+        variant: RaiseLayoutVariant,
+        descriptor: &ModelDescriptor,
+    ) -> MockFocusApi {
+        let keymap = [0; KEYS_PER_LAYER * KEYMAP_CUSTOM_COMMAND_LAYERS]
+            .iter()
+            .join(" ");
+        let superkeys = [u16::MAX; Self::SUPERKEY_MEMORY_SIZE].iter().join(" ");
+        let macros = [u8::MAX; Self::MACRO_MEMORY_SIZE].iter().join(" ");
+
+        with_virtual_commands(MockFocusApi::new(), descriptor)
+            .with_read_only_command(keymap::LAYOUT_COMMAND_NAME, variant.to_string())
+            .with_command(keymap::CUSTOM_COMMAND_NAME, keymap)
+            .with_read_only_command(
+                keymap::DEFAULT_COMMAND_NAME,
+                virtual_default_keymap(KEYS_PER_LAYER),
+            )
+            .with_command(superkeys::MAP_COMMAND_NAME, superkeys)
+            .with_command(macros::MAP_COMMAND_NAME, macros)
+    }
+
+    /// Get the physical layout variant of the keyboard.
+    pub async fn get_layout_variant(
+        &mut self,
+    ) -> Result<RaiseLayoutVariant, GetLayoutVariantError> {
+        let variant = self
+            .run_command(keymap::LAYOUT_COMMAND_NAME, None)
+            .await?
+            .parse()?;
+
+        Ok(variant)
+    }
+
+    /// Get the custom keymap from the keyboard, interpreted according to the
+    /// keyboard's layout variant.
+    pub async fn get_custom_keymap(&mut self) -> Result<RaiseKeymap, GetCustomKeymapError> {
+        let variant = self.get_layout_variant().await?;
+
+        let data = self.run_command(keymap::CUSTOM_COMMAND_NAME, None).await?;
+
+        RaiseKeymap::from_str_with_variant(&data, variant).map_err(Into::into)
+    }
+
+    /// Apply the keymap to the keyboard.
+    pub async fn apply_custom_keymap(
+        &mut self,
+        keymap: &RaiseKeymap,
+    ) -> Result<(), ApplyCustomKeymapError> {
+        let data = keymap.to_keymap_custom_data()?;
+
+        self.run_command(keymap::CUSTOM_COMMAND_NAME, Some(&data))
+            .await?;
+
+        Ok(())
+    }
+}
+
+impl Keyboard for RaiseKeyboard {
+    fn descriptor(&self) -> &'static ModelDescriptor {
+        &Self::DESCRIPTOR
+    }
+
+    fn focus_api(&mut self) -> &mut DynFocusApi {
+        self
+    }
+
+    async fn capabilities(&mut self) -> Result<&Capabilities, GetCapabilitiesError> {
+        if self.capabilities.is_none() {
+            self.capabilities = Some(self.focus_api.capabilities().await?);
+        }
+
+        Ok(self.capabilities.as_ref().unwrap())
+    }
+
+    async fn get_keymap(&mut self) -> Result<AnyKeymap, GetKeymapError> {
+        Ok(self.get_custom_keymap().await?.into())
+    }
+
+    async fn get_default_keymap(&mut self) -> Result<AnyKeymap, GetKeymapError> {
+        self.require_command(keymap::DEFAULT_COMMAND_NAME).await?;
+
+        let variant = self.get_layout_variant().await?;
+
+        let data = self.run_command(keymap::DEFAULT_COMMAND_NAME, None).await?;
+
+        Ok(RaiseKeymap::from_str_with_variant(&data, variant)?.into())
+    }
+
+    async fn apply_keymap(&mut self, keymap: &AnyKeymap) -> Result<(), ApplyKeymapError> {
+        match keymap {
+            AnyKeymap::Raise(keymap) => Ok(self.apply_custom_keymap(keymap).await?),
+            keymap => Err(ApplyKeymapError::WrongModel {
+                keymap: keymap.family(),
+                keyboard: self.descriptor().name,
+            }),
+        }
+    }
+}
+
+/// Structure representing the physical layout of the Raise keyboard.
+#[derive(Clone, Copy, Debug)]
+pub struct RaiseLayout {
+    /// Left half of the keyboard.
+    pub left: RaiseLayoutLeft,
+    /// Right half of the keyboard.
+    pub right: RaiseLayoutRight,
+}
+
+/// Left half layout of the Raise keyboard.
+#[derive(Clone, Copy, Debug)]
+pub struct RaiseLayoutLeft {
+    /// Row 1 key indices.
+    pub row_1: [u8; 7],
+    /// Row 2 key indices.
+    pub row_2: [u8; 6],
+    /// Row 3 key indices.
+    pub row_3: [u8; 6],
+    /// Row 4 key indices, without the ISO key.
+    pub row_4: [u8; 6],
+    /// Index of the key between the left shift and `Z`, which only ISO
+    /// keyboards have.
+    pub iso_key: Option<u8>,
+    /// Bottom row key indices, left of the thumb cluster.
+    pub bottom: [u8; 4],
+    /// Thumb cluster key indices.
+    pub thumb_cluster: [u8; 4],
+}
+
+/// Right half layout of the Raise keyboard.
+#[derive(Clone, Copy, Debug)]
+pub struct RaiseLayoutRight {
+    /// Row 1 key indices.
+    pub row_1: [u8; 7],
+    /// Row 2 key indices.
+    pub row_2: [u8; 8],
+    /// Row 3 key indices.
+    pub row_3: [u8; 7],
+    /// Row 4 key indices.
+    pub row_4: [u8; 6],
+    /// Thumb cluster key indices.
+    pub thumb_cluster: [u8; 4],
+    /// Bottom row key indices, right of the thumb cluster.
+    pub bottom: [u8; 4],
+}
+
+/// Full Raise keymap.
+#[derive(Clone, Debug, PartialEq, Eq, Hash, From, Deref, DerefMut, Deserialize)]
+pub struct RaiseKeymap(pub Vec<RaiseKeymapLayer>);
+
+impl FromStr for RaiseKeymap {
+    type Err = ParseKeymapError;
+
+    /// Parses the keymap as if it came from an ISO keyboard, so that no key
+    /// is lost. Use [`RaiseKeymap::from_str_with_variant`] when the layout
+    /// variant is known.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Self::from_str_with_variant(s, RaiseLayoutVariant::Iso)
+    }
+}
+
+impl serde::Serialize for RaiseKeymap {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: serde::Serializer,
+    {
+        self.0
+            .iter()
+            .copied()
+            .enumerate()
+            .map(|(i, layer)| RaiseKeymapLayer {
+                layer_number: i as u8 + 1,
+                ..layer
+            })
+            .collect::<Vec<_>>()
+            .serialize(serializer)
+    }
+}
+
+impl RaiseKeymap {
+    /// Parses `keymap.custom` data, only keeping the keys physically present
+    /// on the given layout variant.
+    pub fn from_str_with_variant(
+        s: &str,
+        variant: RaiseLayoutVariant,
+    ) -> Result<Self, ParseKeymapError> {
+        let layers = s
+            .parse::<parsing::keymap::Keymap>()?
+            .iter()
+            .map(|layer_data| RaiseKeymapLayer::from_layer_data(layer_data, variant))
+            .collect();
+
+        Ok(Self(layers))
+    }
+
+    /// Converts this keymap into a form suitable for sending over to the keyboard
+    /// as the data of a `keymap.custom` command.
+    pub fn to_keymap_custom_data(&self) -> Result<String, KeymapDoesNotHave10LayersError> {
+        if self.0.len() != KEYMAP_CUSTOM_COMMAND_LAYERS {
+            return Err(KeymapDoesNotHave10LayersError);
+        };
+
+        let data = self
+            .0
+            .iter()
+            .map(|layer| {
+                layer
+                    .to_keymap_data()
+                    .map(|opt| opt.unwrap_or(KeyKind::from(0)))
+            })
+            .collect::<Vec<_>>();
+
+        let keymap = parsing::keymap::Keymap(data);
+
+        Ok(keymap.to_string())
+    }
+
+    /// Clears the layer to the provided key, keeping the layout variant of
+    /// the layer.
+    pub fn clear_layer_to(&mut self, layer: usize, key: KeyKind) -> Result<(), ClearLayerError> {
+        if layer == 0 {
+            return Err(ClearLayerError::ZeroIndexProvided);
+        }
+
+        let layer = layer - 1;
+
+        if self.len() <= layer {
+            return Err(ClearLayerError::LayerDoesNotExist);
+        }
+
+        self[layer] = RaiseKeymapLayer::new_cleared_to(key, self[layer].variant());
+
+        Ok(())
+    }
+
+    /// Drops or adds layers at the end of the keymap, so that it has the
+    /// provided number of layers, with new layers cleared to the key.
+    pub fn resize_to(&mut self, layers: usize, key: KeyKind) {
+        let variant = self
+            .first()
+            .map_or(RaiseLayoutVariant::Iso, RaiseKeymapLayer::variant);
+
+        self.resize(layers, RaiseKeymapLayer::new_cleared_to(key, variant));
+    }
+}
+
+/// A single human-readable Raise layer.
+#[derive(Clone, Copy, Debug, Eq, Serialize, Deserialize)]
+pub struct RaiseKeymapLayer {
+    /// A human-readable label for knowing what layer your editing in the
+    /// JSON file.
+    ///
+    /// **Note**: This number is only a UX aid, and is not taken
+    /// into account when deserializing from the config file.
+    #[serde(skip_deserializing)]
+    pub layer_number: u8,
+    /// Left half of the keyboard.
+    pub left: RaiseKeymapLeft,
+    /// Right half of the keyboard.
+    pub right: RaiseKeymapRight,
+}
+
+impl PartialEq for RaiseKeymapLayer {
+    fn eq(&self, other: &Self) -> bool {
+        self.left == other.left && self.right == other.right
+    }
+}
+
+impl std::hash::Hash for RaiseKeymapLayer {
+    fn hash<H: std::hash::Hasher>(&self, state: &mut H) {
+        self.left.hash(state);
+        self.right.hash(state);
+    }
+}
+
+impl RaiseKeymapLayer {
+    /// Creates a layer from raw keymap data, only keeping the keys
+    /// physically present on the given layout variant.
+    pub fn from_layer_data(layer_data: &RaiseLayerData, variant: RaiseLayoutVariant) -> Self {
+        let layout = variant.layout();
+        let key = |index: u8| layer_data[index as usize];
+
+        Self {
+            layer_number: 0,
+            left: RaiseKeymapLeft {
+                row_1: layout.left.row_1.map(key),
+                row_2: layout.left.row_2.map(key),
+                row_3: layout.left.row_3.map(key),
+                row_4: layout.left.row_4.map(key),
+                iso_key: layout.left.iso_key.map(key),
+                bottom: layout.left.bottom.map(key),
+                thumb_cluster: layout.left.thumb_cluster.map(key),
+            },
+            right: RaiseKeymapRight {
+                row_1: layout.right.row_1.map(key),
+                row_2: layout.right.row_2.map(key),
+                row_3: layout.right.row_3.map(key),
+                row_4: layout.right.row_4.map(key),
+                thumb_cluster: layout.right.thumb_cluster.map(key),
+                bottom: layout.right.bottom.map(key),
+            },
+        }
+    }
+
+    /// The layout variant this layer was made for, which is told apart by
+    /// the presence of the ISO key.
+    pub fn variant(&self) -> RaiseLayoutVariant {
+        match self.left.iso_key {
+            Some(_) => RaiseLayoutVariant::Iso,
+            None => RaiseLayoutVariant::Ansi,
+        }
+    }
+
+    /// Converts this layer into a form suitable for using with keymap commands.
+    ///
+    /// **Note**: This function returns [`Option<KeyKind>`], rather than [`KeyKind`].
+    /// This is done because a keymap layer must contain 80 keys, but the keyboard only
+    /// has 69 keys, or 70 for ISO keyboards. You should therefore pick a default
+    /// placeholder key for the missing ones, usually `u16::MIN` or `u16::MAX`.
+    pub fn to_keymap_data(&self) -> [Option<KeyKind>; KEYS_PER_LAYER] {
+        let layout = LAYOUT_ISO;
+        let mut data = [None; KEYS_PER_LAYER];
+
+        let left = &self.left;
+        let right = &self.right;
+
+        let indices = layout
+            .left
+            .row_1
+            .iter()
+            .zip(left.row_1)
+            .chain(layout.left.row_2.iter().zip(left.row_2))
+            .chain(layout.left.row_3.iter().zip(left.row_3))
+            .chain(layout.left.row_4.iter().zip(left.row_4))
+            .chain(layout.left.iso_key.iter().zip(left.iso_key))
+            .chain(layout.left.bottom.iter().zip(left.bottom))
+            .chain(layout.left.thumb_cluster.iter().zip(left.thumb_cluster))
+            .chain(layout.right.row_1.iter().zip(right.row_1))
+            .chain(layout.right.row_2.iter().zip(right.row_2))
+            .chain(layout.right.row_3.iter().zip(right.row_3))
+            .chain(layout.right.row_4.iter().zip(right.row_4))
+            .chain(layout.right.thumb_cluster.iter().zip(right.thumb_cluster))
+            .chain(layout.right.bottom.iter().zip(right.bottom));
+
+        for (index, key) in indices {
+            data[*index as usize] = Some(key);
+        }
+
+        data
+    }
+
+    /// Clears the entire layer to the provided key.
+    pub fn new_cleared_to(key: KeyKind, variant: RaiseLayoutVariant) -> Self {
+        let keys: RaiseLayerData = array::from_fn(|_| key);
+
+        RaiseKeymapLayer::from_layer_data(&keys, variant)
+    }
+}
+
+/// Left half human-readable Raise keymap.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct RaiseKeymapLeft {
+    /// Row 1.
+    pub row_1: [KeyKind; 7],
+    /// Row 2.
+    pub row_2: [KeyKind; 6],
+    /// Row 3.
+    pub row_3: [KeyKind; 6],
+    /// Row 4, without the ISO key.
+    pub row_4: [KeyKind; 6],
+    /// The key between the left shift and `Z`, only present on ISO
+    /// keyboards.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub iso_key: Option<KeyKind>,
+    /// Bottom row, left of the thumb cluster.
+    pub bottom: [KeyKind; 4],
+    /// Thumb cluster, from left to right.
+    pub thumb_cluster: [KeyKind; 4],
+}
+
+/// Right half human-readable Raise keymap.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct RaiseKeymapRight {
+    /// Row 1.
+    pub row_1: [KeyKind; 7],
+    /// Row 2.
+    pub row_2: [KeyKind; 8],
+    /// Row 3.
+    pub row_3: [KeyKind; 7],
+    /// Row 4.
+    pub row_4: [KeyKind; 6],
+    /// Thumb cluster, from left to right.
+    pub thumb_cluster: [KeyKind; 4],
+    /// Bottom row, right of the thumb cluster.
+    pub bottom: [KeyKind; 4],
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A QWERTY base layer recorded from an ISO keyboard, followed by a
+    /// function layer, and 8 blank layers.
+    const KEYMAP_DATA: &str = "41 30 31 32 33 34 35 0 0 36 37 38 39 45 46 42 43 20 26 8 21 23 0 0 28 24 12 18 19 47 48 40 57 4 22 7 9 10 0 0 0 11 13 14 15 51 52 50 225 100 29 27 6 25 5 0 0 0 17 16 54 55 56 229 224 227 226 17450 44 42 76 43 40 41 17451 81 230 231 101 228 53 58 59 60 61 62 63 0 0 64 65 66 67 68 69 76 0 0 0 82 0 0 0 0 0 74 75 0 0 0 0 0 0 80 81 79 0 0 0 0 0 77 78 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 ";
+
+    fn key(code: u16) -> KeyKind {
+        KeyKind::from(code)
+    }
+
+    #[test]
+    fn layouts_cover_every_key_once() {
+        for (variant, key_count) in [
+            (RaiseLayoutVariant::Ansi, 69),
+            (RaiseLayoutVariant::Iso, 70),
+        ] {
+            let layer = RaiseKeymapLayer::new_cleared_to(key(4), variant);
+
+            let data = layer.to_keymap_data();
+
+            assert_eq!(data.iter().flatten().count(), key_count, "{variant}");
+        }
+    }
+
+    #[test]
+    fn iso_key_is_only_kept_for_iso_keyboards() {
+        let ansi =
+            RaiseKeymap::from_str_with_variant(KEYMAP_DATA, RaiseLayoutVariant::Ansi).unwrap();
+        let iso = RaiseKeymap::from_str_with_variant(KEYMAP_DATA, RaiseLayoutVariant::Iso).unwrap();
+
+        assert_eq!(ansi.len(), KEYMAP_CUSTOM_COMMAND_LAYERS);
+        assert_eq!(ansi[0].left.iso_key, None);
+        assert_eq!(iso[0].left.iso_key, Some(key(100)));
+        assert_eq!(ansi[0].variant(), RaiseLayoutVariant::Ansi);
+        assert_eq!(iso[0].variant(), RaiseLayoutVariant::Iso);
+
+        // Escape and 1 through 6
+        assert_eq!(ansi[0].left.row_1, [41, 30, 31, 32, 33, 34, 35].map(key));
+        // Left shift, then Z through B
+        assert_eq!(iso[0].left.row_4, [225, 29, 27, 6, 25, 5].map(key));
+        // Y through the ISO enter key
+        assert_eq!(
+            iso[0].right.row_2,
+            [28, 24, 12, 18, 19, 47, 48, 40].map(key)
+        );
+    }
+
+    #[test]
+    fn keymap_round_trips_from_str() {
+        let keymap = KEYMAP_DATA.parse::<RaiseKeymap>().unwrap();
+
+        assert_eq!(
+            format!("{} ", keymap.to_keymap_custom_data().unwrap()),
+            KEYMAP_DATA
+        );
+    }
+
+    #[test]
+    fn keymap_round_trips_through_json() {
+        let keymap =
+            RaiseKeymap::from_str_with_variant(KEYMAP_DATA, RaiseLayoutVariant::Ansi).unwrap();
+
+        let json = serde_json::to_string(&keymap).unwrap();
+
+        assert!(!json.contains("iso_key"));
+        assert_eq!(serde_json::from_str::<RaiseKeymap>(&json).unwrap(), keymap);
+    }
+
+    #[test]
+    fn layout_variant_parses_firmware_output() {
+        assert_eq!(
+            "ANSI".parse::<RaiseLayoutVariant>().unwrap(),
+            RaiseLayoutVariant::Ansi
+        );
+        assert_eq!(
+            "ISO\n".parse::<RaiseLayoutVariant>().unwrap(),
+            RaiseLayoutVariant::Iso
+        );
+        assert!("JIS".parse::<RaiseLayoutVariant>().is_err());
+    }
+
+    #[tokio::test]
+    async fn virtual_keyboard_round_trips_keymap() {
+        let mut raise = RaiseKeyboard::new_virtual(RaiseLayoutVariant::Iso);
+
+        let keymap = KEYMAP_DATA.parse::<RaiseKeymap>().unwrap();
+
+        raise.apply_custom_keymap(&keymap).await.unwrap();
+
+        assert_eq!(raise.get_custom_keymap().await.unwrap(), keymap);
+    }
+}
@@ -0,0 +1,141 @@
+//! Provides the [`Raise2Keyboard`] struct for programatically interacting
+//! with the Dygma Raise 2.
+//!
+//! The Raise 2 shares its physical layout, and therefore its keymap types,
+//! with the original [Raise](super::raise), which are re-exported here.
+
+pub use super::raise::{
+    KEYMAP_CUSTOM_COMMAND_LAYERS, KEYS_PER_LAYER, LAYOUT_ANSI, LAYOUT_ISO, LEDS_PER_LAYER,
+    RaiseKeymap, RaiseKeymapLayer, RaiseKeymapLeft, RaiseKeymapRight, RaiseLayerData, RaiseLayout,
+    RaiseLayoutLeft, RaiseLayoutRight, RaiseLayoutVariant,
+};
+use super::{
+    AnyKeymap, ApplyKeymapError, DynFocusApi, GetKeymapError, Keyboard, Model, ModelDescriptor,
+    ModelLayout, raise::RaiseKeyboard,
+};
+use crate::focus_api::{
+    Capabilities, ConnectionEvent, CreateHidFoducApiError, DeviceSelector, GetCapabilitiesError,
+    HidFocusApi, SerialPortFocusApi,
+};
+pub use error::*;
+
+mod error {
+    use super::*;
+
+    /// Error returned when creating a handle to the keyboard.
+    #[derive(Debug, Display, From, Error)]
+    #[display("failed to create handle to the Dygma Raise 2 keyboard: {_0}")]
+    pub struct CreateRaise2KeyboardError(CreateHidFoducApiError);
+}
+
+/// A handle to the Dygma Raise 2 keyboard, allowing for programatic control.
+///
+/// Everything but connecting works the same as on the original Raise, so
+/// this dereferences to a [`RaiseKeyboard`].
+#[derive(Debug, Deref, DerefMut)]
+pub struct Raise2Keyboard(RaiseKeyboard);
+
+impl Raise2Keyboard {
+    const HID_PRODUCT_ID: u16 = 0x0022;
+    const PRODUCT_NAME: &str = "Raise2";
+
+    /// The memory size of the superkey map.
+    pub const SUPERKEY_MEMORY_SIZE: usize = RaiseKeyboard::SUPERKEY_MEMORY_SIZE;
+
+    /// The memory size of the macro map, in bytes.
+    pub const MACRO_MEMORY_SIZE: usize = RaiseKeyboard::MACRO_MEMORY_SIZE;
+
+    /// Static description of the Raise 2.
+    pub const DESCRIPTOR: ModelDescriptor = ModelDescriptor {
+        model: Model::Raise2,
+        name: "Raise 2",
+        product_ids: &[0x0021, Self::HID_PRODUCT_ID],
+        wireless: true,
+        layout: ModelLayout::Raise {
+            ansi: LAYOUT_ANSI,
+            iso: LAYOUT_ISO,
+        },
+        keys_per_layer: KEYS_PER_LAYER,
+        layers: KEYMAP_CUSTOM_COMMAND_LAYERS,
+        leds_per_layer: LEDS_PER_LAYER,
+        rgbw: true,
+        superkey_memory_size: Self::SUPERKEY_MEMORY_SIZE,
+        macro_memory_size: Self::MACRO_MEMORY_SIZE,
+    };
+
+    /// Creates a handle to the keyboard.
+    pub async fn new() -> Result<Self, CreateRaise2KeyboardError> {
+        Self::with_selector(&DeviceSelector::Any).await
+    }
+
+    /// Creates a handle to the keyboard chosen by the selector.
+    ///
+    /// Serial port connections are tried first, falling back to HID.
+    pub async fn with_selector(
+        selector: &DeviceSelector,
+    ) -> Result<Self, CreateRaise2KeyboardError> {
+        let sp_focus_api_res = SerialPortFocusApi::with_selector(
+            Self::PRODUCT_NAME,
+            RaiseKeyboard::BAUD_RATE,
+            selector,
+        )
+        .await;
+
+        let focus_api: DynFocusApi = match sp_focus_api_res {
+            Ok(sp) => sp.into(),
+            Err(err) => {
+                let hid = HidFocusApi::with_selector(Self::HID_PRODUCT_ID, selector).await?;
+
+                hid.emit_event(ConnectionEvent::FellBackToHid {
+                    reason: err.to_string(),
+                });
+
+                hid.into()
+            }
+        };
+
+        Ok(Self(RaiseKeyboard::from(focus_api)))
+    }
+
+    /// Creates a handle to a virtual keyboard emulated entirely in memory.
+    ///
+    /// The virtual keyboard has the given layout variant, and starts with a
+    /// blank keymap, and no superkeys or macros.
+    pub fn new_virtual(variant: RaiseLayoutVariant) -> Self {
+        let mock = RaiseKeyboard::virtual_focus_api(variant, &Self::DESCRIPTOR);
+
+        Self(RaiseKeyboard::from(DynFocusApi::from(mock)))
+    }
+}
+
+impl Keyboard for Raise2Keyboard {
+    fn descriptor(&self) -> &'static ModelDescriptor {
+        &Self::DESCRIPTOR
+    }
+
+    fn focus_api(&mut self) -> &mut DynFocusApi {
+        self
+    }
+
+    async fn capabilities(&mut self) -> Result<&Capabilities, GetCapabilitiesError> {
+        self.0.capabilities().await
+    }
+
+    async fn get_keymap(&mut self) -> Result<AnyKeymap, GetKeymapError> {
+        Ok(self.get_custom_keymap().await?.into())
+    }
+
+    async fn get_default_keymap(&mut self) -> Result<AnyKeymap, GetKeymapError> {
+        self.0.get_default_keymap().await
+    }
+
+    async fn apply_keymap(&mut self, keymap: &AnyKeymap) -> Result<(), ApplyKeymapError> {
+        match keymap {
+            AnyKeymap::Raise(keymap) => Ok(self.apply_custom_keymap(keymap).await?),
+            keymap => Err(ApplyKeymapError::WrongModel {
+                keymap: keymap.family(),
+                keyboard: self.descriptor().name,
+            }),
+        }
+    }
+}
@@ -0,0 +1,421 @@
+//! Provides typed access to the `settings.*` family of commands, and to the
+//! firmware version, shared by every keyboard model.
+//!
+//! The methods themselves live on the [`Keyboard`] trait, while this module
+//! holds the types they return, and the helpers used to read and write
+//! single-value settings, which other groups of commands reuse.
+
+use super::Keyboard;
+use crate::focus_api::{FocusApiConnection, RequireCommandError, RunCommandError};
+pub use error::*;
+use std::{fmt, str::FromStr};
+
+mod error {
+    use super::*;
+
+    /// Error returned when a value returned by the keyboard fails to parse.
+    #[derive(Clone, Debug, Display, Error)]
+    #[display("`{command}` returned `{value}`, which is not a valid {expected}")]
+    pub struct ParseSettingError {
+        /// The command which returned the value.
+        pub command: &'static str,
+        /// The value returned by the keyboard.
+        pub value: String,
+        /// What the value was expected to be.
+        pub expected: &'static str,
+    }
+
+    /// Error returned when a value is outside the range accepted by a
+    /// setting.
+    #[derive(Clone, Debug, Display, Error)]
+    #[display("{value} is out of range for `{command}`, expected {min} to {max}")]
+    pub struct SettingOutOfRangeError {
+        /// The command the value was meant for.
+        pub command: &'static str,
+        /// The rejected value.
+        pub value: u32,
+        /// The smallest accepted value.
+        pub min: u32,
+        /// The largest accepted value.
+        pub max: u32,
+    }
+
+    /// Error returned when reading a setting from the keyboard.
+    #[derive(Debug, Display, From, Error)]
+    pub enum GetSettingError {
+        /// The firmware does not support the setting.
+        #[display("{_0}")]
+        Unsupported(RequireCommandError),
+        /// Failed to run command.
+        #[display("{_0}")]
+        CommandFailed(RunCommandError),
+        /// The value returned by the keyboard failed to parse.
+        #[display("{_0}")]
+        ParsingFailure(ParseSettingError),
+    }
+
+    /// Error returned when changing a setting on the keyboard.
+    #[derive(Debug, Display, From, Error)]
+    pub enum SetSettingError {
+        /// The firmware does not support the setting.
+        #[display("{_0}")]
+        Unsupported(RequireCommandError),
+        /// The value is not accepted by the setting.
+        #[display("{_0}")]
+        OutOfRange(SettingOutOfRangeError),
+        /// Failed to run command.
+        #[display("{_0}")]
+        CommandFailed(RunCommandError),
+    }
+}
+
+/// Command used to get the firmware version.
+pub const VERSION_COMMAND_NAME: &str = "version";
+
+/// Command used to get the hardware version.
+pub const HARDWARE_VERSION_COMMAND_NAME: &str = "hardware.version";
+
+/// Command used to get the unique id of the keyboard's chip.
+pub const CHIP_ID_COMMAND_NAME: &str = "hardware.chip_id";
+
+/// Command used to get and set the layer the keyboard starts on.
+pub const DEFAULT_LAYER_COMMAND_NAME: &str = "settings.defaultLayer";
+
+/// Command used to check whether the stored settings are valid.
+pub const VALID_COMMAND_NAME: &str = "settings.valid?";
+
+/// Command used to get the version of the stored settings.
+pub const SETTINGS_VERSION_COMMAND_NAME: &str = "settings.version";
+
+/// Command used to get the checksum of the stored settings.
+pub const CRC_COMMAND_NAME: &str = "settings.crc";
+
+/// Command used to get and set how often the keyboard halves check on each
+/// other.
+pub const ALIVE_INTERVAL_COMMAND_NAME: &str = "settings.aliveInterval";
+
+/// The version of the firmware running on the keyboard, such as `v1.2.3`.
+///
+/// Anything following the version numbers, like `-beta` or `+build.5`, is
+/// kept as the suffix.
+#[derive(Clone, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct FirmwareVersion {
+    /// Major version number.
+    pub major: u32,
+    /// Minor version number.
+    pub minor: u32,
+    /// Patch version number.
+    pub patch: u32,
+    /// Whatever follows the version numbers, including the leading `-` or
+    /// `+`.
+    pub suffix: Option<String>,
+}
+
+impl fmt::Display for FirmwareVersion {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "v{}.{}.{}", self.major, self.minor, self.patch)?;
+
+        if let Some(suffix) = &self.suffix {
+            write!(f, "{suffix}")?;
+        }
+
+        Ok(())
+    }
+}
+
+impl FromStr for FirmwareVersion {
+    type Err = ParseSettingError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let err = || ParseSettingError {
+            command: VERSION_COMMAND_NAME,
+            value: s.to_owned(),
+            expected: "firmware version",
+        };
+
+        let version = s.trim();
+        let version = version.strip_prefix(['v', 'V']).unwrap_or(version);
+
+        let (numbers, suffix) = match version.find(['-', '+']) {
+            Some(i) => (&version[..i], Some(version[i..].to_owned())),
+            None => (version, None),
+        };
+
+        let mut numbers = numbers.split('.').map(str::parse::<u32>);
+
+        let (Some(Ok(major)), Some(Ok(minor)), Some(Ok(patch)), None) = (
+            numbers.next(),
+            numbers.next(),
+            numbers.next(),
+            numbers.next(),
+        ) else {
+            return Err(err());
+        };
+
+        Ok(Self {
+            major,
+            minor,
+            patch,
+            suffix,
+        })
+    }
+}
+
+impl serde::Serialize for FirmwareVersion {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: serde::Serializer,
+    {
+        self.to_string().serialize(serializer)
+    }
+}
+
+impl<'de> serde::Deserialize<'de> for FirmwareVersion {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: serde::Deserializer<'de>,
+    {
+        String::deserialize(deserializer)?
+            .parse()
+            .map_err(serde::de::Error::custom)
+    }
+}
+
+/// The checksums of the settings stored on the keyboard.
+///
+/// The firmware reports both the checksum computed from the stored settings
+/// and the one saved alongside them, which only match when the settings are
+/// intact.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SettingsCrc {
+    /// The checksum computed from the stored settings.
+    pub computed: u16,
+    /// The checksum saved alongside the settings.
+    pub stored: u16,
+}
+
+impl SettingsCrc {
+    /// Checks that the computed and stored checksums match.
+    pub fn is_valid(&self) -> bool {
+        self.computed == self.stored
+    }
+}
+
+impl fmt::Display for SettingsCrc {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:X}/{:X}", self.computed, self.stored)
+    }
+}
+
+impl FromStr for SettingsCrc {
+    type Err = ParseSettingError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let err = || ParseSettingError {
+            command: CRC_COMMAND_NAME,
+            value: s.to_owned(),
+            expected: "pair of checksums",
+        };
+
+        let (computed, stored) = s.trim().split_once('/').ok_or_else(err)?;
+
+        Ok(Self {
+            computed: u16::from_str_radix(computed.trim(), 16).map_err(|_| err())?,
+            stored: u16::from_str_radix(stored.trim(), 16).map_err(|_| err())?,
+        })
+    }
+}
+
+/// Every setting read by [`Keyboard::get_settings`].
+///
+/// Settings the firmware does not support are left as [`None`].
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Settings {
+    /// The version of the firmware, left out when it isn't in the
+    /// `v1.2.3` format.
+    pub firmware_version: Option<FirmwareVersion>,
+    /// The layer the keyboard starts on, indexed from 0.
+    pub default_layer: Option<u8>,
+    /// Whether the stored settings are valid.
+    pub valid: Option<bool>,
+    /// The version of the stored settings.
+    pub version: Option<u16>,
+    /// The checksums of the stored settings.
+    pub crc: Option<SettingsCrc>,
+    /// How often the keyboard halves check on each other, in milliseconds.
+    pub alive_interval: Option<u32>,
+}
+
+/// Reads the raw value of a setting, after checking that the firmware
+/// supports it.
+pub(crate) async fn read_setting<K>(
+    keyboard: &mut K,
+    command: &'static str,
+) -> Result<String, GetSettingError>
+where
+    K: Keyboard + ?Sized,
+{
+    keyboard.require_command(command).await?;
+
+    let value = keyboard.focus_api().run_command(command, None).await?;
+
+    Ok(value.trim().to_owned())
+}
+
+/// Reads a setting, and parses it into `T`.
+///
+/// `expected` describes the value in the error returned when parsing fails.
+pub(crate) async fn get_setting<K, T>(
+    keyboard: &mut K,
+    command: &'static str,
+    expected: &'static str,
+) -> Result<T, GetSettingError>
+where
+    K: Keyboard + ?Sized,
+    T: FromStr,
+{
+    let value = read_setting(keyboard, command).await?;
+
+    Ok(parse_setting(command, value, expected)?)
+}
+
+/// Parses a value returned by the keyboard.
+pub(crate) fn parse_setting<T>(
+    command: &'static str,
+    value: String,
+    expected: &'static str,
+) -> Result<T, ParseSettingError>
+where
+    T: FromStr,
+{
+    value.parse().map_err(|_| ParseSettingError {
+        command,
+        value,
+        expected,
+    })
+}
+
+/// Parses a boolean value, which the firmware sends as either `1`/`0` or
+/// `true`/`false`.
+pub(crate) fn parse_bool_setting(
+    command: &'static str,
+    value: String,
+) -> Result<bool, ParseSettingError> {
+    match value.as_str() {
+        "1" | "true" => Ok(true),
+        "0" | "false" => Ok(false),
+        _ => Err(ParseSettingError {
+            command,
+            value,
+            expected: "boolean",
+        }),
+    }
+}
+
+/// Writes a setting, after checking that the firmware supports it.
+pub(crate) async fn set_setting<K>(
+    keyboard: &mut K,
+    command: &'static str,
+    value: impl fmt::Display,
+) -> Result<(), SetSettingError>
+where
+    K: Keyboard + ?Sized,
+{
+    keyboard.require_command(command).await?;
+
+    keyboard
+        .focus_api()
+        .run_command(command, Some(&value.to_string()))
+        .await?;
+
+    Ok(())
+}
+
+/// Checks that the value is within the range accepted by a setting.
+pub(crate) fn check_setting_range(
+    command: &'static str,
+    value: u32,
+    min: u32,
+    max: u32,
+) -> Result<(), SettingOutOfRangeError> {
+    if (min..=max).contains(&value) {
+        return Ok(());
+    }
+
+    Err(SettingOutOfRangeError {
+        command,
+        value,
+        min,
+        max,
+    })
+}
+
+/// Turns the error for a setting the firmware doesn't support into [`None`],
+/// so that optional settings can be read without failing.
+pub fn if_supported<T>(res: Result<T, GetSettingError>) -> Result<Option<T>, GetSettingError> {
+    match res {
+        Ok(value) => Ok(Some(value)),
+        Err(GetSettingError::Unsupported(RequireCommandError::Unsupported(_))) => Ok(None),
+        Err(err) => Err(err),
+    }
+}
+
+/// Like [`if_supported`], but also turns values which fail to parse into
+/// [`None`], with a warning, for helpers which read many settings at once.
+pub(crate) fn if_readable<T>(
+    res: Result<T, GetSettingError>,
+) -> Result<Option<T>, GetSettingError> {
+    match res {
+        Err(GetSettingError::ParsingFailure(err)) => {
+            warn!("ignoring unreadable setting: {err}");
+
+            Ok(None)
+        }
+        res => if_supported(res),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn firmware_versions_parse() {
+        let version = "v1.2.3-beta".parse::<FirmwareVersion>().unwrap();
+
+        assert_eq!(
+            version,
+            FirmwareVersion {
+                major: 1,
+                minor: 2,
+                patch: 3,
+                suffix: Some("-beta".to_owned()),
+            }
+        );
+        assert_eq!(version.to_string(), "v1.2.3-beta");
+
+        let build = "v1.2.3+build.5".parse::<FirmwareVersion>().unwrap();
+
+        assert_eq!(build.to_string(), "v1.2.3+build.5");
+        assert_eq!(
+            serde_json::from_str::<FirmwareVersion>(&serde_json::to_string(&build).unwrap())
+                .unwrap(),
+            build
+        );
+        assert_eq!("0.90.1".parse::<FirmwareVersion>().unwrap().minor, 90);
+
+        assert!("v1.2".parse::<FirmwareVersion>().is_err());
+        assert!("garbage".parse::<FirmwareVersion>().is_err());
+    }
+
+    #[test]
+    fn settings_crc_parses() {
+        let crc = "1A2B/1a2b".parse::<SettingsCrc>().unwrap();
+
+        assert!(crc.is_valid());
+        assert_eq!(crc.to_string(), "1A2B/1A2B");
+
+        assert!(!"FFFF/0".parse::<SettingsCrc>().unwrap().is_valid());
+        assert!("FFFF".parse::<SettingsCrc>().is_err());
+    }
+}
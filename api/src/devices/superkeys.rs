@@ -0,0 +1,169 @@
+//! Provides the types used for the global superkey timings, set with the
+//! `superkeys.*` family of commands, and the superkeys config file which
+//! bundles them with a [`SuperkeyMap`].
+//!
+//! The methods themselves live on the [`Keyboard`](super::Keyboard) trait.
+
+use super::defy::SuperkeyMap;
+use crate::focus_api::MockFocusApi;
+
+/// Command used to get and set the superkey map.
+pub const MAP_COMMAND_NAME: &str = "superkeys.map";
+
+/// Command used to get and set how long a superkey waits for the next tap.
+pub const WAIT_TIME_COMMAND_NAME: &str = "superkeys.waittime";
+
+/// Command used to get and set how long a superkey must be held to trigger
+/// its hold action.
+pub const TIMEOUT_COMMAND_NAME: &str = "superkeys.timeout";
+
+/// Command used to get and set how often a held superkey repeats.
+pub const REPEAT_COMMAND_NAME: &str = "superkeys.repeat";
+
+/// Command used to get and set how long a superkey must be held before it
+/// starts repeating.
+pub const HOLD_START_COMMAND_NAME: &str = "superkeys.holdstart";
+
+/// Command used to get and set how much a superkey may overlap with the next
+/// key before it counts as held.
+pub const OVERLAP_COMMAND_NAME: &str = "superkeys.overlap";
+
+/// The largest overlap accepted by the firmware, in percent.
+pub const MAX_OVERLAP: u8 = 100;
+
+/// The global timings shared by every superkey.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct SuperkeySettings {
+    /// How long a superkey waits for the next tap, in milliseconds.
+    pub wait_time: u16,
+    /// How long a superkey must be held to trigger its hold action, in
+    /// milliseconds.
+    pub timeout: u16,
+    /// How often a held superkey repeats, in milliseconds.
+    pub repeat: u16,
+    /// How long a superkey must be held before it starts repeating, in
+    /// milliseconds.
+    pub hold_start: u16,
+    /// How much a superkey may overlap with the next key before it counts
+    /// as held, in percent.
+    pub overlap: u8,
+}
+
+/// The contents of a superkeys config file: the superkey map, and
+/// optionally the timings that go with it.
+///
+/// Files without timings are a plain array of superkeys, while files with
+/// timings are an object with `settings` and `superkeys` fields. Both are
+/// accepted when deserializing.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct SuperkeyConfig {
+    /// The global superkey timings.
+    pub settings: Option<SuperkeySettings>,
+    /// The superkeys.
+    pub superkeys: SuperkeyMap,
+}
+
+impl serde::Serialize for SuperkeyConfig {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: serde::Serializer,
+    {
+        #[derive(Serialize)]
+        struct WithSettings<'a> {
+            settings: &'a SuperkeySettings,
+            superkeys: &'a SuperkeyMap,
+        }
+
+        match &self.settings {
+            Some(settings) => WithSettings {
+                settings,
+                superkeys: &self.superkeys,
+            }
+            .serialize(serializer),
+            None => self.superkeys.serialize(serializer),
+        }
+    }
+}
+
+impl<'de> serde::Deserialize<'de> for SuperkeyConfig {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: serde::Deserializer<'de>,
+    {
+        #[derive(Deserialize)]
+        #[serde(untagged)]
+        enum Repr {
+            WithSettings {
+                settings: SuperkeySettings,
+                superkeys: SuperkeyMap,
+            },
+            Superkeys(SuperkeyMap),
+        }
+
+        let config = match Repr::deserialize(deserializer)? {
+            Repr::WithSettings {
+                settings,
+                superkeys,
+            } => Self {
+                settings: Some(settings),
+                superkeys,
+            },
+            Repr::Superkeys(superkeys) => superkeys.into(),
+        };
+
+        Ok(config)
+    }
+}
+
+impl From<SuperkeyMap> for SuperkeyConfig {
+    fn from(superkeys: SuperkeyMap) -> Self {
+        Self {
+            settings: None,
+            superkeys,
+        }
+    }
+}
+
+/// Adds the `superkeys.*` timing commands to a virtual keyboard, with the
+/// firmware's default values.
+pub(crate) fn with_virtual_superkey_settings(mock: MockFocusApi) -> MockFocusApi {
+    mock.with_command(WAIT_TIME_COMMAND_NAME, "1000")
+        .with_command(TIMEOUT_COMMAND_NAME, "250")
+        .with_command(REPEAT_COMMAND_NAME, "20")
+        .with_command(HOLD_START_COMMAND_NAME, "200")
+        .with_command(OVERLAP_COMMAND_NAME, "20")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn configs_with_and_without_settings_deserialize() {
+        let superkeys = r#"[{ "tap": null }]"#;
+
+        let config = serde_json::from_str::<SuperkeyConfig>(superkeys).unwrap();
+
+        assert_eq!(config.settings, None);
+        assert_eq!(config.superkeys.len(), 1);
+        assert!(serde_json::to_string(&config).unwrap().starts_with('['));
+
+        let config = serde_json::from_str::<SuperkeyConfig>(&format!(
+            r#"{{
+                "settings": {{ "wait_time": 500, "timeout": 250, "repeat": 20, "hold_start": 200, "overlap": 20 }},
+                "superkeys": {superkeys}
+            }}"#
+        ))
+        .unwrap();
+
+        assert_eq!(config.settings.unwrap().wait_time, 500);
+        assert_eq!(config.superkeys.len(), 1);
+
+        let json = serde_json::to_string(&config).unwrap();
+
+        assert_eq!(
+            serde_json::from_str::<SuperkeyConfig>(&json).unwrap(),
+            config
+        );
+    }
+}
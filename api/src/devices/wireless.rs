@@ -0,0 +1,214 @@
+//! Provides the types used for querying the battery, energy and wireless
+//! status of wireless keyboards, with the `wireless.*` family of commands.
+//!
+//! The methods themselves live on the [`Keyboard`](super::Keyboard) trait.
+
+use super::ModelDescriptor;
+use crate::focus_api::MockFocusApi;
+use std::{fmt, str::FromStr};
+
+/// Command used to get the battery level of the left half.
+pub const LEFT_BATTERY_LEVEL_COMMAND_NAME: &str = "wireless.battery.left.level";
+
+/// Command used to get the battery level of the right half.
+pub const RIGHT_BATTERY_LEVEL_COMMAND_NAME: &str = "wireless.battery.right.level";
+
+/// Command used to get the battery status of the left half.
+pub const LEFT_BATTERY_STATUS_COMMAND_NAME: &str = "wireless.battery.left.status";
+
+/// Command used to get the battery status of the right half.
+pub const RIGHT_BATTERY_STATUS_COMMAND_NAME: &str = "wireless.battery.right.status";
+
+/// Command used to get whether the power-saving mode is on.
+pub const SAVING_MODE_COMMAND_NAME: &str = "wireless.battery.savingMode";
+
+/// Command used to get the transmission power of the RF connection.
+pub const RF_POWER_COMMAND_NAME: &str = "wireless.rf.power";
+
+/// Command used to get whether the RF connection hops between channels.
+pub const RF_CHANNEL_HOP_COMMAND_NAME: &str = "wireless.rf.channelHop";
+
+/// Command used to list the Bluetooth devices the keyboard is paired with.
+pub const BLUETOOTH_DEVICES_COMMAND_NAME: &str = "wireless.bluetooth.devices";
+
+/// A half of a split keyboard.
+#[derive(Clone, Copy, Debug, Display, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Side {
+    /// The left half.
+    #[display("left")]
+    Left,
+    /// The right half.
+    #[display("right")]
+    Right,
+}
+
+impl Side {
+    /// Both halves, left first.
+    pub const ALL: [Self; 2] = [Self::Left, Self::Right];
+
+    pub(crate) fn battery_level_command(self) -> &'static str {
+        match self {
+            Self::Left => LEFT_BATTERY_LEVEL_COMMAND_NAME,
+            Self::Right => RIGHT_BATTERY_LEVEL_COMMAND_NAME,
+        }
+    }
+
+    pub(crate) fn battery_status_command(self) -> &'static str {
+        match self {
+            Self::Left => LEFT_BATTERY_STATUS_COMMAND_NAME,
+            Self::Right => RIGHT_BATTERY_STATUS_COMMAND_NAME,
+        }
+    }
+}
+
+/// Whether a battery is charging, as reported by the firmware.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum BatteryStatus {
+    /// Running on battery.
+    Discharging,
+    /// Plugged in and charging.
+    Charging,
+    /// Plugged in and fully charged.
+    Charged,
+    /// The battery is faulty.
+    Fault,
+    /// The half is not connected.
+    Disconnected,
+    /// A status code unknown to the SDK.
+    Unknown(u8),
+}
+
+impl fmt::Display for BatteryStatus {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Discharging => write!(f, "discharging"),
+            Self::Charging => write!(f, "charging"),
+            Self::Charged => write!(f, "charged"),
+            Self::Fault => write!(f, "fault"),
+            Self::Disconnected => write!(f, "disconnected"),
+            Self::Unknown(code) => write!(f, "unknown ({code})"),
+        }
+    }
+}
+
+impl From<u8> for BatteryStatus {
+    fn from(code: u8) -> Self {
+        match code {
+            0 => Self::Discharging,
+            1 => Self::Charging,
+            2 => Self::Charged,
+            3 => Self::Fault,
+            4 => Self::Disconnected,
+            code => Self::Unknown(code),
+        }
+    }
+}
+
+impl FromStr for BatteryStatus {
+    type Err = std::num::ParseIntError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        s.parse::<u8>().map(Self::from)
+    }
+}
+
+/// The charge of one half's battery.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct Battery {
+    /// The charge left, in percent.
+    pub level: u8,
+    /// Whether the battery is charging.
+    pub status: BatteryStatus,
+}
+
+/// The transmission power of the RF connection.
+#[derive(Clone, Copy, Debug, Display, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RfPower {
+    /// Low power, saving battery.
+    #[display("low")]
+    Low,
+    /// Medium power.
+    #[display("medium")]
+    Medium,
+    /// High power, for the longest range.
+    #[display("high")]
+    High,
+}
+
+impl FromStr for RfPower {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "0" => Ok(Self::Low),
+            "1" => Ok(Self::Medium),
+            "2" => Ok(Self::High),
+            _ => Err(()),
+        }
+    }
+}
+
+/// Everything read by
+/// [`Keyboard::get_wireless_status`](super::Keyboard::get_wireless_status).
+///
+/// Anything the firmware does not support, such as on wired keyboards, is
+/// left as [`None`].
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct WirelessStatus {
+    /// The battery of the left half.
+    pub left_battery: Option<Battery>,
+    /// The battery of the right half.
+    pub right_battery: Option<Battery>,
+    /// Whether the power-saving mode is on.
+    pub power_saving: Option<bool>,
+    /// The transmission power of the RF connection.
+    pub rf_power: Option<RfPower>,
+    /// Whether the RF connection hops between channels.
+    pub rf_channel_hop: Option<bool>,
+    /// The names of the Bluetooth devices the keyboard is paired with.
+    pub bluetooth_devices: Option<Vec<String>>,
+}
+
+/// Adds the `wireless.*` commands to a virtual keyboard, if the model is
+/// wireless, with both halves fully charged and nothing paired.
+pub(crate) fn with_virtual_wireless(
+    mock: MockFocusApi,
+    descriptor: &ModelDescriptor,
+) -> MockFocusApi {
+    if !descriptor.wireless {
+        return mock;
+    }
+
+    mock.with_read_only_command(LEFT_BATTERY_LEVEL_COMMAND_NAME, "100")
+        .with_read_only_command(RIGHT_BATTERY_LEVEL_COMMAND_NAME, "100")
+        .with_read_only_command(LEFT_BATTERY_STATUS_COMMAND_NAME, "2")
+        .with_read_only_command(RIGHT_BATTERY_STATUS_COMMAND_NAME, "2")
+        .with_command(SAVING_MODE_COMMAND_NAME, "0")
+        .with_command(RF_POWER_COMMAND_NAME, "1")
+        .with_command(RF_CHANNEL_HOP_COMMAND_NAME, "1")
+        .with_read_only_command(BLUETOOTH_DEVICES_COMMAND_NAME, "")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn battery_status_parses() {
+        assert_eq!(
+            "1".parse::<BatteryStatus>().unwrap(),
+            BatteryStatus::Charging
+        );
+        assert_eq!(
+            "9".parse::<BatteryStatus>().unwrap(),
+            BatteryStatus::Unknown(9)
+        );
+        assert!("-1".parse::<BatteryStatus>().is_err());
+
+        assert_eq!("2".parse::<RfPower>(), Ok(RfPower::High));
+        assert!("3".parse::<RfPower>().is_err());
+    }
+}
@@ -1,18 +1,51 @@
 //! Functions for interacting with the Focus API provided with Dygma
 //! firmware.
 
+mod capabilities;
+mod device_selector;
+mod discovery;
+mod events;
+mod mock;
 pub mod parsing;
+mod receive_buffer;
 mod serial_port;
 
+use std::time::Duration;
+
 use async_hid::{AsyncHidRead, AsyncHidWrite};
-use bytes::Bytes;
+pub use capabilities::{
+    Capabilities, GetCapabilitiesError, RequireCommandError, UnsupportedCommandError,
+};
+pub use device_selector::{DeviceSelector, ParseDeviceSelectorError};
+pub use discovery::{DiscoverDevicesError, DiscoveredDevice, Link, Transport, discover_devices};
+pub use events::ConnectionEvent;
+use events::EventSender;
+use futures::FutureExt;
+pub use mock::{MockFocusApi, MockHandler};
+use receive_buffer::ReceiveBuffer;
 use serial_port::{OpenSerialPortError, SerialPort};
-use tokio::io::AsyncWriteExt;
+use tokio::{
+    io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt},
+    sync::mpsc,
+};
 use tokio_stream::StreamExt;
-use tokio_util::io::ReaderStream;
 
 use crate::focus_api::parsing::focus_api::serialize_command;
 
+/// How long connections to physical devices wait for a command to complete
+/// before giving up with [`RunCommandError::TimedOut`].
+pub const DEFAULT_COMMAND_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// How long the device must stay quiet before the remains of an interrupted
+/// command are considered fully discarded.
+const RESYNC_QUIET_PERIOD: Duration = Duration::from_millis(200);
+
+/// Id of the reports used to talk to the device over HID.
+const HID_REPORT_ID: u8 = 5;
+
+/// Size of the device's HID output reports, not counting the report id.
+const HID_MAX_SEND_SIZE: usize = 200;
+
 /// Trait used to abstract over focus API connections.
 #[allow(async_fn_in_trait)]
 pub trait FocusApiConnection {
@@ -23,6 +56,53 @@ pub trait FocusApiConnection {
         data: Option<&str>,
     ) -> Result<String, RunCommandError>;
 
+    /// Executes commands and returns their response, failing with
+    /// [`RunCommandError::TimedOut`] if the command does not complete within
+    /// the provided timeout.
+    ///
+    /// Commands which time out, or whose future is dropped before completing,
+    /// leave the connection in a state which is cleaned up before the next
+    /// command is sent, so their late response is never mistaken for the
+    /// response to another command.
+    async fn run_command_with_timeout(
+        &mut self,
+        command: &str,
+        data: Option<&str>,
+        timeout: Duration,
+    ) -> Result<String, RunCommandError> {
+        tokio::time::timeout(timeout, self.run_command(command, data))
+            .await
+            .map_err(|_| RunCommandError::TimedOut)?
+    }
+
+    /// Discards any stale data received from the device, such as the remains
+    /// of a command which timed out, so it can't be mistaken for the response
+    /// to the next command.
+    ///
+    /// This is done automatically before running a command if the previous
+    /// one did not complete. The discarded data can be retrieved with
+    /// [`FocusApiConnection::take_unsolicited_data`].
+    async fn resync(&mut self) -> Result<(), RunCommandError> {
+        Ok(())
+    }
+
+    /// Takes the data received from the device which was not part of the
+    /// response to any command, such as data following a response, or the
+    /// remains of interrupted commands.
+    fn take_unsolicited_data(&mut self) -> Vec<u8> {
+        Vec::new()
+    }
+
+    /// Takes the receiving end of the connection's [`ConnectionEvent`]s, such
+    /// as transport caveats or resynchronisations, which is only available
+    /// once.
+    ///
+    /// Until taken, events are kept up to a limit, past which newer events
+    /// are dropped.
+    fn take_events(&mut self) -> Option<mpsc::Receiver<ConnectionEvent>> {
+        None
+    }
+
     /// Gets a list of available commands on the device.
     async fn available_commands(&mut self) -> Result<Vec<String>, GetCommandsError> {
         let cmds = self
@@ -34,6 +114,11 @@ pub trait FocusApiConnection {
 
         Ok(cmds)
     }
+
+    /// Gets what the firmware on the device supports.
+    async fn capabilities(&mut self) -> Result<Capabilities, GetCapabilitiesError> {
+        Capabilities::query(self).await
+    }
 }
 
 /// Error returned when running commands.
@@ -51,6 +136,9 @@ pub enum RunCommandError {
     /// The response stream completed before a response could be interpreted.
     #[display("response stream terminated while waiting for the response to complete")]
     ResponseStreamTerminatedPrematurely,
+    /// The device did not complete the command in time.
+    #[display("timed out waiting for the device to respond")]
+    TimedOut,
 }
 
 impl From<SerialPortRunCommandError> for RunCommandError {
@@ -74,6 +162,7 @@ impl From<HidRunCommandError> for RunCommandError {
             HidRunCommandError::SendingCommand(err) => Self::SendingCommand(err.into()),
             HidRunCommandError::RecievingResponse(err) => Self::RecievingResponse(err.into()),
             HidRunCommandError::UnexpectedResponse(err) => Self::UnexpectedResponse(err),
+            err @ HidRunCommandError::AckTimedOut => Self::SendingCommand(err.into()),
         }
     }
 }
@@ -119,6 +208,19 @@ pub enum CreateHidFoducApiError {
     ConnectingToDevice(async_hid::HidError),
 }
 
+/// Tracks how far the last command got, so the connection can be
+/// resynchronised if it was interrupted, e.g. by timing out.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+enum CommandState {
+    /// The last command completed, or no command was run yet.
+    #[default]
+    Idle,
+    /// The command was interrupted while being sent to the device.
+    Sending,
+    /// The command was interrupted while receiving its response.
+    Receiving,
+}
+
 /// error returned when running commands.
 #[derive(Debug, Display, Error)]
 pub enum HidRunCommandError {
@@ -129,20 +231,67 @@ pub enum HidRunCommandError {
     /// The response from the device could not be interpreted.
     #[display("received an unexpected response:\n{_0}")]
     UnexpectedResponse(parsing::focus_api::ParseResponseError),
+    /// The device did not acknowledge an output report in time.
+    #[display("the device did not acknowledge the output report in time")]
+    AckTimedOut,
 }
 
 /// Abstracts over a serial port connection to provide the firmware's
 /// Focus API, which is used for controlling the keyboard.
+///
+/// By default, this talks to the keyboard over a USB serial port, but any
+/// byte stream can be used with [`SerialPortFocusApi::from_stream`].
+///
+/// Commands time out after [`DEFAULT_COMMAND_TIMEOUT`], which can be changed
+/// with [`SerialPortFocusApi::set_timeout`].
 #[derive(Debug)]
-pub struct SerialPortFocusApi(SerialPort);
+pub struct SerialPortFocusApi<S = SerialPort> {
+    stream: S,
+    timeout: Option<Duration>,
+    state: CommandState,
+    buffer: ReceiveBuffer,
+    events: EventSender,
+}
 
-impl FocusApiConnection for SerialPortFocusApi {
+impl<S> FocusApiConnection for SerialPortFocusApi<S>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
     async fn run_command(
         &mut self,
         command: &str,
         data: Option<&str>,
     ) -> Result<String, RunCommandError> {
-        self.run_command(command, data).await.map_err(Into::into)
+        match self.timeout {
+            Some(timeout) => {
+                FocusApiConnection::run_command_with_timeout(self, command, data, timeout).await
+            }
+            None => self.run_command(command, data).await.map_err(Into::into),
+        }
+    }
+
+    async fn run_command_with_timeout(
+        &mut self,
+        command: &str,
+        data: Option<&str>,
+        timeout: Duration,
+    ) -> Result<String, RunCommandError> {
+        tokio::time::timeout(timeout, self.run_command(command, data))
+            .await
+            .map_err(|_| RunCommandError::TimedOut)?
+            .map_err(Into::into)
+    }
+
+    async fn resync(&mut self) -> Result<(), RunCommandError> {
+        self.resync().await.map_err(Into::into)
+    }
+
+    fn take_unsolicited_data(&mut self) -> Vec<u8> {
+        self.buffer.take_unsolicited()
+    }
+
+    fn take_events(&mut self) -> Option<mpsc::Receiver<ConnectionEvent>> {
+        self.events.take_receiver()
     }
 }
 
@@ -154,9 +303,44 @@ impl SerialPortFocusApi {
         product_name: &str,
         baud_rate: u32,
     ) -> Result<Self, CreateSerialPortFocusApiError> {
-        let sp = SerialPort::connect(Self::MANUFACTURER_NAME, product_name, baud_rate).await?;
+        Self::with_selector(product_name, baud_rate, &DeviceSelector::Any).await
+    }
+
+    /// Creates a Focus API instance connected to the device chosen by the
+    /// selector.
+    pub async fn with_selector(
+        product_name: &str,
+        baud_rate: u32,
+        selector: &DeviceSelector,
+    ) -> Result<Self, CreateSerialPortFocusApiError> {
+        let sp =
+            SerialPort::connect(Self::MANUFACTURER_NAME, product_name, baud_rate, selector).await?;
 
-        Ok(Self(sp))
+        Ok(Self::from_stream(sp))
+    }
+}
+
+impl<S> SerialPortFocusApi<S>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    /// Creates a Focus API instance which talks to the device over an
+    /// arbitrary byte stream, such as a pseudo-terminal, or an in-memory
+    /// [`tokio::io::duplex`] pipe.
+    pub fn from_stream(stream: S) -> Self {
+        Self {
+            stream,
+            timeout: Some(DEFAULT_COMMAND_TIMEOUT),
+            state: CommandState::Idle,
+            buffer: ReceiveBuffer::default(),
+            events: EventSender::default(),
+        }
+    }
+
+    /// Sets how long commands may take before failing with
+    /// [`RunCommandError::TimedOut`], or disables the timeout if `None`.
+    pub fn set_timeout(&mut self, timeout: Option<Duration>) {
+        self.timeout = timeout;
     }
 
     /// Executes commands and returns their response.
@@ -165,69 +349,209 @@ impl SerialPortFocusApi {
         command: &str,
         data: Option<&str>,
     ) -> Result<String, SerialPortRunCommandError> {
-        let port = &mut self.0;
+        if self.state == CommandState::Idle {
+            self.collect_unsolicited_data()?;
+        } else {
+            self.resync().await?;
+        }
 
         let data_to_send = serialize_command(command, data);
 
-        port.write_all(data_to_send.as_bytes())
+        self.state = CommandState::Sending;
+
+        self.stream
+            .write_all(data_to_send.as_bytes())
             .await
             .map_err(SerialPortRunCommandError::SendingCommand)?;
 
-        let mut stream = ReaderStream::new(port);
+        self.state = CommandState::Receiving;
 
-        let mut buf = Bytes::new();
+        let mut buf = [0; 1024];
 
-        while let Some(chunk_res) = stream.next().await {
-            let chunk = chunk_res.map_err(SerialPortRunCommandError::RecievingResponse)?;
+        loop {
+            if let Some(res) = self
+                .buffer
+                .next_response()
+                .map_err(SerialPortRunCommandError::UnexpectedResponse)?
+            {
+                self.state = CommandState::Idle;
 
-            buf = Bytes::from([buf.as_ref(), chunk.as_ref()].concat());
+                return Ok(res);
+            }
 
-            let data = buf.as_ref();
-            let data = match str::from_utf8(data) {
-                Ok(data) => data,
-                Err(err) => {
-                    debug!(
-                        "data received is not utf8, retrying when more data is available\
-                        \n{err}"
-                    );
-                    continue;
-                }
-            };
+            let bytes_read = self
+                .stream
+                .read(&mut buf)
+                .await
+                .map_err(SerialPortRunCommandError::RecievingResponse)?;
 
-            return match data
-                .parse::<parsing::focus_api::FocusApiCommandResponse>()
-                .map(|res| res.into_inner())
-            {
-                Ok(res) => Ok(res),
-                Err(parsing::focus_api::ParseResponseError::Incomplete) => continue,
-                Err(err) => return Err(SerialPortRunCommandError::UnexpectedResponse(err)),
-            };
+            if bytes_read == 0 {
+                return Err(SerialPortRunCommandError::ResponseStreamTerminatedPrematurely);
+            }
+
+            self.buffer.extend(&buf[..bytes_read]);
+        }
+    }
+
+    /// Discards all data received from the device which is not part of a
+    /// response yet, until the device stays quiet for a while.
+    async fn resync(&mut self) -> Result<(), SerialPortRunCommandError> {
+        // Terminate the partially sent command, otherwise the firmware
+        // would read the next command as data for it
+        if self.state == CommandState::Sending {
+            self.stream
+                .write_all(b"\n")
+                .await
+                .map_err(SerialPortRunCommandError::SendingCommand)?;
         }
 
-        Err(SerialPortRunCommandError::ResponseStreamTerminatedPrematurely)
+        let mut buf = [0; 1024];
+
+        while let Ok(res) =
+            tokio::time::timeout(RESYNC_QUIET_PERIOD, self.stream.read(&mut buf)).await
+        {
+            let bytes_read = res.map_err(SerialPortRunCommandError::RecievingResponse)?;
+
+            if bytes_read == 0 {
+                break;
+            }
+
+            self.buffer.extend(&buf[..bytes_read]);
+        }
+
+        let discarded = self.buffer.discard();
+        self.state = CommandState::Idle;
+
+        self.events
+            .emit(ConnectionEvent::Resynchronised { discarded });
+
+        Ok(())
+    }
+
+    /// Sets aside the data the device sent since the last response as
+    /// unsolicited, without waiting for more to arrive.
+    fn collect_unsolicited_data(&mut self) -> Result<(), SerialPortRunCommandError> {
+        let mut buf = [0; 1024];
+
+        while let Some(res) = self.stream.read(&mut buf).now_or_never() {
+            let bytes_read = res.map_err(SerialPortRunCommandError::RecievingResponse)?;
+
+            if bytes_read == 0 {
+                break;
+            }
+
+            self.buffer.extend(&buf[..bytes_read]);
+        }
+
+        let len = self.buffer.discard();
+
+        if len > 0 {
+            self.events.emit(ConnectionEvent::UnsolicitedData { len });
+        }
+
+        Ok(())
+    }
+}
+
+/// Controls how commands are split into output reports and paced when sent
+/// over HID.
+///
+/// Bluetooth connections can't keep up with output reports sent
+/// back-to-back, so large payloads such as `keymap.custom` or
+/// `superkeys.map` need to be paced for the device to receive them intact.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct HidFlowControl {
+    /// Maximum number of command bytes sent in each output report, not
+    /// counting the leading report id.
+    ///
+    /// Clamped between 1 and 200, the size of the device's output reports.
+    pub chunk_size: usize,
+    /// How long to wait after sending an output report before sending the
+    /// next one.
+    pub report_delay: Duration,
+    /// If set, wait for the device to send an input report after each output
+    /// report, for at most this long, before sending the next one.
+    ///
+    /// Any data in the acknowledging report is kept as part of the response.
+    pub ack_timeout: Option<Duration>,
+}
+
+impl Default for HidFlowControl {
+    fn default() -> Self {
+        Self {
+            chunk_size: HID_MAX_SEND_SIZE,
+            report_delay: Duration::from_millis(20),
+            ack_timeout: None,
+        }
     }
 }
 
 /// Abstracts over a HID connection to provide the firmware's
 /// Focus API, which is used for controlling the keyboard.
 ///
-/// Note that this works also over BTLE.
+/// Note that this works also over BTLE. Commands are sent according to the
+/// connection's [`HidFlowControl`], which can be changed with
+/// [`HidFocusApi::set_flow_control`].
+///
+/// By default, this talks to a HID device found on the system, but any
+/// report reader and writer can be used with [`HidFocusApi::from_parts`].
+///
+/// Commands time out after [`DEFAULT_COMMAND_TIMEOUT`], which can be changed
+/// with [`HidFocusApi::set_timeout`].
 #[derive(Debug)]
-pub struct HidFocusApi {
-    _device: async_hid::Device,
+pub struct HidFocusApi<R = async_hid::DeviceReader, W = async_hid::DeviceWriter> {
+    _device: Option<async_hid::Device>,
     #[debug(ignore)]
-    reader: async_hid::DeviceReader,
+    reader: R,
     #[debug(ignore)]
-    writer: async_hid::DeviceWriter,
+    writer: W,
+    flow_control: HidFlowControl,
+    timeout: Option<Duration>,
+    state: CommandState,
+    buffer: ReceiveBuffer,
+    events: EventSender,
 }
 
-impl FocusApiConnection for HidFocusApi {
+impl<R, W> FocusApiConnection for HidFocusApi<R, W>
+where
+    R: AsyncHidRead,
+    W: AsyncHidWrite,
+{
     async fn run_command(
         &mut self,
         command: &str,
         data: Option<&str>,
     ) -> Result<String, RunCommandError> {
-        self.run_command(command, data).await.map_err(Into::into)
+        match self.timeout {
+            Some(timeout) => {
+                FocusApiConnection::run_command_with_timeout(self, command, data, timeout).await
+            }
+            None => self.run_command(command, data).await.map_err(Into::into),
+        }
+    }
+
+    async fn run_command_with_timeout(
+        &mut self,
+        command: &str,
+        data: Option<&str>,
+        timeout: Duration,
+    ) -> Result<String, RunCommandError> {
+        tokio::time::timeout(timeout, self.run_command(command, data))
+            .await
+            .map_err(|_| RunCommandError::TimedOut)?
+            .map_err(Into::into)
+    }
+
+    async fn resync(&mut self) -> Result<(), RunCommandError> {
+        self.resync().await.map_err(Into::into)
+    }
+
+    fn take_unsolicited_data(&mut self) -> Vec<u8> {
+        self.buffer.take_unsolicited()
+    }
+
+    fn take_events(&mut self) -> Option<mpsc::Receiver<ConnectionEvent>> {
+        self.events.take_receiver()
     }
 }
 
@@ -235,11 +559,17 @@ impl HidFocusApi {
     const VENDOR_ID: u16 = 13807;
     const USAGE_ID: u16 = 1;
     const USAGE_PAGE: u16 = 65280;
-    const REPORT_ID: u8 = 5;
-    const MAX_SEND_SIZE: usize = 200;
 
     /// Opens a connection to the requested Dygma device.
     pub async fn new(product_id: u16) -> Result<Self, CreateHidFoducApiError> {
+        Self::with_selector(product_id, &DeviceSelector::Any).await
+    }
+
+    /// Opens a connection to the requested Dygma device chosen by the selector.
+    pub async fn with_selector(
+        product_id: u16,
+        selector: &DeviceSelector,
+    ) -> Result<Self, CreateHidFoducApiError> {
         let backend = async_hid::HidBackend::default();
 
         let device_stream = backend
@@ -252,7 +582,7 @@ impl HidFocusApi {
                     Self::USAGE_ID,
                     Self::VENDOR_ID,
                     product_id,
-                )
+                ) && selector.matches_hid_device(device)
             });
 
         futures::pin_mut!(device_stream);
@@ -267,21 +597,51 @@ impl HidFocusApi {
             .await
             .map_err(CreateHidFoducApiError::ConnectingToDevice)?;
 
-        eprintln!(
-            "Warning:\n\
-            You are connected to the keyboard via Bluetooth, \
-            which is currently failing to send commands with large data payloads.\n\
-            \n\
-            Please reconnect with wired or wireless RF and try again if the \
-            keyboard becomes unresponsive after this operation.\n\
-            \n\
-            Nothing bad will happen, just restart the keyboard if it becomes unresponsive."
-        );
-        Ok(Self {
-            _device: device,
+        let focus_api = Self {
+            _device: Some(device),
+            ..Self::from_parts(reader, writer)
+        };
+
+        focus_api.events.emit(ConnectionEvent::SlowTransport);
+
+        Ok(focus_api)
+    }
+}
+
+impl<R, W> HidFocusApi<R, W>
+where
+    R: AsyncHidRead,
+    W: AsyncHidWrite,
+{
+    /// Creates a Focus API instance which talks to the device through the
+    /// provided input report reader and output report writer.
+    pub fn from_parts(reader: R, writer: W) -> Self {
+        Self {
+            _device: None,
             reader,
             writer,
-        })
+            flow_control: HidFlowControl::default(),
+            timeout: Some(DEFAULT_COMMAND_TIMEOUT),
+            state: CommandState::Idle,
+            buffer: ReceiveBuffer::default(),
+            events: EventSender::default(),
+        }
+    }
+
+    /// Sends an event to whoever receives the connection's events.
+    pub(crate) fn emit_event(&self, event: ConnectionEvent) {
+        self.events.emit(event);
+    }
+
+    /// Sets how commands are split into output reports and paced.
+    pub fn set_flow_control(&mut self, flow_control: HidFlowControl) {
+        self.flow_control = flow_control;
+    }
+
+    /// Sets how long commands may take before failing with
+    /// [`RunCommandError::TimedOut`], or disables the timeout if `None`.
+    pub fn set_timeout(&mut self, timeout: Option<Duration>) {
+        self.timeout = timeout;
     }
 
     /// Executes commands and returns their response.
@@ -290,23 +650,34 @@ impl HidFocusApi {
         command: &str,
         data: Option<&str>,
     ) -> Result<String, HidRunCommandError> {
+        if self.state == CommandState::Idle {
+            self.collect_unsolicited_data()?;
+        } else {
+            self.resync().await?;
+        }
+
         let data_to_send = serialize_command(command, data).into_bytes();
 
-        for chunk in data_to_send.chunks(Self::MAX_SEND_SIZE) {
-            let data = [&[Self::REPORT_ID], chunk].concat();
+        self.state = CommandState::Sending;
 
-            self.writer
-                .write_output_report(data.as_slice())
-                .await
-                .map_err(HidRunCommandError::SendingCommand)?;
-        }
+        self.send(&data_to_send).await?;
 
-        let mut res = Vec::new();
+        self.state = CommandState::Receiving;
 
         // We need MAX_SEND_SIZE + 1 because of the leading report id byte
-        let mut buf = [0; Self::MAX_SEND_SIZE + 1];
+        let mut buf = [0; HID_MAX_SEND_SIZE + 1];
 
         loop {
+            if let Some(res) = self
+                .buffer
+                .next_response()
+                .map_err(HidRunCommandError::UnexpectedResponse)?
+            {
+                self.state = CommandState::Idle;
+
+                return Ok(res);
+            }
+
             let bytes_read = self
                 .reader
                 .read_input_report(&mut buf)
@@ -314,27 +685,455 @@ impl HidFocusApi {
                 .map_err(HidRunCommandError::RecievingResponse)?;
 
             // Skip the first byte, as it's the report id
-            res.extend_from_slice(&buf[1..bytes_read]);
-
-            let str_res = match str::from_utf8(res.as_ref()) {
-                Ok(data) => data,
-                Err(err) => {
-                    debug!(
-                        "data received is not utf8, retrying when more data is available\
-                        \n{err}"
-                    );
-                    continue;
+            self.buffer
+                .extend(buf.get(1..bytes_read).unwrap_or_default());
+        }
+    }
+
+    /// Sends the data as a series of output reports, following the flow
+    /// control settings.
+    async fn send(&mut self, data: &[u8]) -> Result<(), HidRunCommandError> {
+        let HidFlowControl {
+            chunk_size,
+            report_delay,
+            ack_timeout,
+        } = self.flow_control;
+
+        let mut chunks = data
+            .chunks(chunk_size.clamp(1, HID_MAX_SEND_SIZE))
+            .peekable();
+
+        if chunks.len() > 1 {
+            self.events.emit(ConnectionEvent::SendingPaced {
+                len: data.len(),
+                reports: chunks.len(),
+            });
+        }
+
+        while let Some(chunk) = chunks.next() {
+            let report = [&[HID_REPORT_ID], chunk].concat();
+
+            self.writer
+                .write_output_report(report.as_slice())
+                .await
+                .map_err(HidRunCommandError::SendingCommand)?;
+
+            // The response follows the last report, so there's nothing
+            // left to wait for
+            if chunks.peek().is_none() {
+                break;
+            }
+
+            if let Some(ack_timeout) = ack_timeout {
+                let mut buf = [0; HID_MAX_SEND_SIZE + 1];
+
+                let bytes_read =
+                    tokio::time::timeout(ack_timeout, self.reader.read_input_report(&mut buf))
+                        .await
+                        .map_err(|_| HidRunCommandError::AckTimedOut)?
+                        .map_err(HidRunCommandError::RecievingResponse)?;
+
+                self.buffer
+                    .extend(buf.get(1..bytes_read).unwrap_or_default());
+            }
+
+            if !report_delay.is_zero() {
+                tokio::time::sleep(report_delay).await;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Discards all data received from the device which is not part of a
+    /// response yet, until the device stays quiet for a while.
+    async fn resync(&mut self) -> Result<(), HidRunCommandError> {
+        // Terminate the partially sent command, otherwise the firmware
+        // would read the next command as data for it
+        if self.state == CommandState::Sending {
+            self.writer
+                .write_output_report(&[HID_REPORT_ID, b'\n'])
+                .await
+                .map_err(HidRunCommandError::SendingCommand)?;
+        }
+
+        let mut buf = [0; HID_MAX_SEND_SIZE + 1];
+
+        while let Ok(res) =
+            tokio::time::timeout(RESYNC_QUIET_PERIOD, self.reader.read_input_report(&mut buf)).await
+        {
+            let bytes_read = res.map_err(HidRunCommandError::RecievingResponse)?;
+
+            self.buffer
+                .extend(buf.get(1..bytes_read).unwrap_or_default());
+        }
+
+        let discarded = self.buffer.discard();
+        self.state = CommandState::Idle;
+
+        self.events
+            .emit(ConnectionEvent::Resynchronised { discarded });
+
+        Ok(())
+    }
+
+    /// Sets aside the data the device sent since the last response as
+    /// unsolicited, without waiting for more to arrive.
+    fn collect_unsolicited_data(&mut self) -> Result<(), HidRunCommandError> {
+        let mut buf = [0; HID_MAX_SEND_SIZE + 1];
+
+        while let Some(res) = self.reader.read_input_report(&mut buf).now_or_never() {
+            let bytes_read = res.map_err(HidRunCommandError::RecievingResponse)?;
+
+            self.buffer
+                .extend(buf.get(1..bytes_read).unwrap_or_default());
+        }
+
+        let len = self.buffer.discard();
+
+        if len > 0 {
+            self.events.emit(ConnectionEvent::UnsolicitedData { len });
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::{
+        io::{AsyncBufReadExt, BufReader, DuplexStream},
+        sync::mpsc,
+        task::JoinHandle,
+        time::Instant,
+    };
+
+    /// Pretends to be the firmware, answering a single command by sending
+    /// `response` in chunks of `chunk_size` bytes.
+    fn spawn_fake_firmware(
+        chunk_size: usize,
+        expected_request: &'static str,
+        response: &'static [u8],
+    ) -> SerialPortFocusApi<DuplexStream> {
+        let (host, device) = tokio::io::duplex(chunk_size);
+
+        tokio::spawn(async move {
+            let mut device = BufReader::new(device);
+
+            let mut request = String::new();
+            device.read_line(&mut request).await.unwrap();
+
+            assert_eq!(request, expected_request);
+
+            for chunk in response.chunks(chunk_size) {
+                device.write_all(chunk).await.unwrap();
+                tokio::task::yield_now().await;
+            }
+        });
+
+        SerialPortFocusApi::from_stream(host)
+    }
+
+    /// Output report writer which forwards reports to the fake firmware.
+    struct MockHidWriter(mpsc::UnboundedSender<Vec<u8>>);
+
+    impl AsyncHidWrite for MockHidWriter {
+        fn write_output_report<'a>(
+            &'a mut self,
+            buf: &'a [u8],
+        ) -> impl Future<Output = async_hid::HidResult<()>> + Send + 'a {
+            let res = self
+                .0
+                .send(buf.to_vec())
+                .map_err(|_| async_hid::HidError::Disconnected);
+
+            std::future::ready(res)
+        }
+    }
+
+    /// Input report reader fed by the fake firmware.
+    struct MockHidReader(mpsc::UnboundedReceiver<Vec<u8>>);
+
+    impl AsyncHidRead for MockHidReader {
+        async fn read_input_report(&mut self, buf: &mut [u8]) -> async_hid::HidResult<usize> {
+            let report = self
+                .0
+                .recv()
+                .await
+                .ok_or(async_hid::HidError::Disconnected)?;
+
+            buf[..report.len()].copy_from_slice(&report);
+
+            Ok(report.len())
+        }
+    }
+
+    /// Output reports received by the fake firmware, and when they arrived.
+    type ReceivedReports = Vec<(Instant, Vec<u8>)>;
+
+    /// Pretends to be the firmware over HID, answering a single command with
+    /// `response`, optionally acknowledging each output report.
+    fn spawn_fake_hid_firmware(
+        response: &'static str,
+        ack: bool,
+    ) -> (
+        HidFocusApi<MockHidReader, MockHidWriter>,
+        JoinHandle<ReceivedReports>,
+    ) {
+        let (output_tx, mut output_rx) = mpsc::unbounded_channel::<Vec<u8>>();
+        let (input_tx, input_rx) = mpsc::unbounded_channel();
+
+        let firmware = tokio::spawn(async move {
+            let mut reports = Vec::new();
+
+            while let Some(report) = output_rx.recv().await {
+                let complete = report.ends_with(b"\n");
+
+                reports.push((Instant::now(), report));
+
+                if complete {
+                    for chunk in response.as_bytes().chunks(HID_MAX_SEND_SIZE) {
+                        input_tx.send([&[HID_REPORT_ID], chunk].concat()).unwrap();
+                    }
+
+                    break;
                 }
-            };
 
-            return match str_res
-                .parse::<parsing::focus_api::FocusApiCommandResponse>()
-                .map(|res| res.into_inner())
-            {
-                Ok(res) => Ok(res),
-                Err(parsing::focus_api::ParseResponseError::Incomplete) => continue,
-                Err(err) => return Err(HidRunCommandError::UnexpectedResponse(err)),
-            };
+                if ack {
+                    input_tx.send(vec![HID_REPORT_ID]).unwrap();
+                }
+            }
+
+            reports
+        });
+
+        let focus_api = HidFocusApi::from_parts(MockHidReader(input_rx), MockHidWriter(output_tx));
+
+        (focus_api, firmware)
+    }
+
+    #[tokio::test]
+    async fn large_payloads_are_sent_in_paced_chunks() {
+        let (mut focus_api, firmware) = spawn_fake_hid_firmware("\r\n.", false);
+
+        focus_api.set_flow_control(HidFlowControl {
+            chunk_size: 64,
+            report_delay: Duration::from_millis(5),
+            ack_timeout: None,
+        });
+
+        let mut events = focus_api.take_events().unwrap();
+        let data = vec!["65535"; 100].join(" ");
+
+        let res = focus_api
+            .run_command("superkeys.map", Some(&data))
+            .await
+            .unwrap();
+
+        assert_eq!(res, "");
+
+        let reports = firmware.await.unwrap();
+        let request = serialize_command("superkeys.map", Some(&data));
+
+        assert_eq!(reports.len(), request.len().div_ceil(64));
+        assert_eq!(
+            events.try_recv().unwrap(),
+            ConnectionEvent::SendingPaced {
+                len: request.len(),
+                reports: reports.len(),
+            }
+        );
+        assert!(
+            reports
+                .iter()
+                .all(|(_, report)| report[0] == HID_REPORT_ID && report.len() <= 65)
+        );
+
+        let payload = reports
+            .iter()
+            .flat_map(|(_, report)| &report[1..])
+            .copied()
+            .collect::<Vec<_>>();
+
+        assert_eq!(payload, request.as_bytes());
+
+        for pair in reports.windows(2) {
+            assert!(pair[1].0 - pair[0].0 >= Duration::from_millis(5));
         }
     }
+
+    #[tokio::test]
+    async fn acknowledged_reports_are_sent() {
+        let (mut focus_api, firmware) = spawn_fake_hid_firmware("\r\n.", true);
+
+        focus_api.set_flow_control(HidFlowControl {
+            chunk_size: 16,
+            report_delay: Duration::ZERO,
+            ack_timeout: Some(Duration::from_millis(100)),
+        });
+
+        let data = vec!["0"; 40].join(" ");
+
+        focus_api
+            .run_command("keymap.custom", Some(&data))
+            .await
+            .unwrap();
+
+        let request = serialize_command("keymap.custom", Some(&data));
+
+        assert_eq!(firmware.await.unwrap().len(), request.len().div_ceil(16));
+    }
+
+    #[tokio::test]
+    async fn unacknowledged_reports_time_out() {
+        let (mut focus_api, _firmware) = spawn_fake_hid_firmware("\r\n.", false);
+
+        focus_api.set_flow_control(HidFlowControl {
+            chunk_size: 16,
+            report_delay: Duration::ZERO,
+            ack_timeout: Some(Duration::from_millis(20)),
+        });
+
+        let data = vec!["0"; 40].join(" ");
+
+        let err = focus_api
+            .run_command("keymap.custom", Some(&data))
+            .await
+            .unwrap_err();
+
+        assert!(matches!(err, HidRunCommandError::AckTimedOut));
+    }
+
+    #[tokio::test]
+    async fn response_split_into_single_bytes_is_reassembled() {
+        let mut focus_api =
+            spawn_fake_firmware(1, "keymap.custom\n", b"41 30 31\r\n0 0 0\r\n.\r\n");
+
+        let res = FocusApiConnection::run_command(&mut focus_api, "keymap.custom", None)
+            .await
+            .unwrap();
+
+        assert_eq!(res, "41 30 31\n0 0 0");
+    }
+
+    #[tokio::test]
+    async fn utf8_characters_split_across_chunks_are_reassembled() {
+        let mut focus_api = spawn_fake_firmware(3, "version\n", "v1.0 «défy»\r\n.".as_bytes());
+
+        let res = FocusApiConnection::run_command(&mut focus_api, "version", None)
+            .await
+            .unwrap();
+
+        assert_eq!(res, "v1.0 «défy»");
+    }
+
+    #[tokio::test]
+    async fn stream_closing_mid_response_is_an_error() {
+        let mut focus_api = spawn_fake_firmware(4, "help\n", b"help\r\nversion\r\n");
+
+        let err = FocusApiConnection::run_command(&mut focus_api, "help", None)
+            .await
+            .unwrap_err();
+
+        assert!(matches!(
+            err,
+            RunCommandError::ResponseStreamTerminatedPrematurely
+        ));
+    }
+
+    #[tokio::test]
+    async fn unresponsive_device_times_out() {
+        let (host, _device) = tokio::io::duplex(64);
+        let mut focus_api = SerialPortFocusApi::from_stream(host);
+
+        let err = focus_api
+            .run_command_with_timeout("version", None, Duration::from_millis(50))
+            .await
+            .unwrap_err();
+
+        assert!(matches!(err, RunCommandError::TimedOut));
+    }
+
+    #[tokio::test]
+    async fn late_response_to_timed_out_command_is_discarded() {
+        let (host, device) = tokio::io::duplex(64);
+
+        tokio::spawn(async move {
+            let mut device = BufReader::new(device);
+
+            let mut request = String::new();
+            device.read_line(&mut request).await.unwrap();
+            assert_eq!(request, "version\n");
+
+            tokio::time::sleep(Duration::from_millis(100)).await;
+            device.write_all(b"v1.0.0\r\n.").await.unwrap();
+
+            request.clear();
+            device.read_line(&mut request).await.unwrap();
+            assert_eq!(request, "help\n");
+
+            device.write_all(b"help\r\nversion\r\n.").await.unwrap();
+
+            // Keep the stream open until the host is done
+            device.read_line(&mut request).await.unwrap();
+        });
+
+        let mut focus_api = SerialPortFocusApi::from_stream(host);
+        let mut events = focus_api.take_events().unwrap();
+
+        let err = focus_api
+            .run_command_with_timeout("version", None, Duration::from_millis(50))
+            .await
+            .unwrap_err();
+
+        assert!(matches!(err, RunCommandError::TimedOut));
+
+        let res = FocusApiConnection::run_command(&mut focus_api, "help", None)
+            .await
+            .unwrap();
+
+        assert_eq!(res, "help\nversion");
+        assert_eq!(
+            events.try_recv().unwrap(),
+            ConnectionEvent::Resynchronised { discarded: 9 }
+        );
+    }
+
+    #[tokio::test]
+    async fn data_following_a_response_is_unsolicited() {
+        let (host, device) = tokio::io::duplex(64);
+
+        tokio::spawn(async move {
+            let mut device = BufReader::new(device);
+
+            let mut request = String::new();
+            device.read_line(&mut request).await.unwrap();
+            device.write_all(b"v1.0.0\r\n.\r\nstray\r\n").await.unwrap();
+
+            request.clear();
+            device.read_line(&mut request).await.unwrap();
+            assert_eq!(request, "settings.valid?\n");
+            device.write_all(b"true\r\n.\r\n").await.unwrap();
+
+            device.read_line(&mut request).await.unwrap();
+        });
+
+        let mut focus_api = SerialPortFocusApi::from_stream(host);
+
+        let res = FocusApiConnection::run_command(&mut focus_api, "version", None)
+            .await
+            .unwrap();
+        assert_eq!(res, "v1.0.0");
+
+        // Give the stray data time to arrive
+        tokio::time::sleep(Duration::from_millis(50)).await;
+
+        let res = FocusApiConnection::run_command(&mut focus_api, "settings.valid?", None)
+            .await
+            .unwrap();
+        assert_eq!(res, "true");
+
+        assert_eq!(focus_api.take_unsolicited_data(), b"stray\r\n");
+    }
 }
@@ -0,0 +1,149 @@
+//! This module defines the [`Capabilities`] type, which describes what the
+//! firmware on a device supports, so that unsupported features can be
+//! reported clearly before trying to use them.
+
+use std::collections::BTreeSet;
+
+use super::{FocusApiConnection, GetCommandsError, RunCommandError};
+use crate::devices::{keymap, settings};
+
+/// Error returned when getting the [`Capabilities`] of a device.
+#[derive(Debug, Display, From, Error)]
+pub enum GetCapabilitiesError {
+    /// The list of supported commands could not be retrieved.
+    #[display("{_0}")]
+    ListingCommands(GetCommandsError),
+    /// A command describing the firmware or hardware failed to run.
+    #[display("failed to query the firmware: {_0}")]
+    QueryingFirmware(RunCommandError),
+}
+
+/// Error returned when the firmware does not support a command.
+#[derive(Clone, Debug, Display, Error)]
+#[display("`{command}` is unsupported by firmware {firmware}")]
+pub struct UnsupportedCommandError {
+    /// The unsupported command.
+    pub command: String,
+    /// The firmware version, or `unknown` when the firmware doesn't report
+    /// it.
+    pub firmware: String,
+}
+
+/// Error returned when checking whether the firmware supports a command.
+#[derive(Debug, Display, From, Error)]
+pub enum RequireCommandError {
+    /// The capabilities of the device could not be determined.
+    #[display("{_0}")]
+    GettingCapabilities(GetCapabilitiesError),
+    /// The firmware does not support the command.
+    #[display("{_0}")]
+    Unsupported(UnsupportedCommandError),
+}
+
+/// What the firmware on a device supports, as reported by `help`, `version`
+/// and the `hardware.*` commands.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Capabilities {
+    /// Every command the firmware supports.
+    pub commands: BTreeSet<String>,
+    /// The firmware version, if the firmware reports it.
+    pub firmware_version: Option<String>,
+    /// The hardware version, if the firmware reports it.
+    pub hardware_version: Option<String>,
+    /// The physical layout, such as `ANSI` or `ISO`, if the firmware
+    /// reports it.
+    pub layout: Option<String>,
+    /// The unique id of the keyboard's chip, if the firmware reports it.
+    pub chip_id: Option<String>,
+}
+
+impl Capabilities {
+    /// Queries the capabilities of the device.
+    ///
+    /// Only commands listed by `help` are run, so this works with any
+    /// firmware.
+    pub async fn query<C>(conn: &mut C) -> Result<Self, GetCapabilitiesError>
+    where
+        C: FocusApiConnection + ?Sized,
+    {
+        let mut capabilities = Self {
+            commands: conn.available_commands().await?.into_iter().collect(),
+            ..Self::default()
+        };
+
+        for (command, value) in [
+            (
+                settings::VERSION_COMMAND_NAME,
+                &mut capabilities.firmware_version,
+            ),
+            (
+                settings::HARDWARE_VERSION_COMMAND_NAME,
+                &mut capabilities.hardware_version,
+            ),
+            (keymap::LAYOUT_COMMAND_NAME, &mut capabilities.layout),
+            (settings::CHIP_ID_COMMAND_NAME, &mut capabilities.chip_id),
+        ] {
+            if capabilities.commands.contains(command) {
+                let res = conn
+                    .run_command(command, None)
+                    .await
+                    .map_err(GetCapabilitiesError::QueryingFirmware)?;
+
+                *value = Some(res.trim().to_owned()).filter(|res| !res.is_empty());
+            }
+        }
+
+        Ok(capabilities)
+    }
+
+    /// Checks if the firmware supports the command.
+    pub fn supports(&self, command: &str) -> bool {
+        self.commands.contains(command)
+    }
+
+    /// Checks that the firmware supports the command, failing with an error
+    /// naming the firmware otherwise.
+    pub fn require(&self, command: &str) -> Result<(), UnsupportedCommandError> {
+        if self.supports(command) {
+            return Ok(());
+        }
+
+        Err(UnsupportedCommandError {
+            command: command.to_owned(),
+            firmware: self
+                .firmware_version
+                .clone()
+                .unwrap_or_else(|| "unknown".to_owned()),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::focus_api::MockFocusApi;
+
+    #[tokio::test]
+    async fn capabilities_are_queried_from_supported_commands() {
+        let mut mock = MockFocusApi::new()
+            .with_read_only_command(settings::VERSION_COMMAND_NAME, "v1.2.3")
+            .with_read_only_command(keymap::LAYOUT_COMMAND_NAME, "ISO")
+            .with_command(keymap::CUSTOM_COMMAND_NAME, "0");
+
+        let capabilities = Capabilities::query(&mut mock).await.unwrap();
+
+        assert_eq!(capabilities.firmware_version.as_deref(), Some("v1.2.3"));
+        assert_eq!(capabilities.layout.as_deref(), Some("ISO"));
+        assert_eq!(capabilities.hardware_version, None);
+        assert!(capabilities.supports(keymap::CUSTOM_COMMAND_NAME));
+
+        let err = capabilities
+            .require(crate::devices::superkeys::MAP_COMMAND_NAME)
+            .unwrap_err();
+
+        assert_eq!(
+            err.to_string(),
+            "`superkeys.map` is unsupported by firmware v1.2.3"
+        );
+    }
+}
@@ -0,0 +1,142 @@
+//! This module defines the [`DeviceSelector`] type, which is used to choose
+//! which device to connect to when more than one is available.
+
+use std::str::FromStr;
+use tokio_serial::UsbPortInfo;
+
+/// Error returned when parsing a [`DeviceSelector`] from a string fails.
+#[derive(Clone, Copy, Debug, Display, Error)]
+#[display("device selector value cannot be empty")]
+pub struct ParseDeviceSelectorError;
+
+/// Chooses which device to connect to.
+///
+/// When parsed from a string, the following formats are accepted:
+///
+/// - `any` selects the first matching device.
+/// - `serial:<serial number>` selects a device by its USB serial number.
+/// - `hid:<device id>` selects a HID device by its platform-specific id, such
+///   as `/dev/hidraw3` on Linux.
+/// - `path:<port path>`, or just `<port path>`, selects the serial port at
+///   the given path, such as `/dev/ttyACM0` or `COM3`.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Hash)]
+pub enum DeviceSelector {
+    /// Connect to the first matching device found.
+    #[default]
+    Any,
+    /// Connect to the serial port at the given path.
+    ///
+    /// The port is opened directly, without checking the manufacturer or
+    /// product name, so pseudo-terminals and symlinks, such as those in
+    /// `/dev/serial/by-id`, can also be used.
+    Path(String),
+    /// Connect to the device with the given USB serial number, over either
+    /// serial port or HID.
+    SerialNumber(String),
+    /// Connect to the HID device with the given platform-specific id.
+    HidId(String),
+}
+
+impl FromStr for DeviceSelector {
+    type Err = ParseDeviceSelectorError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let selector = if s == "any" {
+            Self::Any
+        } else if let Some(serial_number) = s.strip_prefix("serial:") {
+            Self::SerialNumber(serial_number.to_owned())
+        } else if let Some(id) = s.strip_prefix("hid:") {
+            Self::HidId(id.to_owned())
+        } else {
+            Self::Path(s.strip_prefix("path:").unwrap_or(s).to_owned())
+        };
+
+        match &selector {
+            Self::Path(value) | Self::SerialNumber(value) | Self::HidId(value)
+                if value.is_empty() =>
+            {
+                Err(ParseDeviceSelectorError)
+            }
+            _ => Ok(selector),
+        }
+    }
+}
+
+impl std::fmt::Display for DeviceSelector {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Any => write!(f, "any"),
+            Self::Path(path) => write!(f, "path:{path}"),
+            Self::SerialNumber(serial_number) => write!(f, "serial:{serial_number}"),
+            Self::HidId(id) => write!(f, "hid:{id}"),
+        }
+    }
+}
+
+impl DeviceSelector {
+    /// Checks if the selector matches the serial port.
+    pub fn matches_serial_port(&self, port_name: &str, info: &UsbPortInfo) -> bool {
+        match self {
+            Self::Any => true,
+            Self::Path(path) => path == port_name,
+            Self::SerialNumber(serial_number) => info.serial_number.as_ref() == Some(serial_number),
+            Self::HidId(_) => false,
+        }
+    }
+
+    /// Checks if the selector matches the HID device.
+    pub fn matches_hid_device(&self, info: &async_hid::DeviceInfo) -> bool {
+        match self {
+            Self::Any => true,
+            Self::Path(_) => false,
+            Self::SerialNumber(serial_number) => info.serial_number.as_ref() == Some(serial_number),
+            Self::HidId(id) => *id == hid_device_id(&info.id),
+        }
+    }
+}
+
+/// Formats the platform-specific HID device id as a string.
+pub(crate) fn hid_device_id(id: &async_hid::DeviceId) -> String {
+    match id {
+        #[cfg(target_os = "linux")]
+        async_hid::DeviceId::DevPath(path) => path.to_string_lossy().into_owned(),
+        #[cfg(target_os = "windows")]
+        async_hid::DeviceId::UncPath(path) => path.to_string(),
+        #[cfg(target_os = "macos")]
+        async_hid::DeviceId::RegistryEntryId(id) => id.to_string(),
+        id => format!("{id:?}"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn selectors_round_trip_from_str() {
+        let selectors = [
+            ("/dev/ttyACM0", DeviceSelector::Path("/dev/ttyACM0".into())),
+            ("path:COM3", DeviceSelector::Path("COM3".into())),
+            (
+                "serial:ABC123",
+                DeviceSelector::SerialNumber("ABC123".into()),
+            ),
+            (
+                "hid:/dev/hidraw3",
+                DeviceSelector::HidId("/dev/hidraw3".into()),
+            ),
+        ];
+
+        for (s, expected) in selectors {
+            let selector = s.parse::<DeviceSelector>().unwrap();
+
+            assert_eq!(selector, expected);
+            assert_eq!(
+                selector.to_string().parse::<DeviceSelector>().unwrap(),
+                expected
+            );
+        }
+
+        assert!("serial:".parse::<DeviceSelector>().is_err());
+    }
+}
@@ -0,0 +1,216 @@
+//! This module provides [`discover_devices`], which lists every Dygma device
+//! the SDK could connect to.
+
+use tokio_serial::SerialPortType;
+use tokio_stream::StreamExt;
+
+use super::{DeviceSelector, HidFocusApi, SerialPortFocusApi, device_selector::hid_device_id};
+
+/// Product ids of keyboards connected with a USB cable.
+///
+/// Every id here and in [`WIRELESS_PRODUCT_IDS`] must also be listed by the
+/// [`ModelDescriptor`](crate::devices::ModelDescriptor) of its model.
+const WIRED_PRODUCT_IDS: &[u16] = &[
+    0x0010, // Defy
+    0x0021, // Raise 2
+    0x2201, // Raise, which can only be wired
+];
+
+/// Product ids of keyboards connected wirelessly, over either the RF dongle
+/// or Bluetooth.
+const WIRELESS_PRODUCT_IDS: &[u16] = &[
+    0x0012, // Defy
+    0x0022, // Raise 2
+];
+
+/// Error returned from [`discover_devices`].
+#[derive(Debug, Display, Error)]
+pub enum DiscoverDevicesError {
+    /// We were unable to get a list of available serial ports.
+    #[display("failed to enumerate serial port devices: {_0}")]
+    EnumeratingSerialPorts(tokio_serial::Error),
+    /// We were unable to get a list of available HID devices.
+    #[display("failed to enumerate HID devices: {_0}")]
+    EnumeratingHidDevices(async_hid::HidError),
+}
+
+/// The transport used to talk to a device.
+#[derive(Clone, Copy, Debug, Display, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Transport {
+    /// Serial port, used for both wired and RF connections.
+    #[display("serial")]
+    Serial,
+    /// HID, used for Bluetooth connections.
+    #[display("hid")]
+    Hid,
+}
+
+/// How the keyboard is physically linked to the computer.
+#[derive(Clone, Copy, Debug, Display, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Link {
+    /// Connected with a USB cable.
+    #[display("wired")]
+    Wired,
+    /// Connected wirelessly through the RF dongle.
+    #[display("rf")]
+    Rf,
+    /// Connected wirelessly over Bluetooth LE.
+    #[display("bluetooth")]
+    Bluetooth,
+    /// The product id is not known to the SDK.
+    #[display("unknown")]
+    Unknown,
+}
+
+impl Link {
+    fn from_product_id(transport: Transport, product_id: u16) -> Self {
+        if WIRED_PRODUCT_IDS.contains(&product_id) {
+            Self::Wired
+        } else if WIRELESS_PRODUCT_IDS.contains(&product_id) {
+            match transport {
+                Transport::Serial => Self::Rf,
+                Transport::Hid => Self::Bluetooth,
+            }
+        } else {
+            Self::Unknown
+        }
+    }
+}
+
+/// A Dygma device which was found connected to the computer.
+#[derive(Clone, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct DiscoveredDevice {
+    /// The transport used to talk to the device.
+    pub transport: Transport,
+    /// How the device is linked to the computer.
+    pub link: Link,
+    /// The product name reported by the device, if any.
+    pub product_name: Option<String>,
+    /// The USB vendor id.
+    pub vendor_id: u16,
+    /// The USB product id.
+    pub product_id: u16,
+    /// The serial port path, or the platform-specific HID device id.
+    pub path: String,
+    /// The USB serial number, if the device reports one.
+    pub serial_number: Option<String>,
+}
+
+impl DiscoveredDevice {
+    /// Checks if the selector matches this device.
+    pub fn matches(&self, selector: &DeviceSelector) -> bool {
+        match selector {
+            DeviceSelector::Any => true,
+            DeviceSelector::Path(path) => self.transport == Transport::Serial && *path == self.path,
+            DeviceSelector::SerialNumber(serial_number) => {
+                self.serial_number.as_ref() == Some(serial_number)
+            }
+            DeviceSelector::HidId(id) => self.transport == Transport::Hid && *id == self.path,
+        }
+    }
+
+    /// Creates a [`DeviceSelector`] which selects exactly this device.
+    pub fn selector(&self) -> DeviceSelector {
+        match self.transport {
+            Transport::Serial => DeviceSelector::Path(self.path.clone()),
+            Transport::Hid => DeviceSelector::HidId(self.path.clone()),
+        }
+    }
+}
+
+/// Lists every Dygma device connected over serial port or HID.
+pub async fn discover_devices() -> Result<Vec<DiscoveredDevice>, DiscoverDevicesError> {
+    let mut devices = tokio_serial::available_ports()
+        .map_err(DiscoverDevicesError::EnumeratingSerialPorts)?
+        .into_iter()
+        .filter_map(|info| match info.port_type {
+            SerialPortType::UsbPort(usb_info)
+                if usb_info.manufacturer.as_deref()
+                    == Some(SerialPortFocusApi::MANUFACTURER_NAME) =>
+            {
+                Some(DiscoveredDevice {
+                    transport: Transport::Serial,
+                    link: Link::from_product_id(Transport::Serial, usb_info.pid),
+                    product_name: usb_info.product,
+                    vendor_id: usb_info.vid,
+                    product_id: usb_info.pid,
+                    path: info.port_name,
+                    serial_number: usb_info.serial_number,
+                })
+            }
+            _ => None,
+        })
+        .collect::<Vec<_>>();
+
+    let backend = async_hid::HidBackend::default();
+
+    let hid_devices = backend
+        .enumerate()
+        .await
+        .map_err(DiscoverDevicesError::EnumeratingHidDevices)?
+        .filter(|device| {
+            device.vendor_id == HidFocusApi::VENDOR_ID
+                && device.usage_page == HidFocusApi::USAGE_PAGE
+                && device.usage_id == HidFocusApi::USAGE_ID
+        })
+        .map(|device| DiscoveredDevice {
+            transport: Transport::Hid,
+            link: Link::from_product_id(Transport::Hid, device.product_id),
+            product_name: Some(device.name.clone()),
+            vendor_id: device.vendor_id,
+            product_id: device.product_id,
+            path: hid_device_id(&device.id),
+            serial_number: device.serial_number.clone(),
+        })
+        .collect::<Vec<_>>()
+        .await;
+
+    devices.extend(hid_devices);
+
+    Ok(devices)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn link_is_inferred_from_transport_and_product_id() {
+        assert_eq!(
+            Link::from_product_id(Transport::Serial, 0x0010),
+            Link::Wired
+        );
+        assert_eq!(Link::from_product_id(Transport::Serial, 0x0012), Link::Rf);
+        assert_eq!(
+            Link::from_product_id(Transport::Hid, 0x0012),
+            Link::Bluetooth
+        );
+        assert_eq!(Link::from_product_id(Transport::Hid, 0xffff), Link::Unknown);
+    }
+
+    #[test]
+    fn product_ids_match_the_model_descriptors() {
+        use crate::devices::Model;
+
+        for model in Model::ALL {
+            let descriptor = model.descriptor();
+            let (wired, wireless): (Vec<u16>, Vec<u16>) = descriptor
+                .product_ids
+                .iter()
+                .partition(|id| WIRED_PRODUCT_IDS.contains(id));
+
+            assert!(!wired.is_empty(), "{model} has no wired product id");
+            assert!(
+                wireless.iter().all(|id| WIRELESS_PRODUCT_IDS.contains(id)),
+                "{model} has unknown product ids"
+            );
+            assert_eq!(!wireless.is_empty(), descriptor.wireless, "{model}");
+        }
+
+        for id in WIRED_PRODUCT_IDS.iter().chain(WIRELESS_PRODUCT_IDS) {
+            assert!(Model::from_product_id(*id).is_some(), "{id:#06x}");
+        }
+    }
+}
@@ -0,0 +1,78 @@
+//! This module defines the [`ConnectionEvent`] type, which connections use to
+//! report noteworthy things happening on them, leaving it up to the consumer
+//! to decide how, or whether, to present them.
+
+use tokio::sync::mpsc;
+
+/// How many events are kept while nobody is receiving them. Newer events are
+/// dropped once this many pile up.
+const EVENT_CAPACITY: usize = 64;
+
+/// Something noteworthy which happened on a connection.
+#[derive(Clone, Debug, Display, PartialEq, Eq, Hash)]
+pub enum ConnectionEvent {
+    /// The connection goes over HID, which includes Bluetooth LE, where
+    /// large payloads are sent in paced chunks, and can take a while.
+    #[display("connected over HID, large payloads will take a while to send")]
+    SlowTransport,
+    /// Connecting over serial port failed, so HID was used instead.
+    #[display("failed to connect over serial port, falling back to HID: {reason}")]
+    FellBackToHid {
+        /// Why the serial port connection failed.
+        reason: String,
+    },
+    /// A command is being sent as a series of paced reports.
+    #[display("sending {len} bytes in {reports} paced reports")]
+    SendingPaced {
+        /// Number of bytes being sent.
+        len: usize,
+        /// Number of reports the bytes are split into.
+        reports: usize,
+    },
+    /// The remains of an interrupted command were discarded.
+    #[display("resynchronised the connection, discarding {discarded} bytes")]
+    Resynchronised {
+        /// Number of bytes discarded.
+        discarded: usize,
+    },
+    /// Data which was not part of any response was received.
+    #[display("received {len} bytes of unsolicited data")]
+    UnsolicitedData {
+        /// Number of bytes received.
+        len: usize,
+    },
+}
+
+/// The sending end of a connection's events, which holds on to the receiving
+/// end until it is taken.
+#[derive(Debug)]
+pub(crate) struct EventSender {
+    sender: mpsc::Sender<ConnectionEvent>,
+    receiver: Option<mpsc::Receiver<ConnectionEvent>>,
+}
+
+impl Default for EventSender {
+    fn default() -> Self {
+        let (sender, receiver) = mpsc::channel(EVENT_CAPACITY);
+
+        Self {
+            sender,
+            receiver: Some(receiver),
+        }
+    }
+}
+
+impl EventSender {
+    /// Sends the event, dropping it if too many events are waiting to be
+    /// received, or nobody is listening anymore.
+    pub(crate) fn emit(&self, event: ConnectionEvent) {
+        debug!("connection event: {event}");
+
+        let _ = self.sender.try_send(event);
+    }
+
+    /// Takes the receiving end of the events, which can only be done once.
+    pub(crate) fn take_receiver(&mut self) -> Option<mpsc::Receiver<ConnectionEvent>> {
+        self.receiver.take()
+    }
+}
@@ -0,0 +1,224 @@
+//! This module defines the [`MockFocusApi`] type, which emulates a keyboard's
+//! Focus API entirely in memory, allowing the SDK to be used and tested
+//! without any hardware attached.
+
+use std::collections::BTreeMap;
+
+use super::{
+    FocusApiConnection, RunCommandError,
+    parsing::focus_api::{FocusApiCommandResponse, serialize_command},
+};
+
+/// In-memory emulation of a device's Focus API.
+///
+/// The device state is a set of commands, each holding a value. Running a
+/// command without data returns its value, and running it with data replaces
+/// the value, just like the firmware does for `keymap.custom`,
+/// `superkeys.map`, `settings.*`, etc. Commands marked as read-only, such as
+/// `version`, ignore any data sent to them.
+///
+/// Commands whose data is an argument rather than a new value, such as
+/// `layer.activate`, are emulated with a [`MockHandler`].
+///
+/// Requests and responses go through the same wire format used by real
+/// devices, so the response parsing is exercised as well.
+#[derive(Clone, Debug, Default)]
+pub struct MockFocusApi {
+    commands: BTreeMap<String, MockCommand>,
+}
+
+/// Function emulating a command, called with the device and the data sent
+/// along with the command, and returning the response, if any.
+pub type MockHandler = fn(&mut MockFocusApi, Option<&str>) -> Option<String>;
+
+#[derive(Clone, Debug)]
+enum MockCommand {
+    Value { value: String, read_only: bool },
+    Handler(MockHandler),
+}
+
+impl FocusApiConnection for MockFocusApi {
+    async fn run_command(
+        &mut self,
+        command: &str,
+        data: Option<&str>,
+    ) -> Result<String, RunCommandError> {
+        let request = serialize_command(command, data);
+
+        let response = self.respond(&request);
+
+        response
+            .parse::<FocusApiCommandResponse>()
+            .map(FocusApiCommandResponse::into_inner)
+            .map_err(RunCommandError::UnexpectedResponse)
+    }
+}
+
+impl MockFocusApi {
+    /// Creates a device which only supports the `help` command.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds a command which stores the provided value, and can be overwritten
+    /// by sending data along with the command.
+    pub fn with_command(mut self, command: &str, value: impl Into<String>) -> Self {
+        self.set_command(command, value, false);
+
+        self
+    }
+
+    /// Adds a command which always returns the provided value, ignoring any
+    /// data sent along with the command.
+    pub fn with_read_only_command(mut self, command: &str, value: impl Into<String>) -> Self {
+        self.set_command(command, value, true);
+
+        self
+    }
+
+    /// Adds a command emulated by the handler, which can read and change
+    /// the values of other commands.
+    pub fn with_handler(mut self, command: &str, handler: MockHandler) -> Self {
+        self.commands
+            .insert(command.to_owned(), MockCommand::Handler(handler));
+
+        self
+    }
+
+    /// Removes a command, making the device behave as if the firmware
+    /// did not support it.
+    pub fn without_command(mut self, command: &str) -> Self {
+        self.commands.remove(command);
+
+        self
+    }
+
+    /// Gets the current value of a command, if the device supports it.
+    pub fn get(&self, command: &str) -> Option<&str> {
+        match self.commands.get(command)? {
+            MockCommand::Value { value, .. } => Some(value),
+            MockCommand::Handler(_) => None,
+        }
+    }
+
+    /// Changes the value of a command, even if it is read-only, like the
+    /// firmware would do internally.
+    ///
+    /// Does nothing if the device doesn't support the command, or if it is
+    /// emulated by a handler.
+    pub fn set(&mut self, command: &str, new_value: impl Into<String>) {
+        if let Some(MockCommand::Value { value, .. }) = self.commands.get_mut(command) {
+            *value = new_value.into();
+        }
+    }
+
+    fn set_command(&mut self, command: &str, value: impl Into<String>, read_only: bool) {
+        let value = value.into();
+
+        self.commands
+            .insert(command.to_owned(), MockCommand::Value { value, read_only });
+    }
+
+    /// Handles a raw request, as it would be sent over the wire, and returns
+    /// the raw response the firmware would send back.
+    fn respond(&mut self, request: &str) -> String {
+        let request = request.trim_end();
+
+        let (command, data) = match request.split_once(' ') {
+            Some((command, data)) => (command, Some(data)),
+            None => (request, None),
+        };
+
+        let handler = match self.commands.get(command) {
+            Some(MockCommand::Handler(handler)) => Some(*handler),
+            _ => None,
+        };
+
+        let value = match (command, data) {
+            ("help", _) => Some(
+                std::iter::once("help")
+                    .chain(self.commands.keys().map(String::as_str))
+                    .collect::<Vec<_>>()
+                    .join("\r\n"),
+            ),
+            (_, data) if let Some(handler) = handler => handler(self, data),
+            (command, None) => self.get(command).map(ToOwned::to_owned),
+            (command, Some(data)) => {
+                if let Some(MockCommand::Value {
+                    value,
+                    read_only: false,
+                }) = self.commands.get_mut(command)
+                {
+                    *value = data.to_owned();
+                }
+
+                None
+            }
+        };
+
+        // The firmware terminates every response with a line containing
+        // only a `.`, even when there is nothing else to send back
+        format!("{}\r\n.", value.unwrap_or_default())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn commands_store_data() {
+        let mut mock = MockFocusApi::new().with_command("settings.defaultLayer", "0");
+
+        mock.run_command("settings.defaultLayer", Some("3"))
+            .await
+            .unwrap();
+
+        let res = mock
+            .run_command("settings.defaultLayer", None)
+            .await
+            .unwrap();
+
+        assert_eq!(res, "3");
+    }
+
+    #[tokio::test]
+    async fn help_lists_supported_commands() {
+        let mut mock = MockFocusApi::new()
+            .with_read_only_command("version", "mock")
+            .with_command("keymap.custom", "");
+
+        let cmds = mock.available_commands().await.unwrap();
+
+        assert_eq!(cmds, ["help", "keymap.custom", "version"]);
+
+        mock.run_command("version", Some("1.0.0")).await.unwrap();
+
+        assert_eq!(mock.get("version"), Some("mock"));
+    }
+
+    #[tokio::test]
+    async fn handlers_emulate_commands_with_arguments() {
+        let mut mock = MockFocusApi::new()
+            .with_command("layer.state", "1 0")
+            .with_handler("layer.activate", |mock, data| {
+                mock.set("layer.state", format!("1 {}", data.unwrap_or("0")));
+
+                None
+            });
+
+        mock.run_command("layer.activate", Some("1")).await.unwrap();
+
+        assert_eq!(mock.get("layer.state"), Some("1 1"));
+        assert_eq!(mock.get("layer.activate"), None);
+    }
+
+    #[tokio::test]
+    async fn unsupported_commands_return_empty_response() {
+        let mut mock = MockFocusApi::new();
+
+        let res = mock.run_command("led.mode", None).await.unwrap();
+
+        assert_eq!(res, "");
+    }
+}
@@ -50,6 +50,19 @@ impl FocusApiCommandResponse {
     pub fn into_inner(self) -> String {
         self.0
     }
+
+    /// Parses the response at the start of the input, returning it along
+    /// with the number of bytes it took up, so any data following it, such
+    /// as the next response, can be kept.
+    pub fn parse_prefix(s: &str) -> Result<(Self, usize), ParseResponseError> {
+        let mut input = Partial::new(s);
+
+        let res = response_parser
+            .parse_next(&mut input)
+            .map_err(ParseResponseError::from_winnow_err)?;
+
+        Ok((Self(res), s.len() - input.into_inner().len()))
+    }
 }
 
 fn response_parser(input: &mut Partial<&str>) -> ModalResult<String> {
@@ -136,6 +149,16 @@ mod test {
         );
     }
 
+    #[test]
+    fn parse_prefix_reports_consumed_length() {
+        let data = "first\r\n.\r\nsecond\r\n.";
+
+        let (res, len) = FocusApiCommandResponse::parse_prefix(data).unwrap();
+
+        assert_eq!(*res, "first");
+        assert_eq!(&data[len..], "\r\nsecond\r\n.");
+    }
+
     #[test]
     fn parser_fails_on_incomplete_data() {
         let data = "\
@@ -96,6 +96,17 @@ impl PartialEq<KeyKind> for u16 {
     }
 }
 
+impl KeyKind {
+    /// Checks whether the key acts as a modifier or layer shift when held,
+    /// and as a regular key when tapped.
+    ///
+    /// The timings of these keys are set with
+    /// [`DualFunctionSettings`](crate::devices::qukeys::DualFunctionSettings).
+    pub fn is_dual_function(self) -> bool {
+        (CONTROL_DUAL_FUNCTION..=LAYER_8_DUAL_FUNCTION + 0xff).contains(&u16::from(self))
+    }
+}
+
 macros::generate_keycode_tables! {
   /// Blank keys.
   blank: {
@@ -253,8 +264,10 @@ macros::generate_keycode_tables! {
     BracketRight,
     /// \
     Backslash,
+    /// ISO #
+    IsoHash,
     /// ;
-    Semicolon = 51,
+    Semicolon,
     /// '
     SingleQuote,
     /// `
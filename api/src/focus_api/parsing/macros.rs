@@ -1,24 +1,151 @@
 //! Types for parsing macros.
 
+use itertools::Itertools;
+use std::str::FromStr;
 use winnow::{
     ModalResult, Parser,
-    ascii::dec_uint,
-    combinator::{fail, repeat_till},
+    ascii::{dec_uint, multispace0},
+    combinator::{alt, opt, peek, repeat, repeat_till, terminated},
     error::{StrContext, StrContextValue},
-    token::rest,
 };
 
 use crate::keycode_tables::KeyKind;
 
+/// Error when parsing a macro map.
+#[derive(Clone, Debug, Display, Error, From)]
+#[display("failed to parse macro map data:\n{_0}")]
+pub struct ParseMacroMapError(#[error(not(source))] String);
+
+/// Error returned when a [`MacroMap`] cannot be converted into command data.
+#[derive(Clone, Copy, Debug, Display, Error)]
+pub enum MacroCommandDataError {
+    /// The macros do not fit in the macro memory of the keyboard.
+    #[display("macros do not fit in the available macro memory")]
+    TooManyMacros,
+    /// A macro has no actions, which the keyboard would interpret as the end
+    /// of the macro map. Holds the macro number, starting at 1.
+    #[display("macro {_0} has no actions")]
+    EmptyMacro(#[error(not(source))] usize),
+    /// A key code action was given a key which cannot be represented as a u8.
+    ///
+    /// Use [`MacroAction::Special`] for pressing these keys instead.
+    #[display("`{_0}` does not fit in a single byte key code action")]
+    KeyDoesNotFitInU8(#[error(not(source))] KeyKind),
+}
+
+/// Struct containing a list of defined macros, along with the rest of the
+/// macro memory.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct MacroMap {
+    /// The defined macros.
+    pub macros: Vec<Macro>,
+    /// The bytes in macro memory following the end of the macro map.
+    ///
+    /// The firmware does not clear the macro memory when a shorter macro
+    /// map is written, so this usually holds leftovers of a previous, longer
+    /// macro map followed by the 255 padding. These bytes are never executed,
+    /// but are kept so that the macro memory round-trips unchanged.
+    pub unused_memory: Vec<u8>,
+}
+
+impl FromStr for MacroMap {
+    type Err = ParseMacroMapError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (macros, unused_memory, _) = (macro_map_parser, unused_memory_parser, multispace0)
+            .parse(s)
+            .map_err(|err| ParseMacroMapError(err.to_string()))?;
+
+        Ok(Self {
+            macros,
+            unused_memory,
+        })
+    }
+}
+
+impl From<Vec<Macro>> for MacroMap {
+    fn from(macros: Vec<Macro>) -> Self {
+        Self {
+            macros,
+            unused_memory: Vec::new(),
+        }
+    }
+}
+
+impl MacroMap {
+    /// Converts the macro map into a form suitable for sending to
+    /// the keyboard as command data.
+    ///
+    /// The [`MacroMap::unused_memory`] is written after the end of the
+    /// macro map, as long as it fits, and the rest of the memory is padded
+    /// with 255.
+    pub fn to_command_data<const MEMORY_SIZE: usize>(
+        &self,
+    ) -> Result<String, MacroCommandDataError> {
+        self.to_sized_command_data(MEMORY_SIZE)
+    }
+
+    /// Like [`MacroMap::to_command_data`], for when the memory size is
+    /// only known at runtime, such as when it depends on the keyboard model.
+    pub fn to_sized_command_data(
+        &self,
+        memory_size: usize,
+    ) -> Result<String, MacroCommandDataError> {
+        let mut macros = Vec::with_capacity(memory_size);
+        for (i, macro_) in self.macros.iter().enumerate() {
+            if macro_.actions.is_empty() {
+                return Err(MacroCommandDataError::EmptyMacro(i + 1));
+            }
+
+            macros.extend(macro_.to_command_data()?);
+        }
+
+        // We need to add a final 0 byte to indicate the end of the
+        // macro map, unless there are no macros at all, in which case
+        // the memory is left erased
+        if !self.macros.is_empty() {
+            macros.push(0);
+        }
+
+        if macros.len() > memory_size {
+            return Err(MacroCommandDataError::TooManyMacros);
+        }
+
+        let mut res = vec![u8::MAX; memory_size];
+
+        res.iter_mut()
+            .zip(macros.into_iter().chain(self.unused_memory.iter().copied()))
+            .for_each(|(res, byte)| *res = byte);
+
+        Ok(res.iter().join(" "))
+    }
+}
+
 /// Represents a single macro.
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
 pub struct Macro {
     /// The actions this macro will perform.
     pub actions: Vec<MacroAction>,
 }
 
+impl Macro {
+    /// Converts the macro into a form suitable for sending to the
+    /// keyboard, including the trailing 0 byte marking the end of the macro.
+    pub fn to_command_data(&self) -> Result<Vec<u8>, MacroCommandDataError> {
+        let mut data = Vec::new();
+
+        for action in &self.actions {
+            data.extend(action.to_command_data()?);
+        }
+
+        data.push(0);
+
+        Ok(data)
+    }
+}
+
 /// The possible actions a macro can perform.
-#[derive(Clone, Copy, Debug)]
+#[derive(Clone, Debug, PartialEq, Eq)]
 pub enum MacroAction {
     /// Delay in ms.
     Delay(u16),
@@ -32,6 +159,13 @@ pub enum MacroAction {
     /// A press of a [`KeyKind`] that can't be represented as a u8.
     #[debug("Special({_0})")]
     Special(KeyKind),
+    /// Holds down a [`KeyKind`] that can't be represented as a u8, such as
+    /// keys combined with modifiers.
+    #[debug("SpecialKeyDown({_0})")]
+    SpecialKeyDown(KeyKind),
+    /// Releases a held [`KeyKind`] that can't be represented as a u8.
+    #[debug("SpecialKeyUp({_0})")]
+    SpecialKeyUp(KeyKind),
     /// Equivalent to  arapid keydown and keyup.
     #[debug("Press({_0})")]
     Press(KeyKind),
@@ -41,33 +175,66 @@ pub enum MacroAction {
     /// Releases the held key.
     #[debug("KeyUp({_0})")]
     KeyUp(KeyKind),
-    /// We don't yet know what this key action type does.
+    /// An action type this crate doesn't know about.
+    ///
+    /// As the length of its data is unknown, every byte up to the end of the
+    /// macro is kept as its data, so that the macro is written back unchanged.
     Unknown {
         /// The raw action type.
         kind: u8,
-        /// The data associated with this action.
-        data: RawActionData,
+        /// The bytes following the action type, up to the end of the macro.
+        data: Vec<u8>,
     },
 }
 
-/// Raw action data we don't yet know what to do with.
-#[derive(Clone, Copy, Debug)]
-pub enum RawActionData {
-    /// A single byte of data.
-    U8(u8),
-    /// A single u16.
-    OneU16(u16),
-    /// Two sequential u16s.
-    TwoU16(u16, u16),
+impl MacroAction {
+    /// Converts the action into a form suitable for sending to the keyboard.
+    pub fn to_command_data(&self) -> Result<Vec<u8>, MacroCommandDataError> {
+        let key_code_to_u8 = |key: KeyKind| {
+            u8::try_from(u16::from(key)).map_err(|_| MacroCommandDataError::KeyDoesNotFitInU8(key))
+        };
+
+        let data = match *self {
+            Self::RandomDelay { min, max } => {
+                [[1].as_slice(), &min.to_be_bytes(), &max.to_be_bytes()].concat()
+            }
+            Self::Delay(delay) => [[2].as_slice(), &delay.to_be_bytes()].concat(),
+            Self::SpecialKeyDown(key) => [[3].as_slice(), &u16::from(key).to_be_bytes()].concat(),
+            Self::SpecialKeyUp(key) => [[4].as_slice(), &u16::from(key).to_be_bytes()].concat(),
+            Self::Special(key) => [[5].as_slice(), &u16::from(key).to_be_bytes()].concat(),
+            Self::KeyDown(key) => vec![6, key_code_to_u8(key)?],
+            Self::KeyUp(key) => vec![7, key_code_to_u8(key)?],
+            Self::Press(key) => vec![8, key_code_to_u8(key)?],
+            Self::Unknown { kind, ref data } => [[kind].as_slice(), data].concat(),
+        };
+
+        Ok(data)
+    }
 }
 
 /// Takes an [`str`] and tries to parse it into a macro map.
 pub fn parse_macros(input: &str) -> Result<Vec<Macro>, String> {
-    let ((macros, _), _) = (repeat_till(1.., macro_parser, "0 "), rest)
-        .parse(input)
-        .map_err(|err| err.to_string())?;
+    input
+        .parse::<MacroMap>()
+        .map(|map| map.macros)
+        .map_err(|err| err.to_string())
+}
+
+fn macro_map_parser(input: &mut &str) -> ModalResult<Vec<Macro>> {
+    alt((
+        // Erased macro memory is entirely filled with 255
+        peek("255").value(Vec::new()),
+        repeat_till(1.., macro_parser, "0 ").map(|(macros, _)| macros),
+    ))
+    .context(StrContext::Label("macro map"))
+    .parse_next(input)
+}
 
-    Ok(macros)
+fn unused_memory_parser(input: &mut &str) -> ModalResult<Vec<u8>> {
+    // The last byte in memory is not followed by a space
+    repeat(0.., terminated(dec_uint::<_, u8, _>, opt(" ")))
+        .context(StrContext::Label("unused macro memory"))
+        .parse_next(input)
 }
 
 fn macro_parser(input: &mut &str) -> ModalResult<Macro> {
@@ -88,19 +255,6 @@ fn action_parser(input: &mut &str) -> ModalResult<MacroAction> {
         )))
         .parse_next(input)?;
 
-    // If the kind byte is not between 1 and 8 inclusive, we need to skip it.
-    // No clue why there would be a seemingly random byte here
-    let kind = if !(1..=8).contains(&kind) {
-        u8_parser
-            .context(context_label.clone())
-            .context(StrContext::Expected(StrContextValue::Description(
-                "u8 representing action type",
-            )))
-            .parse_next(input)?
-    } else {
-        kind
-    };
-
     let action = match kind {
         1 => {
             let (min, max) = (u16_parser, u16_parser)
@@ -116,12 +270,19 @@ fn action_parser(input: &mut &str) -> ModalResult<MacroAction> {
 
             MacroAction::Delay(delay)
         }
-        3..=4 => {
-            let data = action_data_one_u16_parser
+        3 => {
+            let key = u16_parser
                 .context(context_label.clone())
                 .parse_next(input)?;
 
-            MacroAction::Unknown { kind, data }
+            MacroAction::SpecialKeyDown(KeyKind::from(key))
+        }
+        4 => {
+            let key = u16_parser
+                .context(context_label.clone())
+                .parse_next(input)?;
+
+            MacroAction::SpecialKeyUp(KeyKind::from(key))
         }
         5 => {
             let key = u16_parser
@@ -146,14 +307,15 @@ fn action_parser(input: &mut &str) -> ModalResult<MacroAction> {
             MacroAction::Press(KeyKind::from(data as u16))
         }
         _ => {
-            let _ = fail
+            let data = repeat_till(0.., u8_parser, peek("0 "))
+                .map(|(data, _)| data)
                 .context(context_label)
                 .context(StrContext::Expected(StrContextValue::Description(
-                    "action type between 1 and 8 inclusive",
+                    "unknown action data followed by the end of the macro",
                 )))
                 .parse_next(input)?;
 
-            unreachable!()
+            MacroAction::Unknown { kind, data }
         }
     };
 
@@ -179,17 +341,6 @@ fn u16_parser(input: &mut &str) -> ModalResult<u16> {
     Ok(u16::from_ne_bytes([high_byte, low_byte]))
 }
 
-fn action_data_one_u16_parser(input: &mut &str) -> ModalResult<RawActionData> {
-    let data = u16_parser
-        .context(StrContext::Label("macro action data"))
-        .context(StrContext::Expected(StrContextValue::Description(
-            "two u16s representing action data",
-        )))
-        .parse_next(input)?;
-
-    Ok(RawActionData::OneU16(data))
-}
-
 #[cfg(test)]
 mod tests {
     use itertools::Itertools;
@@ -220,4 +371,108 @@ mod tests {
 
         // panic!()
     }
+
+    #[test]
+    fn macros_round_trip_to_command_data() {
+        let map = MACRO_DATA.parse::<MacroMap>().unwrap();
+
+        let data = map.to_command_data::<2048>().unwrap();
+
+        assert_eq!(data, MACRO_DATA);
+    }
+
+    #[test]
+    fn leftover_memory_follows_macro_map_terminator() {
+        let map = MACRO_DATA.parse::<MacroMap>().unwrap();
+
+        assert_eq!(map.macros.len(), 2);
+        // Leftover data byte of a `Press(T)` action whose action type was
+        // overwritten by the terminator of a shorter macro map
+        assert_eq!(map.unused_memory[..5], [23, 8, 8, 8, 7]);
+
+        let data = MacroMap::from(map.macros)
+            .to_command_data::<2048>()
+            .unwrap();
+
+        // Without the leftovers, everything after the end of the macro map is padding
+        assert!(MACRO_DATA.starts_with(data.trim_end_matches(" 255")));
+    }
+
+    #[test]
+    fn special_key_down_and_up_are_parsed() {
+        let map = "3 9 226 8 4 4 9 226 0 0 ".parse::<MacroMap>().unwrap();
+
+        assert_eq!(
+            map.macros[0].actions,
+            [
+                MacroAction::SpecialKeyDown(KeyKind::from(2530)),
+                MacroAction::Press(KeyKind::from(4)),
+                MacroAction::SpecialKeyUp(KeyKind::from(2530)),
+            ]
+        );
+    }
+
+    #[test]
+    fn unknown_action_types_round_trip() {
+        let data = "8 4 9 4 3 0 0 255";
+        let map = data.parse::<MacroMap>().unwrap();
+
+        assert_eq!(
+            map.macros[0].actions,
+            [
+                MacroAction::Press(KeyKind::from(4)),
+                MacroAction::Unknown {
+                    kind: 9,
+                    data: vec![4, 3],
+                },
+            ]
+        );
+        assert_eq!(map.to_sized_command_data(8).unwrap(), data);
+    }
+
+    #[test]
+    fn empty_macro_map_round_trips() {
+        let data = MacroMap::default().to_command_data::<2048>().unwrap();
+
+        let map = data.parse::<MacroMap>().unwrap();
+
+        assert!(map.macros.is_empty());
+        assert_eq!(map.to_command_data::<2048>().unwrap(), data);
+    }
+
+    #[test]
+    fn macro_actions_to_command_data() {
+        let actions = [
+            (
+                MacroAction::RandomDelay {
+                    min: 200,
+                    max: 1000,
+                },
+                "1 0 200 3 232",
+            ),
+            (MacroAction::Delay(1000), "2 3 232"),
+            (MacroAction::Special(KeyKind::from(17450)), "5 68 42"),
+            (MacroAction::KeyDown(KeyKind::from(225)), "6 225"),
+            (MacroAction::KeyUp(KeyKind::from(225)), "7 225"),
+            (MacroAction::Press(KeyKind::from(4)), "8 4"),
+            (MacroAction::SpecialKeyDown(KeyKind::from(2530)), "3 9 226"),
+            (MacroAction::SpecialKeyUp(KeyKind::from(2530)), "4 9 226"),
+        ];
+
+        for (action, expected) in actions {
+            assert_eq!(action.to_command_data().unwrap().iter().join(" "), expected);
+        }
+    }
+
+    #[test]
+    fn to_command_data_fails_on_wide_key_codes() {
+        let map = MacroMap::from(vec![Macro {
+            actions: vec![MacroAction::Press(KeyKind::from(17450))],
+        }]);
+
+        assert!(matches!(
+            map.to_command_data::<2048>(),
+            Err(MacroCommandDataError::KeyDoesNotFitInU8(_))
+        ));
+    }
 }
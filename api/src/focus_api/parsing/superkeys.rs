@@ -6,7 +6,7 @@ use std::str::FromStr;
 use winnow::{
     ModalResult, Parser,
     ascii::{dec_uint, space1},
-    combinator::{repeat, repeat_till, terminated},
+    combinator::{repeat_till, terminated},
     token::rest,
 };
 
@@ -27,6 +27,12 @@ impl FromStr for SuperkeyMap {
     type Err = ParseSuperkeyMapError;
 
     fn from_str(s: &str) -> Result<Self, Self::Err> {
+        // An empty map is left entirely unwritten, which is also how
+        // `to_sized_command_data` serializes it
+        if s.split_whitespace().all(|value| value == "65535") {
+            return Ok(Self(vec![]));
+        }
+
         let map = super_keys_parser
             .parse(s)
             .map_err(|err| ParseSuperkeyMapError(err.to_string()))?;
@@ -40,9 +46,18 @@ impl SuperkeyMap {
     /// the keyboard as command data.
     pub fn to_command_data<const MEMORY_SIZE: usize>(
         &self,
+    ) -> Result<String, TooManySuperkeysError> {
+        self.to_sized_command_data(MEMORY_SIZE)
+    }
+
+    /// Like [`SuperkeyMap::to_command_data`], for when the memory size is
+    /// only known at runtime, such as when it depends on the keyboard model.
+    pub fn to_sized_command_data(
+        &self,
+        memory_size: usize,
     ) -> Result<String, TooManySuperkeysError> {
         if self.0.is_empty() {
-            return Ok([u16::MAX; MEMORY_SIZE].iter().join(" "));
+            return Ok(vec![u16::MAX; memory_size].iter().join(" "));
         }
 
         let superkeys = self
@@ -53,11 +68,11 @@ impl SuperkeyMap {
             // superkey map
             .chain([0]);
 
-        if superkeys.clone().count() > MEMORY_SIZE {
+        if superkeys.clone().count() > memory_size {
             return Err(TooManySuperkeysError);
         }
 
-        let mut res = [u16::MAX; MEMORY_SIZE];
+        let mut res = vec![u16::MAX; memory_size];
 
         res.iter_mut()
             .zip(superkeys)
@@ -161,4 +176,11 @@ mod tests {
     fn parse_succeeds() {
         let _ = SUPERKEY_DATA.parse::<SuperkeyMap>().unwrap();
     }
+
+    #[test]
+    fn empty_map_round_trips() {
+        let data = SuperkeyMap(vec![]).to_sized_command_data(16).unwrap();
+
+        assert!(data.parse::<SuperkeyMap>().unwrap().0.is_empty());
+    }
 }
@@ -0,0 +1,193 @@
+//! This module defines the [`ReceiveBuffer`] type, which holds on to the data
+//! received from a device across commands, so that nothing is lost when a
+//! read contains more than a single response.
+
+use super::parsing::focus_api::{FocusApiCommandResponse, ParseResponseError};
+
+/// Maximum amount of unsolicited data kept until it is taken, the oldest
+/// data being dropped first.
+const MAX_UNSOLICITED_DATA_LEN: usize = 4096;
+
+/// Data received from a device which has not been handed out as a response
+/// yet.
+#[derive(Debug, Default)]
+pub(crate) struct ReceiveBuffer {
+    data: Vec<u8>,
+    /// The firmware follows the `.` terminating each response with a line
+    /// ending, which may not have been received yet.
+    after_response: bool,
+    unsolicited: Vec<u8>,
+}
+
+impl ReceiveBuffer {
+    /// Appends data received from the device.
+    pub(crate) fn extend(&mut self, data: &[u8]) {
+        self.data.extend_from_slice(data);
+    }
+
+    /// Takes the next complete response out of the buffer, if there is one.
+    pub(crate) fn next_response(&mut self) -> Result<Option<String>, ParseResponseError> {
+        self.skip_line_ending_after_response();
+
+        if self.after_response {
+            return Ok(None);
+        }
+
+        // The data may end in the middle of a multi-byte character, or be
+        // followed by garbage, so only the valid part is parsed
+        let (data, utf8_err) = match str::from_utf8(&self.data) {
+            Ok(data) => (data, None),
+            Err(err) => (
+                str::from_utf8(&self.data[..err.valid_up_to()]).unwrap(),
+                err.error_len().map(|_| err),
+            ),
+        };
+
+        match FocusApiCommandResponse::parse_prefix(data) {
+            Ok((res, len)) => {
+                self.data.drain(..len);
+                self.after_response = true;
+                self.skip_line_ending_after_response();
+
+                Ok(Some(res.into_inner()))
+            }
+            Err(ParseResponseError::Incomplete) => match utf8_err {
+                Some(err) => Err(ParseResponseError::Err(format!(
+                    "response is not valid UTF-8: {err}"
+                ))),
+                None => Ok(None),
+            },
+            Err(err) => Err(err),
+        }
+    }
+
+    /// Sets aside everything in the buffer as unsolicited data, as it is not
+    /// part of any response, returning how many bytes were set aside.
+    pub(crate) fn discard(&mut self) -> usize {
+        self.skip_line_ending_after_response();
+
+        if self.after_response {
+            // Only the line ending after the last response, or part of it,
+            // was received
+            if self.data != b"\r" {
+                self.after_response = false;
+            }
+
+            self.data.clear();
+
+            return 0;
+        }
+
+        let len = self.data.len();
+
+        self.unsolicited.append(&mut self.data);
+
+        let overflow = self
+            .unsolicited
+            .len()
+            .saturating_sub(MAX_UNSOLICITED_DATA_LEN);
+
+        self.unsolicited.drain(..overflow);
+
+        len
+    }
+
+    /// Takes the unsolicited data set aside so far.
+    pub(crate) fn take_unsolicited(&mut self) -> Vec<u8> {
+        std::mem::take(&mut self.unsolicited)
+    }
+
+    fn skip_line_ending_after_response(&mut self) {
+        if !self.after_response {
+            return;
+        }
+
+        let line_ending_len = if self.data.starts_with(b"\r\n") {
+            2
+        } else if self.data.starts_with(b"\n") {
+            1
+        } else if self.data.is_empty() || self.data == b"\r" {
+            return;
+        } else {
+            self.after_response = false;
+
+            return;
+        };
+
+        match self.data.get(line_ending_len) {
+            // Wait for more data to tell who the line ending belongs to
+            None => {}
+            // The terminating `.` is never followed by another `.`, so the
+            // line ending must be the start of an empty response instead
+            Some(b'.') => self.after_response = false,
+            Some(_) => {
+                self.data.drain(..line_ending_len);
+                self.after_response = false;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn responses_received_together_are_kept_apart() {
+        let mut buffer = ReceiveBuffer::default();
+
+        buffer.extend(b"v1.0.0\r\n.\r\nhelp\r\nver");
+
+        assert_eq!(buffer.next_response().unwrap().as_deref(), Some("v1.0.0"));
+        assert_eq!(buffer.next_response().unwrap(), None);
+
+        buffer.extend(b"sion\r\n.\r\n");
+
+        assert_eq!(
+            buffer.next_response().unwrap().as_deref(),
+            Some("help\nversion")
+        );
+    }
+
+    #[test]
+    fn empty_responses_are_not_mistaken_for_line_endings() {
+        let mut buffer = ReceiveBuffer::default();
+
+        buffer.extend(b"0\r\n.\r\n");
+        assert_eq!(buffer.next_response().unwrap().as_deref(), Some("0"));
+
+        buffer.extend(b"\r\n.\r\n");
+        assert_eq!(buffer.next_response().unwrap().as_deref(), Some(""));
+
+        // Without the line ending after the terminating `.`
+        buffer.extend(b"1\r\n.");
+        assert_eq!(buffer.next_response().unwrap().as_deref(), Some("1"));
+
+        buffer.extend(b"\r\n.");
+        assert_eq!(buffer.next_response().unwrap().as_deref(), Some(""));
+    }
+
+    #[test]
+    fn leftover_data_is_set_aside_as_unsolicited() {
+        let mut buffer = ReceiveBuffer::default();
+
+        buffer.extend(b"0\r\n.\r\nstray");
+
+        assert_eq!(buffer.next_response().unwrap().as_deref(), Some("0"));
+
+        buffer.discard();
+
+        assert_eq!(buffer.next_response().unwrap(), None);
+        assert_eq!(buffer.take_unsolicited(), b"stray");
+        assert!(buffer.take_unsolicited().is_empty());
+    }
+
+    #[test]
+    fn invalid_utf8_is_an_error() {
+        let mut buffer = ReceiveBuffer::default();
+
+        buffer.extend(b"ok\r\n\xff\xfe");
+
+        assert!(buffer.next_response().is_err());
+    }
+}
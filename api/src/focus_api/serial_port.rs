@@ -6,6 +6,8 @@ use pin_project::pin_project;
 use tokio::io::{AsyncRead, AsyncWrite};
 use tokio_serial::{SerialPortBuilderExt, SerialPortType, UsbPortInfo};
 
+use super::DeviceSelector;
+
 /// Error opening serial port.
 #[derive(Debug, Display, Error)]
 pub enum OpenSerialPortError {
@@ -73,27 +75,37 @@ impl AsyncWrite for SerialPort {
 
 impl SerialPort {
     /// Searches for and opens a serial port with the device.
+    ///
+    /// If the selector is a [`DeviceSelector::Path`], the port is opened
+    /// directly, without searching for it.
     pub async fn connect(
         manufacturer_name: &str,
         product_name: &str,
         baud_rate: u32,
+        selector: &DeviceSelector,
     ) -> Result<SerialPort, OpenSerialPortError> {
-        let port_name = tokio_serial::available_ports()
-            .map_err(OpenSerialPortError::EnumeratingDevices)?
-            .into_iter()
-            .filter(|info| {
-                matches!(
-                      &info.port_type,
-                    SerialPortType::UsbPort(UsbPortInfo {
-                        manufacturer: Some(manufacturer),
-                        product: Some(product),
-                        ..
-                    }) if manufacturer == manufacturer_name  && product ==  product_name
-                )
-            })
-            .map(|info| info.port_name)
-            .next()
-            .ok_or(OpenSerialPortError::DeviceNotFound)?;
+        let port_name = if let DeviceSelector::Path(path) = selector {
+            path.clone()
+        } else {
+            tokio_serial::available_ports()
+                .map_err(OpenSerialPortError::EnumeratingDevices)?
+                .into_iter()
+                .filter(|info| {
+                    matches!(
+                          &info.port_type,
+                        SerialPortType::UsbPort(usb_info @ UsbPortInfo {
+                            manufacturer: Some(manufacturer),
+                            product: Some(product),
+                            ..
+                        }) if manufacturer == manufacturer_name
+                            && product == product_name
+                            && selector.matches_serial_port(&info.port_name, usb_info)
+                    )
+                })
+                .map(|info| info.port_name)
+                .next()
+                .ok_or(OpenSerialPortError::DeviceNotFound)?
+        };
 
         let port = tokio_serial::new(&port_name, baud_rate)
             .timeout(std::time::Duration::from_secs(5))
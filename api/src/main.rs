@@ -1,16 +1,35 @@
 #[macro_use]
 extern crate derive_more;
 
-use clap::{Parser, Subcommand};
-use dygma_cli::devices::defy::{DefyKeyboard, DefyKeymap, SuperkeyMap};
-use dygma_cli::focus_api::{FocusApiConnection, parsing};
+use clap::{Args, Parser, Subcommand};
+use dygma_cli::devices::{
+    AnyKeyboard, AnyKeymap, Keyboard, Model,
+    backup::DeviceBackup,
+    bazecor::BazecorBackup,
+    defy::{DefyMacroMap, SuperkeyMap},
+    lighting::{AnyColormap, Color, Palette},
+    qukeys::DualFunctionSettings,
+    settings::if_supported,
+    superkeys::SuperkeyConfig,
+    wireless::Battery,
+};
+use dygma_cli::focus_api::{
+    ConnectionEvent, DeviceSelector, FocusApiConnection, discover_devices, parsing,
+};
 use dygma_cli::keycode_tables::{Blank, KeyKind};
 use error_stack::{IntoReport, ResultExt};
 use itertools::Itertools;
-use std::path::{Path, PathBuf};
+use std::{
+    path::{Path, PathBuf},
+    str::FromStr,
+    sync::Mutex,
+    time::Duration,
+};
 use tokio::{
     fs::File,
     io::{AsyncReadExt, AsyncWriteExt, BufReader, BufWriter},
+    sync::mpsc,
+    task::JoinHandle,
 };
 
 #[derive(Clone, Copy, Debug, Display, Error)]
@@ -22,7 +41,27 @@ struct Error;
 /// Made with Rust and <3.
 #[derive(Parser)]
 #[clap(about, author)]
-enum Cli {
+struct Cli {
+    #[command(flatten)]
+    device: Device,
+    #[command(subcommand)]
+    command: Commands,
+}
+
+impl Cli {
+    async fn perform(self) -> Result<(), error_stack::Report<Error>> {
+        let res = self.command.perform(&self.device).await;
+
+        // The keyboards are dropped by now, so this only waits for the events
+        // they left behind to be printed.
+        self.device.finish_events().await;
+
+        res
+    }
+}
+
+#[derive(Subcommand)]
+enum Commands {
     /// Commands for talking with your device.
     #[command(subcommand)]
     Command(CommandCommands),
@@ -32,18 +71,252 @@ enum Cli {
     /// Commands for working with superkeys.
     #[command(subcommand)]
     Superkeys(SuperkeyCommands),
+    /// Commands for working with macros.
+    #[command(subcommand)]
+    Macros(MacroCommands),
+    /// Commands for working with the LED palette.
+    #[command(subcommand)]
+    Palette(PaletteCommands),
+    /// Commands for working with the LED colormap.
+    #[command(subcommand)]
+    Colormap(ColormapCommands),
+    /// Commands for controlling the LEDs live, without changing the stored
+    /// colormap.
+    #[command(subcommand)]
+    Led(LedCommands),
+    /// Commands for working with the keyboard's settings.
+    #[command(subcommand)]
+    Settings(SettingsCommands),
+    /// Commands for switching between layers.
+    #[command(subcommand)]
+    Layer(LayerCommands),
+    /// Commands for configuring mouse keys.
+    #[command(subcommand)]
+    Mouse(MouseCommands),
+    /// Commands for configuring when dual-function keys act as their
+    /// modifier or layer.
+    #[command(subcommand)]
+    DualFunction(DualFunctionCommands),
+    /// Shows the battery, energy and wireless status of the keyboard.
+    Status(StatusArgs),
+    /// Saves everything stored on the keyboard to a single backup file.
+    Backup(BackupArgs),
+    /// Restores a backup file onto the keyboard, after checking that it was
+    /// made from a compatible keyboard.
+    Restore(RestoreArgs),
+    /// Commands for converting files from other tools into backup files.
+    #[command(subcommand)]
+    Import(ImportCommands),
+    /// Commands for converting backup files into files for other tools.
+    #[command(subcommand)]
+    Export(ExportCommands),
     /// Commands for working with keymap key codes.
     #[command(subcommand)]
     KeyCode(KeyCodeCommands),
+    /// Commands for finding connected devices.
+    #[command(subcommand)]
+    Devices(DevicesCommands),
 }
 
-impl Cli {
-    async fn perform(self) -> Result<(), error_stack::Report<Error>> {
+impl Commands {
+    async fn perform(self, device: &Device) -> Result<(), error_stack::Report<Error>> {
         match self {
-            Self::Command(cmd) => cmd.perform().await,
-            Self::Keymap(cmd) => cmd.perform().await,
-            Self::Superkeys(cmd) => cmd.perform().await,
+            Self::Command(cmd) => cmd.perform(device).await,
+            Self::Keymap(cmd) => cmd.perform(device).await,
+            Self::Superkeys(cmd) => cmd.perform(device).await,
+            Self::Macros(cmd) => cmd.perform(device).await,
+            Self::Palette(cmd) => cmd.perform(device).await,
+            Self::Colormap(cmd) => cmd.perform(device).await,
+            Self::Led(cmd) => cmd.perform(device).await,
+            Self::Settings(cmd) => cmd.perform(device).await,
+            Self::Layer(cmd) => cmd.perform(device).await,
+            Self::Mouse(cmd) => cmd.perform(device).await,
+            Self::DualFunction(cmd) => cmd.perform(device).await,
+            Self::Status(cmd) => cmd.perform(device).await,
+            Self::Backup(cmd) => cmd.perform(device).await,
+            Self::Restore(cmd) => cmd.perform(device).await,
+            Self::Import(cmd) => cmd.perform(device).await,
+            Self::Export(cmd) => cmd.perform().await,
             Self::KeyCode(cmd) => cmd.perform(),
+            Self::Devices(cmd) => cmd.perform().await,
+        }
+    }
+}
+
+/// The device the CLI talks to, and how to talk to it.
+#[derive(Args)]
+struct Device {
+    /// The device to talk to.
+    ///
+    /// Can be `any` to use the first device found, a serial port path such
+    /// as `/dev/ttyACM0` or `COM3`, `serial:<USB serial number>`,
+    /// `hid:<HID device id>`, or `virtual` to use a keyboard emulated in memory.
+    #[arg(
+        long = "device",
+        value_name = "DEVICE",
+        global = true,
+        default_value = "any"
+    )]
+    target: DeviceTarget,
+    /// How many seconds to wait for each command to complete before giving
+    /// up. Use `0` to wait forever.
+    #[arg(long, value_name = "SECONDS", global = true, default_value_t = 5)]
+    timeout: u64,
+    /// The keyboard model, one of `defy`, `raise` or `raise2`.
+    ///
+    /// Detected from the connected device when omitted. Also used for
+    /// interpreting raw data passed on the command line, and for choosing
+    /// the model of the `virtual` device, where it defaults to `defy`.
+    #[arg(long, value_name = "MODEL", global = true)]
+    model: Option<Model>,
+    /// The tasks printing the connection events of each connected keyboard.
+    #[arg(skip)]
+    event_printers: Mutex<Vec<JoinHandle<()>>>,
+}
+
+impl Device {
+    async fn connect(&self) -> Result<AnyKeyboard, error_stack::Report<Error>> {
+        let mut keyboard = self.target.connect(self.model).await?;
+
+        keyboard.set_timeout((self.timeout > 0).then(|| Duration::from_secs(self.timeout)));
+
+        if let Some(events) = keyboard.take_events() {
+            self.event_printers
+                .lock()
+                .expect("event printers shouldn't be poisoned")
+                .push(tokio::spawn(print_connection_events(events)));
+        }
+
+        Ok(keyboard)
+    }
+
+    /// Waits until the events of every keyboard connected so far have been
+    /// printed, which happens once the keyboards are dropped.
+    async fn finish_events(&self) {
+        let printers = std::mem::take(
+            &mut *self
+                .event_printers
+                .lock()
+                .expect("event printers shouldn't be poisoned"),
+        );
+
+        for printer in printers {
+            let _ = printer.await;
+        }
+    }
+
+    /// The model used when there is no device to detect it from.
+    fn model_or_default(&self) -> Model {
+        self.model.unwrap_or(Model::Defy)
+    }
+}
+
+/// Prints connection events to stderr as they happen, so they don't mix with
+/// command output.
+async fn print_connection_events(mut events: mpsc::Receiver<ConnectionEvent>) {
+    while let Some(event) = events.recv().await {
+        match event {
+            ConnectionEvent::SlowTransport | ConnectionEvent::FellBackToHid { .. } => {
+                eprintln!("Warning: {event}")
+            }
+            ConnectionEvent::SendingPaced { .. } => eprintln!("{event}, this may take a while"),
+            ConnectionEvent::Resynchronised { .. } | ConnectionEvent::UnsolicitedData { .. } => {
+                eprintln!("Note: {event}")
+            }
+        }
+    }
+}
+
+/// Which device the CLI talks to.
+#[derive(Clone, Debug)]
+enum DeviceTarget {
+    /// A keyboard emulated in memory.
+    Virtual,
+    /// A physical device chosen by the selector.
+    Physical(DeviceSelector),
+}
+
+impl FromStr for DeviceTarget {
+    type Err = dygma_cli::focus_api::ParseDeviceSelectorError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if s == "virtual" {
+            return Ok(Self::Virtual);
+        }
+
+        s.parse().map(Self::Physical)
+    }
+}
+
+impl DeviceTarget {
+    async fn connect(
+        &self,
+        model: Option<Model>,
+    ) -> Result<AnyKeyboard, error_stack::Report<Error>> {
+        match (self, model) {
+            (Self::Virtual, model) => Ok(AnyKeyboard::new_virtual(model.unwrap_or(Model::Defy))),
+            (Self::Physical(selector), Some(model)) => AnyKeyboard::connect(model, selector)
+                .await
+                .change_context(Error)
+                .attach_with(|| format!("connecting to the {model} keyboard"))
+                .attach_with(|| format!("device: {selector}")),
+            (Self::Physical(selector), None) => AnyKeyboard::detect_with_selector(selector)
+                .await
+                .change_context(Error)
+                .attach("detecting and connecting to the keyboard")
+                .attach_with(|| format!("device: {selector}")),
+        }
+    }
+}
+
+#[derive(Subcommand)]
+enum DevicesCommands {
+    /// Lists every connected Dygma device.
+    ///
+    /// The selector shown for each device can be passed to the `--device`
+    /// flag to talk to that device.
+    List {
+        /// Output the list as JSON.
+        #[arg(long)]
+        json: bool,
+    },
+}
+
+impl DevicesCommands {
+    async fn perform(self) -> Result<(), error_stack::Report<Error>> {
+        match self {
+            Self::List { json } => {
+                let devices = discover_devices()
+                    .await
+                    .change_context(Error)
+                    .attach("discovering connected devices")?;
+
+                if json {
+                    let data = serde_json::to_string_pretty(&devices).unwrap();
+
+                    println!("{data}");
+                } else if devices.is_empty() {
+                    println!("No devices found");
+                } else {
+                    for device in devices {
+                        println!(
+                            "{} ({:04x}:{:04x}) over {}, {}",
+                            device.product_name.as_deref().unwrap_or("Unknown device"),
+                            device.vendor_id,
+                            device.product_id,
+                            device.transport,
+                            device.link,
+                        );
+                        println!("  selector: {}", device.selector());
+
+                        if let Some(serial_number) = &device.serial_number {
+                            println!("  serial number: {serial_number}");
+                        }
+                    }
+                }
+
+                Ok(())
+            }
         }
     }
 }
@@ -74,18 +347,21 @@ enum CommandCommands {
         /// If provided, filters commands that contain this term.
         term: Option<String>,
     },
+    /// Shows what the firmware on the device supports.
+    Capabilities {
+        /// Output the capabilities as JSON.
+        #[arg(long)]
+        json: bool,
+    },
 }
 
 impl CommandCommands {
-    async fn perform(self) -> Result<(), error_stack::Report<Error>> {
+    async fn perform(self, device: &Device) -> Result<(), error_stack::Report<Error>> {
         match self {
             Self::Run { cmd, data } => {
-                let mut defy = DefyKeyboard::new()
-                    .await
-                    .change_context(Error)
-                    .attach("connecting to the Defy keyboard")?;
+                let mut keyboard = device.connect().await?;
 
-                let available_cmds = defy
+                let available_cmds = keyboard
                     .available_commands()
                     .await
                     .change_context(Error)
@@ -102,7 +378,7 @@ impl CommandCommands {
                         .change_context(Error));
                 }
 
-                let res = defy
+                let res = keyboard
                     .run_command(&cmd, data.as_deref())
                     .await
                     .change_context(Error)
@@ -113,12 +389,10 @@ impl CommandCommands {
                 Ok(())
             }
             Self::List { term } => {
-                let mut defy = DefyKeyboard::new()
-                    .await
-                    .change_context(Error)
-                    .attach("connecting to the Defy keyboard")?;
+                let mut keyboard = device.connect().await?;
 
-                defy.available_commands()
+                keyboard
+                    .available_commands()
                     .await
                     .change_context(Error)
                     .attach("getting list of available commands")?
@@ -134,6 +408,421 @@ impl CommandCommands {
 
                 Ok(())
             }
+            Self::Capabilities { json } => {
+                let mut keyboard = device.connect().await?;
+
+                let capabilities = keyboard
+                    .capabilities()
+                    .await
+                    .change_context(Error)
+                    .attach("getting the firmware capabilities")?;
+
+                if json {
+                    let data = serde_json::to_string_pretty(capabilities).unwrap();
+
+                    println!("{data}");
+                } else {
+                    let unknown = "unknown";
+
+                    println!(
+                        "firmware: {}",
+                        capabilities.firmware_version.as_deref().unwrap_or(unknown)
+                    );
+                    println!(
+                        "hardware: {}",
+                        capabilities.hardware_version.as_deref().unwrap_or(unknown)
+                    );
+                    println!(
+                        "layout: {}",
+                        capabilities.layout.as_deref().unwrap_or(unknown)
+                    );
+                    println!(
+                        "chip id: {}",
+                        capabilities.chip_id.as_deref().unwrap_or(unknown)
+                    );
+                    println!("commands: {}", capabilities.commands.len());
+                }
+
+                Ok(())
+            }
+        }
+    }
+}
+
+#[derive(Subcommand)]
+enum SettingsCommands {
+    /// Shows every setting the firmware supports.
+    Show {
+        /// Output the settings as JSON.
+        #[arg(long)]
+        json: bool,
+    },
+    /// Gets or sets the layer the keyboard starts on.
+    DefaultLayer {
+        /// The layer to start on. Index starts at 1.
+        ///
+        /// If omitted, the current default layer is printed.
+        #[arg(value_parser = clap::value_parser!(u8).range(1..))]
+        layer: Option<u8>,
+    },
+    /// Gets or sets how often the keyboard halves check on each other.
+    AliveInterval {
+        /// The interval, in milliseconds.
+        ///
+        /// If omitted, the current interval is printed.
+        interval: Option<u32>,
+    },
+}
+
+impl SettingsCommands {
+    async fn perform(self, device: &Device) -> Result<(), error_stack::Report<Error>> {
+        let mut keyboard = device.connect().await?;
+
+        match self {
+            Self::Show { json } => {
+                let settings = keyboard
+                    .get_settings()
+                    .await
+                    .change_context(Error)
+                    .attach("getting settings from keyboard")?;
+
+                if json {
+                    let data = serde_json::to_string_pretty(&settings).unwrap();
+
+                    println!("{data}");
+
+                    return Ok(());
+                }
+
+                fn print_setting(name: &str, value: Option<impl std::fmt::Display>) {
+                    match value {
+                        Some(value) => println!("{name}: {value}"),
+                        None => println!("{name}: unsupported"),
+                    }
+                }
+
+                print_setting("firmware version", settings.firmware_version);
+                print_setting(
+                    "default layer",
+                    settings.default_layer.map(|layer| layer + 1),
+                );
+                print_setting("valid", settings.valid);
+                print_setting("version", settings.version);
+                print_setting("crc", settings.crc);
+                print_setting("alive interval (ms)", settings.alive_interval);
+            }
+            Self::DefaultLayer { layer: None } => {
+                let layer = keyboard
+                    .get_default_layer()
+                    .await
+                    .change_context(Error)
+                    .attach("getting default layer from keyboard")?;
+
+                println!("{}", layer + 1);
+            }
+            Self::DefaultLayer { layer: Some(layer) } => {
+                keyboard
+                    .set_default_layer(layer - 1)
+                    .await
+                    .change_context(Error)
+                    .attach("setting default layer on keyboard")?;
+            }
+            Self::AliveInterval { interval: None } => {
+                let interval = keyboard
+                    .get_alive_interval()
+                    .await
+                    .change_context(Error)
+                    .attach("getting alive interval from keyboard")?;
+
+                println!("{interval}");
+            }
+            Self::AliveInterval {
+                interval: Some(interval),
+            } => {
+                keyboard
+                    .set_alive_interval(interval)
+                    .await
+                    .change_context(Error)
+                    .attach("setting alive interval on keyboard")?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[derive(Subcommand)]
+enum LayerCommands {
+    /// Activates a layer, on top of the active ones.
+    Activate {
+        /// The layer to activate. Index starts at 1.
+        #[arg(value_parser = clap::value_parser!(u8).range(1..))]
+        layer: u8,
+    },
+    /// Deactivates a layer.
+    Deactivate {
+        /// The layer to deactivate. Index starts at 1.
+        #[arg(value_parser = clap::value_parser!(u8).range(1..))]
+        layer: u8,
+    },
+    /// Prints whether a layer is active.
+    IsActive {
+        /// The layer to check. Index starts at 1.
+        #[arg(value_parser = clap::value_parser!(u8).range(1..))]
+        layer: u8,
+    },
+    /// Lists the active layers. Index starts at 1.
+    Active {
+        /// Output the layers as JSON.
+        #[arg(long)]
+        json: bool,
+    },
+}
+
+impl LayerCommands {
+    async fn perform(self, device: &Device) -> Result<(), error_stack::Report<Error>> {
+        let mut keyboard = device.connect().await?;
+
+        match self {
+            Self::Activate { layer } => {
+                keyboard
+                    .activate_layer(layer - 1)
+                    .await
+                    .change_context(Error)
+                    .attach_with(|| format!("activating layer {layer}"))?;
+            }
+            Self::Deactivate { layer } => {
+                keyboard
+                    .deactivate_layer(layer - 1)
+                    .await
+                    .change_context(Error)
+                    .attach_with(|| format!("deactivating layer {layer}"))?;
+            }
+            Self::IsActive { layer } => {
+                let active = keyboard
+                    .is_layer_active(layer - 1)
+                    .await
+                    .change_context(Error)
+                    .attach_with(|| format!("checking whether layer {layer} is active"))?;
+
+                println!("{active}");
+            }
+            Self::Active { json } => {
+                let layers = keyboard
+                    .active_layers()
+                    .await
+                    .change_context(Error)
+                    .attach("getting active layers from keyboard")?
+                    .into_iter()
+                    .map(|layer| layer + 1)
+                    .collect::<Vec<_>>();
+
+                if json {
+                    println!("{}", serde_json::to_string(&layers).unwrap());
+                } else {
+                    println!("{}", layers.iter().join(" "));
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[derive(Args)]
+struct StatusArgs {
+    /// Output the status as JSON.
+    #[arg(long)]
+    json: bool,
+}
+
+impl StatusArgs {
+    async fn perform(self, device: &Device) -> Result<(), error_stack::Report<Error>> {
+        let mut keyboard = device.connect().await?;
+
+        let status = keyboard
+            .get_wireless_status()
+            .await
+            .change_context(Error)
+            .attach("getting wireless status from keyboard")?;
+
+        if self.json {
+            let data = serde_json::to_string_pretty(&status).unwrap();
+
+            println!("{data}");
+
+            return Ok(());
+        }
+
+        fn print_status(name: &str, value: Option<impl std::fmt::Display>) {
+            match value {
+                Some(value) => println!("{name}: {value}"),
+                None => println!("{name}: unsupported"),
+            }
+        }
+
+        fn on_off(value: bool) -> &'static str {
+            if value { "on" } else { "off" }
+        }
+
+        let battery = |battery: Option<Battery>| {
+            battery.map(|battery| format!("{}% ({})", battery.level, battery.status))
+        };
+
+        print_status("left battery", battery(status.left_battery));
+        print_status("right battery", battery(status.right_battery));
+        print_status("power saving", status.power_saving.map(on_off));
+        print_status("RF power", status.rf_power);
+        print_status("RF channel hopping", status.rf_channel_hop.map(on_off));
+        print_status(
+            "bluetooth devices",
+            status.bluetooth_devices.map(|devices| {
+                if devices.is_empty() {
+                    "none".to_owned()
+                } else {
+                    devices.join(", ")
+                }
+            }),
+        );
+
+        Ok(())
+    }
+}
+
+#[derive(Args)]
+struct BackupArgs {
+    /// The path the backup will be saved to.
+    #[clap(default_value = "backup.json")]
+    path: PathBuf,
+}
+
+impl BackupArgs {
+    async fn perform(self, device: &Device) -> Result<(), error_stack::Report<Error>> {
+        let mut keyboard = device.connect().await?;
+
+        let backup = keyboard
+            .get_backup()
+            .await
+            .change_context(Error)
+            .attach("backing up the keyboard")?;
+
+        safe_pretty_json_file(&backup, &self.path).await
+    }
+}
+
+#[derive(Args)]
+struct RestoreArgs {
+    /// The path of the backup file.
+    path: PathBuf,
+}
+
+impl RestoreArgs {
+    async fn perform(self, device: &Device) -> Result<(), error_stack::Report<Error>> {
+        let backup = read_json_file::<DeviceBackup>(&self.path).await?;
+
+        let mut keyboard = device.connect().await?;
+
+        let capabilities = keyboard
+            .capabilities()
+            .await
+            .change_context(Error)
+            .attach("getting the firmware capabilities")?;
+
+        if backup.firmware_differs(capabilities) {
+            eprintln!(
+                "Note: the backup was made with firmware {}, but the keyboard runs {}",
+                backup
+                    .device
+                    .firmware_version
+                    .as_deref()
+                    .unwrap_or("unknown"),
+                capabilities
+                    .firmware_version
+                    .as_deref()
+                    .unwrap_or("unknown"),
+            );
+        }
+
+        keyboard
+            .restore_backup(&backup)
+            .await
+            .change_context(Error)
+            .attach("restoring the backup onto the keyboard")?;
+
+        Ok(())
+    }
+}
+
+#[derive(Subcommand)]
+enum ImportCommands {
+    /// Converts a backup saved by Bazecor into a backup file.
+    ///
+    /// Layouts shared from Bazecor's layout editor aren't supported yet.
+    ///
+    /// Bazecor backups don't say which model they were made from, so it is
+    /// worked out from the colormap and palette, unless the global `--model`
+    /// flag is passed. The import fails when the backup fits several models.
+    Bazecor {
+        /// The path of the Bazecor backup.
+        path: PathBuf,
+        /// The path the backup file will be saved to.
+        #[clap(default_value = "backup.json")]
+        output: PathBuf,
+    },
+}
+
+impl ImportCommands {
+    async fn perform(self, device: &Device) -> Result<(), error_stack::Report<Error>> {
+        match self {
+            Self::Bazecor { path, output } => {
+                let bazecor = BazecorBackup::from_json_value(read_json_file(&path).await?)
+                    .change_context(Error)
+                    .attach("reading the Bazecor backup")
+                    .attach_with(|| path.to_string_lossy().into_owned())?;
+
+                let model = match device.model {
+                    Some(model) => model,
+                    None => bazecor
+                        .detect_model()
+                        .change_context(Error)
+                        .attach("detecting the model of the Bazecor backup")?,
+                };
+
+                let backup = bazecor
+                    .to_device_backup(model)
+                    .change_context(Error)
+                    .attach("converting the Bazecor backup")?;
+
+                safe_pretty_json_file(&backup, &output).await
+            }
+        }
+    }
+}
+
+#[derive(Subcommand)]
+enum ExportCommands {
+    /// Converts a backup file into a backup Bazecor can restore.
+    Bazecor {
+        /// The path of the backup file.
+        path: PathBuf,
+        /// The path the Bazecor backup will be saved to.
+        #[clap(default_value = "bazecor-backup.json")]
+        output: PathBuf,
+    },
+}
+
+impl ExportCommands {
+    async fn perform(self) -> Result<(), error_stack::Report<Error>> {
+        match self {
+            Self::Bazecor { path, output } => {
+                let backup = read_json_file::<DeviceBackup>(&path).await?;
+
+                let bazecor = BazecorBackup::from_device_backup(&backup)
+                    .change_context(Error)
+                    .attach("converting the backup for Bazecor")?;
+
+                safe_pretty_json_file(&bazecor, &output).await
+            }
         }
     }
 }
@@ -168,97 +857,490 @@ enum KeymapCommands {
         /// The path of the keymap file.
         path: PathBuf,
     },
-    /// Clears an entire layer, optionally with the specified key.
-    ///
-    /// This command does not automatically apply the change to the keyboard,
-    /// only to the keymap file.
-    ClearLayer {
-        /// The path of the keymap file.
+    /// Clears an entire layer, optionally with the specified key.
+    ///
+    /// This command does not automatically apply the change to the keyboard,
+    /// only to the keymap file.
+    ClearLayer {
+        /// The path of the keymap file.
+        path: PathBuf,
+        /// The layer number to clear. Index starts at 1.
+        ///
+        /// The layer number should match the `layer_number` shown in your keymap JSON
+        /// file after running the `keymap format` command.
+        #[clap(short, long)]
+        layer: u8,
+        /// The key to use to clear the layer.
+        #[arg(short, long, default_value_t = KeyKind::Blank(Blank::NoKey))]
+        key: KeyKind,
+    },
+    /// Saves the factory keymap built into the firmware to a file.
+    Default {
+        /// The path the keymap will be saved to.
+        #[clap(default_value = "default-keymap.json")]
+        path: PathBuf,
+    },
+    /// Lists the keys that differ from the factory keymap.
+    ///
+    /// Layers missing from the factory keymap are compared against blank
+    /// layers, which is what `keymap reset` would apply.
+    Diff {
+        /// The path of the keymap file.
+        ///
+        /// If omitted, the custom keymap on the keyboard is used.
+        path: Option<PathBuf>,
+    },
+    /// Replaces the custom keymap on the keyboard with the factory one.
+    Reset,
+    /// Gets or sets whether the keyboard only uses the custom keymap.
+    ///
+    /// When disabled, the layers of the factory keymap come first, followed
+    /// by the custom ones.
+    OnlyCustom {
+        /// Whether to only use the custom keymap.
+        ///
+        /// If omitted, the current value is printed.
+        only_custom: Option<bool>,
+    },
+}
+
+impl KeymapCommands {
+    async fn perform(self, device: &Device) -> Result<(), error_stack::Report<Error>> {
+        match self {
+            Self::New { keymap, path } => {
+                let keymap = if let Some(keymap) = keymap {
+                    AnyKeymap::from_str_for(device.model_or_default(), &keymap)
+                        .change_context(Error)
+                        .attach("parsing raw keymap data")?
+                } else {
+                    let mut keyboard = device.connect().await?;
+
+                    keyboard
+                        .get_keymap()
+                        .await
+                        .change_context(Error)
+                        .attach("getting the custom keymap from the keyboard")?
+                };
+
+                safe_pretty_json_file(&keymap, &path).await?;
+
+                Ok(())
+            }
+            Self::ToCommandData { path } => {
+                let keymap = read_json_file::<AnyKeymap>(&path).await?;
+
+                let data = keymap
+                    .to_keymap_custom_data()
+                    .change_context(Error)
+                    .attach("serializing keymap into command data")?;
+
+                println!("{data}");
+
+                Ok(())
+            }
+            Self::Apply { path } => {
+                let keymap = read_json_file::<AnyKeymap>(&path).await?;
+
+                let mut keyboard = device.connect().await?;
+
+                keyboard
+                    .apply_keymap(&keymap)
+                    .await
+                    .change_context(Error)
+                    .attach("applying the keymap to the keyboard")?;
+
+                let dual_function_keys = keymap.dual_function_keys();
+
+                if dual_function_keys >= MANY_DUAL_FUNCTION_KEYS
+                    && let Ok(DualFunctionSettings::DEFAULT) =
+                        keyboard.get_dual_function_settings().await
+                {
+                    eprintln!(
+                        "Note: the keymap has {dual_function_keys} dual-function keys, but their \
+                        timings are still the defaults, which can make fast typing trigger \
+                        modifiers by mistake. See `dual-function set --help`"
+                    );
+                }
+
+                // TODO: make this configurable
+                // Overwrite the keymap file to ensure file remains prettified
+                safe_pretty_json_file(&keymap, &path).await?;
+
+                Ok(())
+            }
+            Self::Format { path } => {
+                let keymap = read_json_file::<AnyKeymap>(&path).await?;
+
+                safe_pretty_json_file(&keymap, &path).await?;
+
+                Ok(())
+            }
+            Self::ClearLayer { path, layer, key } => {
+                let mut keymap = read_json_file::<AnyKeymap>(&path).await?;
+
+                keymap
+                    .clear_layer_to(layer as usize, key)
+                    .change_context(Error)
+                    .attach_with(|| format!("clearing the `{layer}` layer to key `{key}`"))?;
+
+                safe_pretty_json_file(&keymap, &path).await?;
+
+                Ok(())
+            }
+            Self::Default { path } => {
+                let mut keyboard = device.connect().await?;
+
+                let keymap = keyboard
+                    .get_default_keymap()
+                    .await
+                    .change_context(Error)
+                    .attach("getting the default keymap from the keyboard")?;
+
+                safe_pretty_json_file(&keymap, &path).await?;
+
+                Ok(())
+            }
+            Self::Diff { path } => {
+                let mut keyboard = device.connect().await?;
+
+                let keymap = match path {
+                    Some(path) => read_json_file::<AnyKeymap>(&path).await?,
+                    None => keyboard
+                        .get_keymap()
+                        .await
+                        .change_context(Error)
+                        .attach("getting the custom keymap from the keyboard")?,
+                };
+
+                let mut default = keyboard
+                    .get_default_keymap()
+                    .await
+                    .change_context(Error)
+                    .attach("getting the default keymap from the keyboard")?;
+
+                default.resize_to(keymap.layers(), KeyKind::Blank(Blank::NoKey));
+
+                let diffs = diff_keymaps(&default, &keymap);
+
+                if diffs.is_empty() {
+                    println!("The keymap matches the factory keymap");
+                }
+
+                for (path, default, current) in diffs {
+                    println!("{path}: {default} -> {current}");
+                }
+
+                Ok(())
+            }
+            Self::Reset => {
+                let mut keyboard = device.connect().await?;
+
+                keyboard
+                    .reset_keymap_to_default()
+                    .await
+                    .change_context(Error)
+                    .attach("resetting the keymap to the factory one")?;
+
+                Ok(())
+            }
+            Self::OnlyCustom { only_custom: None } => {
+                let mut keyboard = device.connect().await?;
+
+                let only_custom = keyboard
+                    .get_only_custom()
+                    .await
+                    .change_context(Error)
+                    .attach("getting whether only the custom keymap is used")?;
+
+                println!("{only_custom}");
+
+                Ok(())
+            }
+            Self::OnlyCustom {
+                only_custom: Some(only_custom),
+            } => {
+                let mut keyboard = device.connect().await?;
+
+                keyboard
+                    .set_only_custom(only_custom)
+                    .await
+                    .change_context(Error)
+                    .attach("setting whether only the custom keymap is used")?;
+
+                Ok(())
+            }
+        }
+    }
+}
+
+#[derive(Subcommand)]
+enum SuperkeyCommands {
+    /// Create a new keymap config file.
+    New {
+        /// The raw keymap string found in the bazecore config file.
+        ///
+        /// If omitted, will attempt to read it from the keyboard.
+        #[clap(short, long)]
+        superkeys: Option<String>,
+        /// The path the keymap will be saved to.
+        #[clap(default_value = "keymap.json")]
+        path: PathBuf,
+    },
+    /// Formats the super keys JSON file.
+    Format {
+        /// The path of the keymap JSON file.
+        path: PathBuf,
+    },
+    /// Reads a keymap file and outputs it as a raw keymap data string that can
+    /// be used to send to the keyboard.
+    ToCommandData {
+        /// The path of the keymap JSON file.
+        path: PathBuf,
+    },
+    /// Apply the superkeys to the keyboard, along with the superkey timings
+    /// if the file has them.
+    Apply {
+        /// The path of the keymap file.
+        path: PathBuf,
+    },
+}
+
+impl SuperkeyCommands {
+    async fn perform(self, device: &Device) -> Result<(), error_stack::Report<Error>> {
+        match self {
+            Self::New { superkeys, path } => {
+                let config = if let Some(superkeys) = superkeys {
+                    superkeys
+                        .parse::<SuperkeyMap>()
+                        .change_context(Error)
+                        .attach("parsing superkeys JSON file")?
+                        .into()
+                } else {
+                    let mut keyboard = device.connect().await?;
+
+                    let superkeys = keyboard
+                        .get_superkeys()
+                        .await
+                        .change_context(Error)
+                        .attach("getting superkeys from the keyboard")?;
+
+                    let settings = if_supported(keyboard.get_superkey_settings().await)
+                        .change_context(Error)
+                        .attach("getting superkey settings from the keyboard")?;
+
+                    SuperkeyConfig {
+                        settings,
+                        superkeys,
+                    }
+                };
+
+                safe_pretty_json_file(&config, &path).await?;
+
+                Ok(())
+            }
+            Self::ToCommandData { path } => {
+                let config = read_json_file::<SuperkeyConfig>(&path).await?;
+
+                let str_data = parsing::superkeys::SuperkeyMap::from(&config.superkeys)
+                    .to_sized_command_data(
+                        device.model_or_default().descriptor().superkey_memory_size,
+                    )
+                    .change_context(Error)
+                    .attach("serializing superkeys to command data")?;
+
+                println!("{str_data}");
+
+                Ok(())
+            }
+            Self::Apply { path } => {
+                let config = read_json_file::<SuperkeyConfig>(&path).await?;
+
+                let mut keyboard = device.connect().await?;
+
+                keyboard
+                    .apply_superkeys(&config.superkeys)
+                    .await
+                    .change_context(Error)
+                    .attach("applying superkeys to the keyboard")?;
+
+                if let Some(settings) = &config.settings {
+                    keyboard
+                        .apply_superkey_settings(settings)
+                        .await
+                        .change_context(Error)
+                        .attach("applying superkey settings to the keyboard")?;
+                }
+
+                // TODO: Make this configurable
+                // We override the original config file to make sure everything stays
+                // nice and prettified
+                safe_pretty_json_file(&config, &path).await?;
+
+                Ok(())
+            }
+            Self::Format { path } => {
+                let config = read_json_file::<SuperkeyConfig>(&path).await?;
+
+                safe_pretty_json_file(&config, &path).await?;
+
+                Ok(())
+            }
+        }
+    }
+}
+
+#[derive(Subcommand)]
+enum MacroCommands {
+    /// Create a new macros config file.
+    New {
+        /// The raw macros string found in the bazecore config file.
+        ///
+        /// If omitted, will attempt to read it from the keyboard.
+        #[clap(short, long)]
+        macros: Option<String>,
+        /// The path the macros will be saved to.
+        #[clap(default_value = "macros.json")]
+        path: PathBuf,
+    },
+    /// Formats the macros JSON file.
+    Format {
+        /// The path of the macros JSON file.
+        #[clap(default_value = "macros.json")]
+        path: PathBuf,
+    },
+    /// Reads a macros file and outputs it as a raw macros data string that can
+    /// be used to send to the keyboard.
+    ToCommandData {
+        /// The path of the macros JSON file.
+        path: PathBuf,
+    },
+    /// Apply the macros to the keyboard.
+    Apply {
+        /// The path of the macros file.
         path: PathBuf,
-        /// The layer number to clear. Index starts at 1.
-        ///
-        /// The layer number should match the `layer_number` shown in your keymap JSON
-        /// file after running the `keymap format` command.
-        #[clap(short, long)]
-        layer: u8,
-        /// The key to use to clear the layer.
-        #[arg(short, long, default_value_t = KeyKind::Blank(Blank::NoKey))]
-        key: KeyKind,
     },
 }
 
-impl KeymapCommands {
-    async fn perform(self) -> Result<(), error_stack::Report<Error>> {
+impl MacroCommands {
+    async fn perform(self, device: &Device) -> Result<(), error_stack::Report<Error>> {
         match self {
-            Self::New { keymap, path } => {
-                let keymap = if let Some(keymap) = keymap {
-                    keymap
-                        .parse::<DefyKeymap>()
+            Self::New { macros, path } => {
+                let map = if let Some(macros) = macros {
+                    macros
+                        .parse::<DefyMacroMap>()
                         .change_context(Error)
-                        .attach("parsing keymap JSON file")?
+                        .attach("parsing raw macros data")?
                 } else {
-                    let mut defy = DefyKeyboard::new()
-                        .await
-                        .change_context(Error)
-                        .attach("connecting to the Defy keyboard")?;
+                    let mut keyboard = device.connect().await?;
 
-                    defy.get_custom_keymap()
+                    keyboard
+                        .get_macros()
                         .await
                         .change_context(Error)
-                        .attach("getting the custom keymap from the Defy")?
+                        .attach("getting macros from the keyboard")?
                 };
 
-                safe_pretty_json_file(&keymap, &path).await?;
+                safe_pretty_json_file(&map, &path).await?;
 
                 Ok(())
             }
             Self::ToCommandData { path } => {
-                let keymap = read_json_file::<DefyKeymap>(&path).await?;
+                let map = read_json_file::<DefyMacroMap>(&path).await?;
 
-                let data = keymap
-                    .to_keymap_custom_data()
+                let str_data = parsing::macros::MacroMap::from(&map)
+                    .to_sized_command_data(device.model_or_default().descriptor().macro_memory_size)
                     .change_context(Error)
-                    .attach("serializing keymap into command data")?;
+                    .attach("serializing macros to command data")?;
 
-                println!("{data}");
+                println!("{str_data}");
 
                 Ok(())
             }
             Self::Apply { path } => {
-                let keymap = read_json_file::<DefyKeymap>(&path).await?;
+                let map = read_json_file::<DefyMacroMap>(&path).await?;
+
+                let mut keyboard = device.connect().await?;
 
-                let mut defy = DefyKeyboard::new()
+                keyboard
+                    .apply_macros(&map)
                     .await
                     .change_context(Error)
-                    .attach("connecting to the Defy keyboard")?;
+                    .attach("applying macros to the keyboard")?;
 
-                defy.apply_custom_keymap(&keymap)
+                // TODO: Make this configurable
+                // We override the original config file to make sure everything stays
+                // nice and prettified
+                safe_pretty_json_file(&map, &path).await?;
+
+                Ok(())
+            }
+            Self::Format { path } => {
+                let map = read_json_file::<DefyMacroMap>(&path).await?;
+
+                safe_pretty_json_file(&map, &path).await?;
+
+                Ok(())
+            }
+        }
+    }
+}
+
+#[derive(Subcommand)]
+enum PaletteCommands {
+    /// Create a new palette file from the keyboard's palette.
+    New {
+        /// The path the palette will be saved to.
+        #[clap(default_value = "palette.json")]
+        path: PathBuf,
+    },
+    /// Formats the palette file.
+    Format {
+        /// The path of the palette JSON file.
+        #[clap(default_value = "palette.json")]
+        path: PathBuf,
+    },
+    /// Apply the palette to the keyboard.
+    Apply {
+        /// The path of the palette file.
+        path: PathBuf,
+    },
+}
+
+impl PaletteCommands {
+    async fn perform(self, device: &Device) -> Result<(), error_stack::Report<Error>> {
+        match self {
+            Self::New { path } => {
+                let mut keyboard = device.connect().await?;
+
+                let palette = keyboard
+                    .get_palette()
                     .await
                     .change_context(Error)
-                    .attach("applying the keymap to the Defy")?;
+                    .attach("getting the palette from the keyboard")?;
 
-                // TODO: make this configurable
-                // Overwrite the keymap file to ensure file remains prettified
-                safe_pretty_json_file(&keymap, &path).await?;
+                safe_pretty_json_file(&palette, &path).await?;
 
                 Ok(())
             }
             Self::Format { path } => {
-                let keymap = read_json_file::<DefyKeymap>(&path).await?;
+                let palette = read_json_file::<Palette>(&path).await?;
 
-                safe_pretty_json_file(&keymap, &path).await?;
+                safe_pretty_json_file(&palette, &path).await?;
 
                 Ok(())
             }
-            Self::ClearLayer { path, layer, key } => {
-                let mut keymap = read_json_file::<DefyKeymap>(&path).await?;
+            Self::Apply { path } => {
+                let palette = read_json_file::<Palette>(&path).await?;
 
-                keymap
-                    .clear_layer_to(layer as usize, key)
+                let mut keyboard = device.connect().await?;
+
+                keyboard
+                    .apply_palette(&palette)
+                    .await
                     .change_context(Error)
-                    .attach_with(|| format!("clearing the `{layer}` layer to key `{key}`"))?;
+                    .attach("applying the palette to the keyboard")?;
 
-                safe_pretty_json_file(&keymap, &path).await?;
+                safe_pretty_json_file(&palette, &path).await?;
 
                 Ok(())
             }
@@ -267,101 +1349,424 @@ impl KeymapCommands {
 }
 
 #[derive(Subcommand)]
-enum SuperkeyCommands {
-    /// Create a new keymap config file.
+enum ColormapCommands {
+    /// Create a new colormap file from the keyboard's colormap.
     New {
-        /// The raw keymap string found in the bazecore config file.
-        ///
-        /// If omitted, will attempt to read it from the keyboard.
-        #[clap(short, long)]
-        superkeys: Option<String>,
-        /// The path the keymap will be saved to.
-        #[clap(default_value = "keymap.json")]
+        /// The path the colormap will be saved to.
+        #[clap(default_value = "colormap.json")]
         path: PathBuf,
     },
-    /// Formats the super keys JSON file.
+    /// Formats the colormap file.
     Format {
-        /// The path of the keymap JSON file.
-        path: PathBuf,
-    },
-    /// Reads a keymap file and outputs it as a raw keymap data string that can
-    /// be used to send to the keyboard.
-    ToCommandData {
-        /// The path of the keymap JSON file.
+        /// The path of the colormap JSON file.
+        #[clap(default_value = "colormap.json")]
         path: PathBuf,
     },
-    /// Apply the keymap to the keyboard.
+    /// Apply the colormap to the keyboard.
     Apply {
-        /// The path of the keymap file.
+        /// The path of the colormap file.
         path: PathBuf,
     },
 }
 
-impl SuperkeyCommands {
-    async fn perform(self) -> Result<(), error_stack::Report<Error>> {
+impl ColormapCommands {
+    async fn perform(self, device: &Device) -> Result<(), error_stack::Report<Error>> {
         match self {
-            Self::New { superkeys, path } => {
-                let map = if let Some(superkeys) = superkeys {
-                    superkeys
-                        .parse::<SuperkeyMap>()
-                        .change_context(Error)
-                        .attach("parsing superkeys JSON file")?
-                } else {
-                    let mut defy = DefyKeyboard::new()
-                        .await
-                        .change_context(Error)
-                        .attach("connecting to the Defy keyboard")?;
+            Self::New { path } => {
+                let mut keyboard = device.connect().await?;
 
-                    defy.get_superkeys()
-                        .await
-                        .change_context(Error)
-                        .attach("getting superkeys from the Defy")?
-                };
+                let colormap = keyboard
+                    .get_colormap()
+                    .await
+                    .change_context(Error)
+                    .attach("getting the colormap from the keyboard")?;
 
-                safe_pretty_json_file(&map, &path).await?;
+                safe_pretty_json_file(&colormap, &path).await?;
 
                 Ok(())
             }
-            Self::ToCommandData { path } => {
-                let map = read_json_file::<SuperkeyMap>(&path).await?;
+            Self::Format { path } => {
+                let colormap = read_json_file::<AnyColormap>(&path).await?;
 
-                let str_data = parsing::superkeys::SuperkeyMap::from(&map)
-                    .to_command_data::<{ DefyKeyboard::SUPERKEY_MEMORY_SIZE }>()
+                safe_pretty_json_file(&colormap, &path).await?;
+
+                Ok(())
+            }
+            Self::Apply { path } => {
+                let colormap = read_json_file::<AnyColormap>(&path).await?;
+
+                let mut keyboard = device.connect().await?;
+
+                keyboard
+                    .apply_colormap(&colormap)
+                    .await
                     .change_context(Error)
-                    .attach("serializing superkeys to command data")?;
+                    .attach("applying the colormap to the keyboard")?;
 
-                println!("{str_data}");
+                safe_pretty_json_file(&colormap, &path).await?;
 
                 Ok(())
             }
-            Self::Apply { path } => {
-                let map = read_json_file::<SuperkeyMap>(&path).await?;
+        }
+    }
+}
+
+#[derive(Subcommand)]
+enum LedCommands {
+    /// Gets or sets the active LED mode.
+    Mode {
+        /// The LED mode, where `0` usually shows the colormap.
+        ///
+        /// If omitted, the current mode is printed.
+        mode: Option<u8>,
+    },
+    /// Gets or sets the brightness of the key LEDs.
+    Brightness {
+        /// The brightness, from 0 to 255.
+        ///
+        /// If omitted, the current brightness is printed.
+        brightness: Option<u8>,
+        /// Use the underglow LEDs instead of the key LEDs.
+        #[arg(long)]
+        underglow: bool,
+    },
+    /// Gets or sets the colour shown by a single LED.
+    ///
+    /// The colour lasts until the LED mode redraws it, and the stored
+    /// colormap is left untouched.
+    At {
+        /// The LED, in the order the firmware uses. Index starts at 0.
+        led: u16,
+        /// The colour to show, as `#rrggbb` or `#rrggbbww`.
+        ///
+        /// If omitted, the current colour is printed.
+        color: Option<Color>,
+    },
+    /// Shows the same colour on every LED.
+    ///
+    /// The colour lasts until the LED mode redraws it, and the stored
+    /// colormap is left untouched.
+    SetAll {
+        /// The colour to show, as `#rrggbb` or `#rrggbbww`.
+        color: Color,
+    },
+    /// Gets or sets the colour shown by every LED.
+    Theme {
+        /// The path of a JSON file listing one colour per LED, in the order
+        /// the firmware uses.
+        ///
+        /// If omitted, the current colours are printed as JSON.
+        path: Option<PathBuf>,
+    },
+    /// Redraws every LED with the active LED mode, undoing colours set with
+    /// `at`, `set-all` and `theme`.
+    Refresh,
+    /// Gets or sets how long the keyboard waits before turning the LEDs off
+    /// when idle.
+    IdleTimeout {
+        /// The timeout, in seconds. Use `0` to never turn the LEDs off.
+        ///
+        /// If omitted, the current timeout is printed.
+        timeout: Option<u16>,
+    },
+}
+
+impl LedCommands {
+    async fn perform(self, device: &Device) -> Result<(), error_stack::Report<Error>> {
+        let mut keyboard = device.connect().await?;
 
-                let mut defy = DefyKeyboard::new()
+        match self {
+            Self::Mode { mode: None } => {
+                let mode = keyboard
+                    .get_led_mode()
                     .await
                     .change_context(Error)
-                    .attach("connecting to the Defy keyboard")?;
+                    .attach("getting LED mode from keyboard")?;
 
-                defy.apply_superkeys(&map)
+                println!("{mode}");
+            }
+            Self::Mode { mode: Some(mode) } => {
+                keyboard
+                    .set_led_mode(mode)
                     .await
                     .change_context(Error)
-                    .attach("applying superkeys to the Defy")?;
+                    .attach("setting LED mode on keyboard")?;
+            }
+            Self::Brightness {
+                brightness: None,
+                underglow,
+            } => {
+                let brightness = if underglow {
+                    keyboard.get_underglow_brightness().await
+                } else {
+                    keyboard.get_led_brightness().await
+                }
+                .change_context(Error)
+                .attach("getting LED brightness from keyboard")?;
 
-                // TODO: Make this configurable
-                // We override the original config file to make sure everything stays
-                // nice and prettified
-                safe_pretty_json_file(&map, &path).await?;
+                println!("{brightness}");
+            }
+            Self::Brightness {
+                brightness: Some(brightness),
+                underglow,
+            } => {
+                if underglow {
+                    keyboard.set_underglow_brightness(brightness).await
+                } else {
+                    keyboard.set_led_brightness(brightness).await
+                }
+                .change_context(Error)
+                .attach("setting LED brightness on keyboard")?;
+            }
+            Self::At { led, color: None } => {
+                let color = keyboard
+                    .get_led_color(led)
+                    .await
+                    .change_context(Error)
+                    .attach_with(|| format!("getting the colour of LED {led}"))?;
 
-                Ok(())
+                println!("{color}");
             }
-            Self::Format { path } => {
-                let map = read_json_file::<SuperkeyMap>(&path).await?;
+            Self::At {
+                led,
+                color: Some(color),
+            } => {
+                keyboard
+                    .set_led_color(led, color)
+                    .await
+                    .change_context(Error)
+                    .attach_with(|| format!("setting LED {led} to {color}"))?;
+            }
+            Self::SetAll { color } => {
+                keyboard
+                    .set_all_leds(color)
+                    .await
+                    .change_context(Error)
+                    .attach_with(|| format!("setting every LED to {color}"))?;
+            }
+            Self::Theme { path: None } => {
+                let theme = keyboard
+                    .get_led_theme()
+                    .await
+                    .change_context(Error)
+                    .attach("getting the colour of every LED")?;
 
-                safe_pretty_json_file(&map, &path).await?;
+                let data = serde_json::to_string_pretty(&theme).unwrap();
 
-                Ok(())
+                println!("{data}");
+            }
+            Self::Theme { path: Some(path) } => {
+                let theme = read_json_file::<Vec<Color>>(&path).await?;
+
+                keyboard
+                    .set_led_theme(&theme)
+                    .await
+                    .change_context(Error)
+                    .attach("setting the colour of every LED")?;
+            }
+            Self::Refresh => {
+                keyboard
+                    .refresh_leds()
+                    .await
+                    .change_context(Error)
+                    .attach("redrawing the LEDs")?;
+            }
+            Self::IdleTimeout { timeout: None } => {
+                let timeout = keyboard
+                    .get_idle_leds_timeout()
+                    .await
+                    .change_context(Error)
+                    .attach("getting idle LED timeout from keyboard")?;
+
+                println!("{timeout}");
+            }
+            Self::IdleTimeout {
+                timeout: Some(timeout),
+            } => {
+                keyboard
+                    .set_idle_leds_timeout(timeout)
+                    .await
+                    .change_context(Error)
+                    .attach("setting idle LED timeout on keyboard")?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[derive(Subcommand)]
+enum MouseCommands {
+    /// Shows how mouse keys move the cursor and scroll.
+    Show {
+        /// Output the settings as JSON.
+        #[arg(long)]
+        json: bool,
+    },
+    /// Changes how mouse keys move the cursor and scroll, leaving the
+    /// settings which aren't given unchanged.
+    #[command(group = clap::ArgGroup::new("settings").required(true).multiple(true))]
+    Set {
+        /// How far the cursor moves each step, from 0 to 127.
+        #[arg(long, group = "settings")]
+        speed: Option<u8>,
+        /// How long the cursor waits between steps, in milliseconds.
+        #[arg(long, group = "settings")]
+        speed_delay: Option<u8>,
+        /// How much faster the cursor gets each step, from 0 to 127.
+        #[arg(long, group = "settings")]
+        accel_speed: Option<u8>,
+        /// How long the cursor waits between speeding up, in milliseconds.
+        #[arg(long, group = "settings")]
+        accel_delay: Option<u8>,
+        /// How far the wheel scrolls each step, from 0 to 127.
+        #[arg(long, group = "settings")]
+        wheel_speed: Option<u8>,
+        /// How long the wheel waits between steps, in milliseconds.
+        #[arg(long, group = "settings")]
+        wheel_delay: Option<u8>,
+        /// The fastest the cursor can move, from 1 to 255.
+        #[arg(long, group = "settings")]
+        speed_limit: Option<u8>,
+    },
+}
+
+impl MouseCommands {
+    async fn perform(self, device: &Device) -> Result<(), error_stack::Report<Error>> {
+        let mut keyboard = device.connect().await?;
+
+        let mut settings = keyboard
+            .get_mouse_key_settings()
+            .await
+            .change_context(Error)
+            .attach("getting mouse key settings from keyboard")?;
+
+        match self {
+            Self::Show { json } => {
+                if json {
+                    let data = serde_json::to_string_pretty(&settings).unwrap();
+
+                    println!("{data}");
+
+                    return Ok(());
+                }
+
+                println!("speed: {}", settings.speed);
+                println!("speed delay (ms): {}", settings.speed_delay);
+                println!("acceleration speed: {}", settings.accel_speed);
+                println!("acceleration delay (ms): {}", settings.accel_delay);
+                println!("wheel speed: {}", settings.wheel_speed);
+                println!("wheel delay (ms): {}", settings.wheel_delay);
+                println!("speed limit: {}", settings.speed_limit);
+            }
+            Self::Set {
+                speed,
+                speed_delay,
+                accel_speed,
+                accel_delay,
+                wheel_speed,
+                wheel_delay,
+                speed_limit,
+            } => {
+                let changes = [
+                    (&mut settings.speed, speed),
+                    (&mut settings.speed_delay, speed_delay),
+                    (&mut settings.accel_speed, accel_speed),
+                    (&mut settings.accel_delay, accel_delay),
+                    (&mut settings.wheel_speed, wheel_speed),
+                    (&mut settings.wheel_delay, wheel_delay),
+                    (&mut settings.speed_limit, speed_limit),
+                ];
+
+                for (setting, value) in changes {
+                    if let Some(value) = value {
+                        *setting = value;
+                    }
+                }
+
+                keyboard
+                    .apply_mouse_key_settings(&settings)
+                    .await
+                    .change_context(Error)
+                    .attach("setting mouse key settings on keyboard")?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// How many dual-function keys a keymap needs before `keymap apply` suggests
+/// tuning their timings.
+const MANY_DUAL_FUNCTION_KEYS: usize = 8;
+
+#[derive(Subcommand)]
+enum DualFunctionCommands {
+    /// Shows when dual-function keys act as their modifier or layer.
+    Show {
+        /// Output the settings as JSON.
+        #[arg(long)]
+        json: bool,
+    },
+    /// Changes when dual-function keys act as their modifier or layer,
+    /// leaving the settings which aren't given unchanged.
+    #[command(group = clap::ArgGroup::new("settings").required(true).multiple(true))]
+    Set {
+        /// How long a dual-function key must be held before it acts as its
+        /// modifier or layer, in milliseconds.
+        #[arg(long, group = "settings")]
+        hold_timeout: Option<u16>,
+        /// How much a dual-function key may overlap with the next key
+        /// before it counts as held, from 0 to 100 percent.
+        #[arg(long, group = "settings")]
+        overlap_threshold: Option<u8>,
+    },
+}
+
+impl DualFunctionCommands {
+    async fn perform(self, device: &Device) -> Result<(), error_stack::Report<Error>> {
+        let mut keyboard = device.connect().await?;
+
+        let mut settings = keyboard
+            .get_dual_function_settings()
+            .await
+            .change_context(Error)
+            .attach("getting dual-function key settings from keyboard")?;
+
+        match self {
+            Self::Show { json } => {
+                if json {
+                    let data = serde_json::to_string_pretty(&settings).unwrap();
+
+                    println!("{data}");
+
+                    return Ok(());
+                }
+
+                println!("hold timeout (ms): {}", settings.hold_timeout);
+                println!("overlap threshold (%): {}", settings.overlap_threshold);
+            }
+            Self::Set {
+                hold_timeout,
+                overlap_threshold,
+            } => {
+                if let Some(hold_timeout) = hold_timeout {
+                    settings.hold_timeout = hold_timeout;
+                }
+
+                if let Some(overlap_threshold) = overlap_threshold {
+                    settings.overlap_threshold = overlap_threshold;
+                }
+
+                keyboard
+                    .apply_dual_function_settings(&settings)
+                    .await
+                    .change_context(Error)
+                    .attach("setting dual-function key settings on keyboard")?;
             }
         }
+
+        Ok(())
     }
 }
 
@@ -481,6 +1886,69 @@ fn get_command_suggestions<'a>(available_cmds: &'a [String], user_input: &str) -
         .collect::<Vec<_>>()
 }
 
+/// Collects the keys which differ between two keymaps, along with where they
+/// are, such as `layer 1 left.row_2[3]`.
+///
+/// Both keymaps are expected to have the same number of layers.
+fn diff_keymaps(old: &AnyKeymap, new: &AnyKeymap) -> Vec<(String, KeyKind, KeyKind)> {
+    use serde_json::Value;
+
+    fn diff(path: String, old: &Value, new: &Value, diffs: &mut Vec<(String, KeyKind, KeyKind)>) {
+        match (old, new) {
+            (Value::Array(old), Value::Array(new)) => {
+                for (i, (old, new)) in old.iter().zip(new).enumerate() {
+                    diff(format!("{path}[{i}]"), old, new, diffs);
+                }
+            }
+            (Value::Object(old), Value::Object(new)) => {
+                for (key, old) in old {
+                    if let Some(new) = new.get(key) {
+                        let path = if path.is_empty() {
+                            key.clone()
+                        } else {
+                            format!("{path}.{key}")
+                        };
+
+                        diff(path, old, new, diffs);
+                    }
+                }
+            }
+            (old, new) if old != new => {
+                if let (Ok(old), Ok(new)) = (
+                    serde_json::from_value(old.clone()),
+                    serde_json::from_value(new.clone()),
+                ) {
+                    diffs.push((path, old, new));
+                }
+            }
+            _ => {}
+        }
+    }
+
+    let (Value::Array(old), Value::Array(new)) = (
+        serde_json::to_value(old).unwrap(),
+        serde_json::to_value(new).unwrap(),
+    ) else {
+        unreachable!("keymaps serialize to a list of layers");
+    };
+
+    let mut diffs = Vec::new();
+
+    for (i, (old, new)) in old.iter().zip(&new).enumerate() {
+        let mut layer_diffs = Vec::new();
+
+        diff(String::new(), old, new, &mut layer_diffs);
+
+        diffs.extend(
+            layer_diffs
+                .into_iter()
+                .map(|(path, old, new)| (format!("layer {} {path}", i + 1), old, new)),
+        );
+    }
+
+    diffs
+}
+
 async fn read_json_file<T>(path: &Path) -> Result<T, error_stack::Report<Error>>
 where
     T: for<'de> serde::Deserialize<'de>,
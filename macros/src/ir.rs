@@ -101,7 +101,7 @@ impl Key {
             .iter()
             .copied()
             .map(|modifier| modifier.as_modifier_value())
-            .fold(0, std::ops::Add::add);
+            .sum::<u16>();
 
         let code_u16 = lit_int_to_u16(code)
             .checked_add(modifier_value)